@@ -145,18 +145,32 @@
 //! );
 //! ```
 
-use crate::common::{FinishReason, Usage};
+use crate::{
+    common::{FinishReason, Usage},
+    error::EndpointError,
+    observer::{short_type_name, RequestObserver},
+};
 use indexmap::IndexMap;
+use regex::{NoExpand, Regex};
 use serde::{
     de::{self, MapAccess, Visitor},
     Deserialize, Deserializer, Serialize,
 };
 use serde_json::Value;
-use std::{collections::HashMap, fmt};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    iter::Peekable,
+    ops::RangeInclusive,
+    str::Chars,
+    sync::{Arc, OnceLock},
+};
 
 /// Request builder for creating a new chat completion request.
 pub struct ChatCompletionRequestBuilder {
     req: ChatCompletionRequest,
+    observer: Option<Arc<dyn RequestObserver>>,
 }
 impl ChatCompletionRequestBuilder {
     /// Creates a new builder with the given model.
@@ -175,6 +189,7 @@ impl ChatCompletionRequestBuilder {
                 messages,
                 ..Default::default()
             },
+            observer: None,
         }
     }
 
@@ -207,9 +222,25 @@ impl ChatCompletionRequestBuilder {
 
     /// Includes uage in streaming response.
     pub fn include_usage(mut self) -> Self {
-        self.req.stream_options = Some(StreamOptions {
-            include_usage: Some(true),
-        });
+        self.req
+            .stream_options
+            .get_or_insert_with(StreamOptions::default)
+            .include_usage = Some(true);
+        self
+    }
+
+    /// Emits a usage chunk every `interval` generated tokens in streaming response.
+    pub fn with_usage_interval(mut self, interval: u32) -> Self {
+        self.req
+            .stream_options
+            .get_or_insert_with(StreamOptions::default)
+            .usage_interval = Some(interval);
+        self
+    }
+
+    /// Sets the options for streaming response, replacing any set by [ChatCompletionRequestBuilder::include_usage] or [ChatCompletionRequestBuilder::with_usage_interval].
+    pub fn with_stream_options(mut self, stream_options: StreamOptions) -> Self {
+        self.req.stream_options = Some(stream_options);
         self
     }
 
@@ -279,14 +310,132 @@ impl ChatCompletionRequestBuilder {
         self
     }
 
-    /// Sets the number of user messages to use for context retrieval.
+    /// Sets the number of user messages to use for context retrieval. Values below 1 are clamped to 1, i.e. the latest user message.
     pub fn with_context_window(mut self, context_window: u64) -> Self {
+        let context_window = if context_window < 1 {
+            1
+        } else {
+            context_window
+        };
         self.req.context_window = Some(context_window);
         self
     }
 
+    /// Sets the condition, in addition to the stop sequences, on which to stop generation.
+    pub fn with_stop_condition(mut self, stop_condition: StopCondition) -> Self {
+        self.req.stop_condition = Some(stop_condition);
+        self
+    }
+
+    /// Sets the output types that the model generates.
+    pub fn with_modalities(mut self, modalities: Vec<Modality>) -> Self {
+        self.req.modalities = Some(modalities);
+        self
+    }
+
+    /// Sets the parameters for the audio output. Requires `audio` in the modalities.
+    pub fn with_audio(mut self, audio: AudioOutputConfig) -> Self {
+        self.req.audio = Some(audio);
+        self
+    }
+
+    /// Sets the key that allows the request to be safely retried. Use [generate_idempotency_key] to create a new key.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.req.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Sets whether to stop generating as soon as a tool call is emitted.
+    pub fn with_stop_on_tool_call(mut self, flag: bool) -> Self {
+        self.req.stop_on_tool_call = Some(flag);
+        self
+    }
+
+    /// Sets the id correlating the request with a cancellation request.
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.req.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Sets the seed for deterministic sampling.
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.req.seed = Some(seed);
+        self
+    }
+
+    /// Sets the min-p sampling threshold.
+    pub fn with_min_p(mut self, min_p: f64) -> Self {
+        self.req.min_p = Some(min_p);
+        self
+    }
+
+    /// Sets the number of most likely tokens to sample from.
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.req.top_k = Some(top_k);
+        self
+    }
+
+    /// Sets the penalty of the tokens repeated within the last `repeat_last_n` tokens.
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f64) -> Self {
+        self.req.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    /// Sets the number of most recent tokens considered by the repeat penalty.
+    pub fn with_repeat_last_n(mut self, repeat_last_n: u32) -> Self {
+        self.req.repeat_last_n = Some(repeat_last_n);
+        self
+    }
+
+    /// Sets whether to return the log probabilities of the output tokens.
+    pub fn with_logprobs(mut self, logprobs: bool) -> Self {
+        self.req.logprobs = Some(logprobs);
+        self
+    }
+
+    /// Sets the number of most likely tokens to return at each token position. Requires `logprobs` to be true.
+    pub fn with_top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.req.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    /// Sets whether the model may call several tools in a single turn.
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.req.parallel_tool_calls = Some(parallel_tool_calls);
+        self
+    }
+
+    /// Sets the GBNF grammar constraining the generated text.
+    pub fn with_grammar(mut self, grammar: impl Into<String>) -> Self {
+        self.req.grammar = Some(grammar.into());
+        self
+    }
+
+    /// Sets the name of the prompt template to apply to the messages.
+    pub fn with_prompt_template(mut self, prompt_template: impl Into<String>) -> Self {
+        self.req.prompt_template = Some(prompt_template.into());
+        self
+    }
+
+    /// Sets the observer to notify of the lifecycle events of the request.
+    pub fn with_observer(mut self, observer: Arc<dyn RequestObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
     /// Builds the chat completion request.
     pub fn build(self) -> ChatCompletionRequest {
+        if let Some(observer) = &self.observer {
+            let request_type = short_type_name::<ChatCompletionRequest>();
+            observer.on_build(request_type);
+            if let Err(e) = self.req.validate() {
+                observer.on_validate_error(request_type, &e);
+            }
+            for warning in self.req.warnings() {
+                observer.on_warning(request_type, &warning);
+            }
+        }
+
         self.req
     }
 }
@@ -375,6 +524,130 @@ pub struct ChatCompletionRequest {
     /// The parameter is only used in RAG chat completions.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context_window: Option<u64>,
+    /// A condition, in addition to `stop`, on which to stop generation.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_condition: Option<StopCondition>,
+    /// Output types that the model generates. Defaults to `["text"]` if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<Modality>>,
+    /// Parameters for the audio output. Required if `audio` is in `modalities`, and must not be set otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOutputConfig>,
+    /// A unique key identifying the request, which allows the request to be safely retried without duplicate generations. Mirrors the `Idempotency-Key` HTTP header. See [generate_idempotency_key].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Whether to stop generating as soon as a tool call is emitted, so that the server returns promptly with the `tool_calls` finish reason, e.g. in agent loops.
+    /// Defaults to None, which means `true` if `tools` are provided.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_on_tool_call: Option<bool>,
+    /// An id correlating the request with a [CancelRequest], which allows a separate endpoint to abort the generation. Use `ensure_request_id` to generate one if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// If set, the sampling is deterministic: repeated requests with the same seed and parameters return the same result.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// A [GBNF grammar](https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md) constraining the structure of the generated text, which overrides the grammar the model is served with.
+    /// The grammar does not take precedence over `stop`: generation still halts at the first stop sequence. See [ChatCompletionRequest::warnings] for stop sequences the grammar cannot generate.
+    /// The grammar cannot be combined with the `json_object` or `json_schema` response format, which constrain the output in their own way; [ChatCompletionRequest::validate] rejects setting both.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    /// The name of the prompt template to apply to the messages, such as `llama-3-chat` or `chatml`, for servers hosting multiple templates. Must not be empty.
+    /// Defaults to None, which means the template the model is served with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<String>,
+    /// Discard tokens whose probability is below `min_p` times the probability of the most likely token. The value should be between 0.0 and 1.0; 0.0 disables min-p sampling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f64>,
+    /// Limit the next token selection to the `top_k` most likely tokens.
+    ///
+    /// `top_k` can be combined with `top_p`: the candidates are first limited to the `top_k` most likely tokens, and then to the ones within the cumulative probability `top_p`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Penalize the tokens repeated within the last `repeat_last_n` tokens, as in llama.cpp. 1.0 disables the penalty; higher values discourage repetition more.
+    ///
+    /// Unlike `presence_penalty` and `frequency_penalty`, the penalty scales the logits of the repeated tokens rather than subtracting from them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    /// The number of most recent tokens considered by `repeat_penalty`; 0 disables the penalty. The -1 of llama.cpp, i.e. the whole context, is not supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_last_n: Option<u32>,
+    /// Whether to return the log probabilities of the output tokens in the `logprobs` of each choice.
+    /// Defaults to None, which means false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// The number of most likely tokens to return at each token position, each with its log probability, between 0 and 20. Requires `logprobs` to be true.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// Whether the model may call several of the `tools` in a single turn. Only used if `tools` is set.
+    /// Defaults to None, which the server should treat as true, as in the OpenAI API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+}
+impl ChatCompletionRequest {
+    /// Returns the request id, generating one by [generate_request_id] if absent.
+    pub fn ensure_request_id(&mut self) -> &str {
+        self.request_id.get_or_insert_with(generate_request_id)
+    }
+
+    /// Checks that the combination of the request fields is valid.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        validate_stop_condition(self.stop_condition, self.response_format.as_ref())?;
+
+        validate_audio_output(self.modalities.as_deref(), self.audio.as_ref())?;
+
+        if let Some(stream_options) = &self.stream_options {
+            stream_options.validate()?;
+        }
+
+        validate_prompt_template(self.prompt_template.as_deref())?;
+
+        validate_grammar(self.grammar.as_deref(), self.response_format.as_ref())?;
+
+        validate_logprobs(self.logprobs, self.top_logprobs)?;
+
+        if let Some(response_format) = &self.response_format {
+            response_format.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the advisory warnings about the request, i.e. valid combinations of fields that some backends do not support.
+    ///
+    /// Stop sequences containing characters that `grammar` never generates are reported as well, since they can never match.
+    pub fn warnings(&self) -> Vec<String> {
+        stream_with_tools_warning(self.stream, self.tools.as_deref())
+            .into_iter()
+            .chain(stop_with_grammar_warnings(
+                self.stop.as_deref(),
+                self.grammar.as_deref(),
+            ))
+            .collect()
+    }
+
+    /// Returns a deterministic hash of the request, e.g. as the key of an exact-match response cache.
+    ///
+    /// The hash is the hex-encoded SHA-256 hash of the request serialized to JSON with the keys of all objects sorted, so requests differing only in the order of map keys, such as those of `logit_bias`, hash equally. `request_id` and `idempotency_key` identify a single request rather than its content, so they are not part of the hash.
+    pub fn canonical_hash(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Value::Object(map) = &mut value {
+            map.remove("request_id");
+            map.remove("idempotency_key");
+        }
+        let json = sort_keys(value).to_string();
+
+        let mut hasher = Sha256::new();
+        hasher.update(json.as_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
 }
 impl<'de> Deserialize<'de> for ChatCompletionRequest {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -414,6 +687,22 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                 let mut tools = None;
                 let mut tool_choice = None;
                 let mut context_window = None;
+                let mut stop_condition = None;
+                let mut modalities = None;
+                let mut audio = None;
+                let mut idempotency_key = None;
+                let mut stop_on_tool_call = None;
+                let mut request_id = None;
+                let mut seed = None;
+                let mut grammar = None;
+                let mut prompt_template = None;
+                let mut min_p = None;
+                let mut top_k = None;
+                let mut repeat_penalty = None;
+                let mut repeat_last_n = None;
+                let mut logprobs = None;
+                let mut top_logprobs = None;
+                let mut parallel_tool_calls = None;
 
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_str() {
@@ -436,6 +725,22 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                         "tools" => tools = map.next_value()?,
                         "tool_choice" => tool_choice = map.next_value()?,
                         "context_window" => context_window = map.next_value()?,
+                        "stop_condition" => stop_condition = map.next_value()?,
+                        "modalities" => modalities = map.next_value()?,
+                        "audio" => audio = map.next_value()?,
+                        "idempotency_key" => idempotency_key = map.next_value()?,
+                        "stop_on_tool_call" => stop_on_tool_call = map.next_value()?,
+                        "request_id" => request_id = map.next_value()?,
+                        "seed" => seed = map.next_value()?,
+                        "grammar" => grammar = map.next_value()?,
+                        "prompt_template" => prompt_template = map.next_value()?,
+                        "min_p" => min_p = map.next_value()?,
+                        "top_k" => top_k = map.next_value()?,
+                        "repeat_penalty" => repeat_penalty = map.next_value()?,
+                        "repeat_last_n" => repeat_last_n = map.next_value()?,
+                        "logprobs" => logprobs = map.next_value()?,
+                        "top_logprobs" => top_logprobs = map.next_value()?,
+                        "parallel_tool_calls" => parallel_tool_calls = map.next_value()?,
                         _ => return Err(de::Error::unknown_field(key.as_str(), FIELDS)),
                     }
                 }
@@ -492,6 +797,22 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                     tools,
                     tool_choice,
                     context_window,
+                    stop_condition,
+                    modalities,
+                    audio,
+                    idempotency_key,
+                    stop_on_tool_call,
+                    request_id,
+                    seed,
+                    grammar,
+                    prompt_template,
+                    min_p,
+                    top_k,
+                    repeat_penalty,
+                    repeat_last_n,
+                    logprobs,
+                    top_logprobs,
+                    parallel_tool_calls,
                 })
             }
         }
@@ -516,6 +837,22 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
             "tools",
             "tool_choice",
             "context_window",
+            "stop_condition",
+            "modalities",
+            "audio",
+            "idempotency_key",
+            "stop_on_tool_call",
+            "request_id",
+            "seed",
+            "grammar",
+            "prompt_template",
+            "min_p",
+            "top_k",
+            "repeat_penalty",
+            "repeat_last_n",
+            "logprobs",
+            "top_logprobs",
+            "parallel_tool_calls",
         ];
         deserializer.deserialize_struct(
             "ChatCompletionRequest",
@@ -546,8 +883,651 @@ impl Default for ChatCompletionRequest {
             tools: None,
             tool_choice: None,
             context_window: Some(1),
+            stop_condition: None,
+            modalities: None,
+            audio: None,
+            idempotency_key: None,
+            stop_on_tool_call: None,
+            request_id: None,
+            seed: None,
+            grammar: None,
+            prompt_template: None,
+            min_p: None,
+            top_k: None,
+            repeat_penalty: None,
+            repeat_last_n: None,
+            logprobs: None,
+            top_logprobs: None,
+            parallel_tool_calls: None,
+        }
+    }
+}
+
+/// Generates a new random idempotency key (a UUID v4) for a request.
+pub fn generate_idempotency_key() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+#[test]
+fn test_chat_stop_on_tool_call() {
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![]).build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("stop_on_tool_call"));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.stop_on_tool_call, None);
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_stop_on_tool_call(false)
+        .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""stop_on_tool_call":false"#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.stop_on_tool_call, Some(false));
+}
+
+#[test]
+fn test_chat_idempotency_key() {
+    let key = generate_idempotency_key();
+    assert_eq!(key.len(), 36);
+    assert_ne!(key, generate_idempotency_key());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_idempotency_key("req-123")
+        .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""idempotency_key":"req-123""#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.idempotency_key.as_deref(), Some("req-123"));
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![]).build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("idempotency_key"));
+}
+
+/// Generates a new random request id (a UUID v4) for a request.
+pub fn generate_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Requests to abort the generation of the chat completion request with the given id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CancelRequest {
+    /// The `request_id` of the request to abort.
+    pub request_id: String,
+}
+
+#[test]
+fn test_chat_request_id() {
+    let mut request = ChatCompletionRequestBuilder::new("model-id", vec![]).build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("request_id"));
+
+    let request_id = request.ensure_request_id().to_string();
+    assert_eq!(request_id.len(), 36);
+    // generated only once
+    assert_eq!(request.ensure_request_id(), request_id);
+    assert_ne!(generate_request_id(), generate_request_id());
+
+    let mut request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_request_id("req-1")
+        .build();
+    assert_eq!(request.ensure_request_id(), "req-1");
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""request_id":"req-1""#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.request_id.as_deref(), Some("req-1"));
+
+    let cancel = CancelRequest {
+        request_id: "req-1".to_string(),
+    };
+    let json = serde_json::to_string(&cancel).unwrap();
+    assert_eq!(json, r#"{"request_id":"req-1"}"#);
+    let cancel: CancelRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(cancel.request_id, "req-1");
+}
+
+#[test]
+fn test_chat_canonical_hash() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("Hello, world!".to_string()),
+        None,
+    )];
+    let mut logit_bias = HashMap::new();
+    logit_bias.insert("1".to_string(), 1.0);
+    logit_bias.insert("2".to_string(), -1.0);
+    let request = ChatCompletionRequestBuilder::new("model-id", messages)
+        .with_presence_penalty(0.5)
+        .with_logits_bias(logit_bias)
+        .build();
+    let hash = request.canonical_hash();
+    assert_eq!(hash.len(), 64);
+    assert_eq!(request.canonical_hash(), hash);
+
+    let parse = |json: &str| serde_json::from_str::<ChatCompletionRequest>(json).unwrap();
+
+    // the order of the keys does not matter
+    let request = parse(
+        r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"presence_penalty":0.5,"logit_bias":{"2":-1.0,"1":1.0}}"#,
+    );
+    let reordered = parse(
+        r#"{"logit_bias":{"1":1.0,"2":-1.0},"presence_penalty":0.5,"messages":[{"content":"Hello, world!","role":"user"}],"model":"model-id"}"#,
+    );
+    assert_eq!(request.canonical_hash(), reordered.canonical_hash());
+
+    // the per-request identifiers do not matter
+    let mut identified = reordered;
+    identified.ensure_request_id();
+    identified.idempotency_key = Some(generate_idempotency_key());
+    assert_eq!(identified.canonical_hash(), request.canonical_hash());
+
+    // the content does
+    let changed = parse(
+        r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"presence_penalty":0.5,"logit_bias":{"2":1.0,"1":1.0}}"#,
+    );
+    assert_ne!(changed.canonical_hash(), request.canonical_hash());
+    let changed = parse(
+        r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"presence_penalty":0.4,"logit_bias":{"2":-1.0,"1":1.0}}"#,
+    );
+    assert_ne!(changed.canonical_hash(), request.canonical_hash());
+}
+
+/// Rebuilds the value with the keys of all objects, including nested ones, in sorted order.
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+/// Returns a warning if the response is streamed while tools are provided, which some backends do not support.
+pub(crate) fn stream_with_tools_warning(
+    stream: Option<bool>,
+    tools: Option<&[Tool]>,
+) -> Option<String> {
+    match stream == Some(true) && tools.is_some_and(|tools| !tools.is_empty()) {
+        true => {
+            Some("Some backends cannot stream the response when tools are provided.".to_string())
+        }
+        false => None,
+    }
+}
+
+#[test]
+fn test_chat_warnings() {
+    let tools = vec![Tool::function("get_weather", "Gets the weather.")];
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_tools(tools.clone())
+        .build();
+    assert!(request.warnings().is_empty());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .enable_stream(true)
+        .build();
+    assert!(request.warnings().is_empty());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .enable_stream(true)
+        .with_tools(tools)
+        .build();
+    assert_eq!(request.warnings().len(), 1);
+}
+
+#[test]
+fn test_chat_context_window() {
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_context_window(0)
+        .build();
+    assert_eq!(request.context_window, Some(1));
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_context_window(3)
+        .build();
+    assert_eq!(request.context_window, Some(3));
+}
+
+/// Returns a warning for each stop sequence containing characters that the GBNF grammar never generates, i.e. which appear in none of its string literals and character classes, so that the stop sequence can never match.
+///
+/// The check is best-effort: it is skipped if the grammar may generate any character, e.g. by a negated character class or the `.` wildcard, or if the grammar cannot be parsed.
+pub(crate) fn stop_with_grammar_warnings(
+    stop: Option<&[String]>,
+    grammar: Option<&str>,
+) -> Vec<String> {
+    let (Some(stop), Some(grammar)) = (stop, grammar) else {
+        return vec![];
+    };
+    let Some(alphabet) = grammar_alphabet(grammar) else {
+        return vec![];
+    };
+
+    stop.iter()
+        .filter(|stop| {
+            stop.chars()
+                .any(|c| !alphabet.iter().any(|range| range.contains(&c)))
+        })
+        .map(|stop| {
+            format!(
+                "The stop sequence {:?} contains characters that the grammar never generates, so it never matches.",
+                stop
+            )
+        })
+        .collect()
+}
+
+/// Collects the characters of the string literals and character classes of the GBNF grammar, or returns None if the grammar may generate any character.
+fn grammar_alphabet(grammar: &str) -> Option<Vec<RangeInclusive<char>>> {
+    let mut alphabet = vec![];
+    let mut chars = grammar.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // comment
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            // string literal
+            '"' => loop {
+                let c = match chars.next()? {
+                    '"' => break,
+                    '\\' => unescape_grammar_char(&mut chars)?,
+                    c => c,
+                };
+                alphabet.push(c..=c);
+            },
+            // character class
+            '[' => {
+                if chars.peek() == Some(&'^') {
+                    return None;
+                }
+                loop {
+                    let start = match chars.next()? {
+                        ']' => break,
+                        '\\' => unescape_grammar_char(&mut chars)?,
+                        c => c,
+                    };
+                    let end = match chars.next_if_eq(&'-') {
+                        Some(_) => match chars.next()? {
+                            // a trailing `-` is a literal
+                            ']' => {
+                                alphabet.push(start..=start);
+                                alphabet.push('-'..='-');
+                                break;
+                            }
+                            '\\' => unescape_grammar_char(&mut chars)?,
+                            c => c,
+                        },
+                        None => start,
+                    };
+                    alphabet.push(start..=end);
+                }
+            }
+            // any character
+            '.' => return None,
+            _ => {}
         }
     }
+
+    Some(alphabet)
+}
+
+/// Reads the character escaped by a backslash in a GBNF grammar.
+fn unescape_grammar_char(chars: &mut Peekable<Chars>) -> Option<char> {
+    let digits = match chars.next()? {
+        'n' => return Some('\n'),
+        'r' => return Some('\r'),
+        't' => return Some('\t'),
+        'x' => 2,
+        'u' => 4,
+        'U' => 8,
+        c => return Some(c),
+    };
+    let hex: String = chars.by_ref().take(digits).collect();
+    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)
+}
+
+#[test]
+fn test_chat_stop_with_grammar_warnings() {
+    let grammar = r#"
+# a yes or no answer, followed by a reason
+root   ::= answer ". " reason "\n"
+answer ::= "Yes" | "No"
+reason ::= [a-z ,]+
+"#;
+
+    // the stop sequences only contain characters of the grammar
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_grammar(grammar)
+        .with_stop(vec!["\n".to_string(), "No".to_string(), ". ".to_string()])
+        .build();
+    assert!(request.warnings().is_empty());
+    assert!(request.validate().is_ok());
+
+    // `###` and `<|end|>` can never be generated
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_grammar(grammar)
+        .with_stop(vec![
+            "###".to_string(),
+            "yes".to_string(),
+            "<|end|>".to_string(),
+        ])
+        .build();
+    let warnings = request.warnings();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].contains("\"###\""));
+    assert!(warnings[1].contains("\"<|end|>\""));
+    assert!(request.validate().is_ok());
+
+    // escapes and ranges
+    let grammar = r#"root ::= "\x41" [\u0030-\u0039]+ [+-]"#;
+    assert!(stop_with_grammar_warnings(Some(&["A1-".to_string()]), Some(grammar)).is_empty());
+    assert_eq!(
+        stop_with_grammar_warnings(Some(&["a".to_string()]), Some(grammar)).len(),
+        1
+    );
+
+    // grammars that may generate any character are not checked
+    for grammar in [r#"root ::= [^\n]+"#, r#"root ::= "Answer: " .*"#] {
+        assert!(stop_with_grammar_warnings(Some(&["###".to_string()]), Some(grammar)).is_empty());
+    }
+
+    // without a grammar, any stop sequence may match
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_stop(vec!["###".to_string()])
+        .build();
+    assert!(request.warnings().is_empty());
+}
+
+/// Checks that `stop_condition` is compatible with `response_format`.
+pub(crate) fn validate_stop_condition(
+    stop_condition: Option<StopCondition>,
+    response_format: Option<&ChatResponseFormat>,
+) -> Result<(), EndpointError> {
+    if stop_condition == Some(StopCondition::BalancedJson)
+        && response_format.map(|format| format.ty.as_str()) != Some("json_object")
+    {
+        return Err(EndpointError::InvalidArgument(
+            "The `balanced_json` stop condition requires the `json_object` response format."
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that `grammar` is not combined with a JSON response format.
+pub(crate) fn validate_grammar(
+    grammar: Option<&str>,
+    response_format: Option<&ChatResponseFormat>,
+) -> Result<(), EndpointError> {
+    if grammar.is_some() && response_format.is_some_and(ChatResponseFormat::is_json) {
+        return Err(EndpointError::InvalidArgument(
+            "The `grammar` cannot be combined with a JSON response format.".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_chat_grammar() {
+    let grammar = r#"root ::= "Yes" | "No""#;
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_grammar(grammar)
+        .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""grammar":"root ::= \"Yes\" | \"No\"""#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.grammar.as_deref(), Some(grammar));
+    assert!(request.validate().is_ok());
+
+    // the grammar conflicts with the JSON response format
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_grammar(grammar)
+        .with_reponse_format(ChatResponseFormat {
+            ty: "json_object".to_string(),
+            schema_hint: None,
+            json_schema: None,
+        })
+        .build();
+    assert!(request.validate().is_err());
+
+    // but not with the text response format
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_grammar(grammar)
+        .with_reponse_format(ChatResponseFormat::default())
+        .build();
+    assert!(request.validate().is_ok());
+
+    // omitted by default
+    let request: ChatCompletionRequest =
+        serde_json::from_str(r#"{"model":"model-id","messages":[]}"#).unwrap();
+    assert!(request.grammar.is_none());
+    assert!(!serde_json::to_string(&request).unwrap().contains("grammar"));
+}
+
+/// The max number of most likely tokens returned at each token position, see `top_logprobs`.
+pub const MAX_TOP_LOGPROBS: u8 = 20;
+
+/// Checks that `top_logprobs` is at most [MAX_TOP_LOGPROBS] and only set if `logprobs` is true.
+pub(crate) fn validate_logprobs(
+    logprobs: Option<bool>,
+    top_logprobs: Option<u8>,
+) -> Result<(), EndpointError> {
+    if let Some(top_logprobs) = top_logprobs {
+        if logprobs != Some(true) {
+            return Err(EndpointError::InvalidArgument(
+                "The `top_logprobs` requires `logprobs` to be true.".to_string(),
+            ));
+        }
+        if top_logprobs > MAX_TOP_LOGPROBS {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The `top_logprobs` must be between 0 and {}.",
+                MAX_TOP_LOGPROBS
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_chat_logprobs() {
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_logprobs(true)
+        .with_top_logprobs(5)
+        .build();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""logprobs":true,"top_logprobs":5"#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.logprobs, Some(true));
+    assert_eq!(request.top_logprobs, Some(5));
+
+    // `logprobs` alone is valid
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_logprobs(true)
+        .build();
+    assert!(request.validate().is_ok());
+
+    // `top_logprobs` requires `logprobs`
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_top_logprobs(5)
+        .build();
+    assert!(request.validate().is_err());
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_logprobs(false)
+        .with_top_logprobs(0)
+        .build();
+    assert!(request.validate().is_err());
+
+    // `top_logprobs` is at most 20
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_logprobs(true)
+        .with_top_logprobs(MAX_TOP_LOGPROBS)
+        .build();
+    assert!(request.validate().is_ok());
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_logprobs(true)
+        .with_top_logprobs(MAX_TOP_LOGPROBS + 1)
+        .build();
+    assert!(request.validate().is_err());
+
+    // omitted by default
+    let request: ChatCompletionRequest =
+        serde_json::from_str(r#"{"model":"model-id","messages":[]}"#).unwrap();
+    assert!(request.logprobs.is_none());
+    assert!(request.top_logprobs.is_none());
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("logprobs"));
+}
+
+/// Checks that the name of the prompt template is not empty, if set.
+pub(crate) fn validate_prompt_template(prompt_template: Option<&str>) -> Result<(), EndpointError> {
+    if prompt_template.is_some_and(|name| name.trim().is_empty()) {
+        return Err(EndpointError::InvalidArgument(
+            "The name of the prompt template must not be empty.".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_chat_prompt_template() {
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_prompt_template("chatml")
+        .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""prompt_template":"chatml""#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.prompt_template.as_deref(), Some("chatml"));
+    assert!(request.validate().is_ok());
+
+    // omitted by default
+    let request: ChatCompletionRequest =
+        serde_json::from_str(r#"{"model":"model-id","messages":[]}"#).unwrap();
+    assert!(request.prompt_template.is_none());
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("prompt_template"));
+    assert!(request.validate().is_ok());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_prompt_template(" ")
+        .build();
+    assert!(request.validate().is_err());
+}
+
+/// Checks that `audio` is set if and only if the `audio` modality is requested.
+pub(crate) fn validate_audio_output(
+    modalities: Option<&[Modality]>,
+    audio: Option<&AudioOutputConfig>,
+) -> Result<(), EndpointError> {
+    let audio_requested =
+        modalities.is_some_and(|modalities| modalities.contains(&Modality::Audio));
+    match (audio_requested, audio.is_some()) {
+        (true, false) => Err(EndpointError::InvalidArgument(
+            "The `audio` modality requires the `audio` parameters to be set.".to_string(),
+        )),
+        (false, true) => Err(EndpointError::InvalidArgument(
+            "The `audio` parameters are only allowed if `audio` is in `modalities`.".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_chat_validate_audio_output() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+    let audio = AudioOutputConfig {
+        voice: "alloy".to_string(),
+        format: "wav".to_string(),
+    };
+
+    let request = ChatCompletionRequestBuilder::new("model-id", messages.clone())
+        .with_modalities(vec![Modality::Text, Modality::Audio])
+        .with_audio(audio.clone())
+        .build();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(
+        json.contains(r#""modalities":["text","audio"],"audio":{"voice":"alloy","format":"wav"}"#)
+    );
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        request.modalities,
+        Some(vec![Modality::Text, Modality::Audio])
+    );
+    assert_eq!(request.audio, Some(audio.clone()));
+
+    // `audio` without the `audio` modality
+    let request = ChatCompletionRequestBuilder::new("model-id", messages.clone())
+        .with_audio(audio.clone())
+        .build();
+    assert!(request.validate().is_err());
+    let request = ChatCompletionRequestBuilder::new("model-id", messages.clone())
+        .with_modalities(vec![Modality::Text])
+        .with_audio(audio)
+        .build();
+    assert!(request.validate().is_err());
+
+    // the `audio` modality without `audio`
+    let request = ChatCompletionRequestBuilder::new("model-id", messages.clone())
+        .with_modalities(vec![Modality::Audio])
+        .build();
+    assert!(request.validate().is_err());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", messages)
+        .with_modalities(vec![Modality::Text])
+        .build();
+    assert!(request.validate().is_ok());
+}
+
+#[test]
+fn test_chat_validate_stop_condition() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+
+    let request = ChatCompletionRequestBuilder::new("model-id", messages.clone())
+        .with_stop_condition(StopCondition::BalancedJson)
+        .build();
+    assert!(request.validate().is_err());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", messages.clone())
+        .with_stop_condition(StopCondition::BalancedJson)
+        .with_reponse_format(ChatResponseFormat {
+            ty: "json_object".to_string(),
+            schema_hint: None,
+            json_schema: None,
+        })
+        .build();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""stop_condition":"balanced_json""#));
+    let request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.stop_condition, Some(StopCondition::BalancedJson));
+
+    let request = ChatCompletionRequestBuilder::new("model-id", messages).build();
+    assert!(request.validate().is_ok());
 }
 
 #[test]
@@ -972,41 +1952,403 @@ fn test_chat_deserialize_chat_request() {
     }
 }
 
-/// An object specifying the format that the model must output.
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct ChatResponseFormat {
-    /// Must be one of `text`` or `json_object`. Defaults to `text`.
-    #[serde(rename = "type")]
-    pub ty: String,
+/// An object specifying the format that the model must output.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChatResponseFormat {
+    /// Must be one of `text`, `json_object` or `json_schema`. Defaults to `text`.
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// An example of the expected JSON output, which the server passes to the model as a formatting instruction. Unlike a JSON schema, the hint is not enforced. Only used with the `json_object` type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_hint: Option<Value>,
+    /// The JSON schema the output must conform to. Only used with the `json_schema` type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<JsonSchema>,
+}
+impl ChatResponseFormat {
+    /// Creates the `json_schema` response format, which constrains the output to `json_schema`.
+    pub fn json_schema(json_schema: JsonSchema) -> Self {
+        Self {
+            ty: "json_schema".to_string(),
+            schema_hint: None,
+            json_schema: Some(json_schema),
+        }
+    }
+
+    /// Returns `true` if the model is asked to output JSON, i.e. for the `json_object` and `json_schema` types.
+    pub fn is_json(&self) -> bool {
+        matches!(self.ty.as_str(), "json_object" | "json_schema")
+    }
+
+    /// Checks that `json_schema` is set for the `json_schema` type, and only for it.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        match (self.ty.as_str(), &self.json_schema) {
+            ("json_schema", None) => Err(EndpointError::InvalidArgument(
+                "The `json_schema` response format requires a `json_schema`.".to_string(),
+            )),
+            ("json_schema", Some(json_schema)) => json_schema.validate(),
+            (ty, Some(_)) => Err(EndpointError::InvalidArgument(format!(
+                "The `json_schema` can only be set for the `json_schema` response format, but got `{}`.",
+                ty
+            ))),
+            (_, None) => Ok(()),
+        }
+    }
+
+    /// Returns the instruction to follow the structure of `schema_hint`, if set for the `json_object` type.
+    pub fn schema_hint_instruction(&self) -> Option<String> {
+        match self.ty.as_str() {
+            "json_object" => self.schema_hint.as_ref().map(|hint| {
+                format!(
+                    "Respond with a JSON object following the structure of this example: {}",
+                    hint
+                )
+            }),
+            _ => None,
+        }
+    }
+}
+impl Default for ChatResponseFormat {
+    fn default() -> Self {
+        Self {
+            ty: "text".to_string(),
+            schema_hint: None,
+            json_schema: None,
+        }
+    }
+}
+
+#[test]
+fn test_chat_serialize_response_format() {
+    let response_format = ChatResponseFormat {
+        ty: "text".to_string(),
+        schema_hint: None,
+        json_schema: None,
+    };
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(json, r#"{"type":"text"}"#);
+
+    let response_format = ChatResponseFormat {
+        ty: "json_object".to_string(),
+        schema_hint: None,
+        json_schema: None,
+    };
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(json, r#"{"type":"json_object"}"#);
+    let response_format: ChatResponseFormat = serde_json::from_str(&json).unwrap();
+    assert!(response_format.schema_hint.is_none());
+    assert!(response_format.schema_hint_instruction().is_none());
+
+    let response_format = ChatResponseFormat {
+        ty: "json_object".to_string(),
+        schema_hint: Some(serde_json::json!({"name": "Alice", "age": 30})),
+        json_schema: None,
+    };
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"json_object","schema_hint":{"age":30,"name":"Alice"}}"#
+    );
+    let round_trip: ChatResponseFormat = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, response_format);
+    assert_eq!(
+        response_format.schema_hint_instruction().as_deref(),
+        Some(
+            r#"Respond with a JSON object following the structure of this example: {"age":30,"name":"Alice"}"#
+        )
+    );
+
+    // the hint is only used with the `json_object` type
+    let response_format = ChatResponseFormat {
+        ty: "text".to_string(),
+        ..response_format
+    };
+    assert!(response_format.schema_hint_instruction().is_none());
+}
+
+/// A named JSON schema for the `json_schema` response format.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct JsonSchema {
+    /// The name of the response format. Must contain only a-z, A-Z, 0-9, underscores and dashes, with a max length of 64.
+    pub name: String,
+    /// The JSON schema the output must conform to.
+    pub schema: Value,
+    /// Whether to enforce the schema strictly. Defaults to None, which means `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+impl JsonSchema {
+    /// Checks that `name` is not empty, at most 64 characters long, and contains only a-z, A-Z, 0-9, underscores and dashes.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if self.name.is_empty()
+            || self.name.len() > 64
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The name of the JSON schema must contain only a-z, A-Z, 0-9, underscores and dashes, with a max length of 64, but got `{}`.",
+                self.name
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_chat_serialize_json_schema_response_format() {
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+        "required": ["name", "age"],
+        "additionalProperties": false
+    });
+    let response_format = ChatResponseFormat::json_schema(JsonSchema {
+        name: "person".to_string(),
+        schema: schema.clone(),
+        strict: Some(true),
+    });
+    assert!(response_format.is_json());
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"json_schema","json_schema":{"name":"person","schema":{"additionalProperties":false,"properties":{"age":{"type":"integer"},"name":{"type":"string"}},"required":["name","age"],"type":"object"},"strict":true}}"#
+    );
+    let round_trip: ChatResponseFormat = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, response_format);
+    assert_eq!(round_trip.json_schema.unwrap().schema, schema);
+
+    // `strict` is omitted if not set
+    let response_format = ChatResponseFormat::json_schema(JsonSchema {
+        name: "person".to_string(),
+        schema: serde_json::json!({"type": "object"}),
+        strict: None,
+    });
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"json_schema","json_schema":{"name":"person","schema":{"type":"object"}}}"#
+    );
+    let round_trip: ChatResponseFormat = serde_json::from_str(&json).unwrap();
+    assert!(round_trip.json_schema.unwrap().strict.is_none());
+
+    assert!(!ChatResponseFormat::default().is_json());
+}
+
+#[test]
+fn test_chat_validate_json_schema_response_format() {
+    let response_format = ChatResponseFormat::json_schema(JsonSchema {
+        name: "person".to_string(),
+        schema: serde_json::json!({"type": "object"}),
+        strict: None,
+    });
+    assert!(response_format.validate().is_ok());
+    assert!(ChatResponseFormat::default().validate().is_ok());
+
+    // the `json_schema` type requires a schema
+    let response_format: ChatResponseFormat =
+        serde_json::from_str(r#"{"type":"json_schema"}"#).unwrap();
+    assert!(response_format.validate().is_err());
+
+    // the schema requires the `json_schema` type
+    let response_format: ChatResponseFormat = serde_json::from_str(
+        r#"{"type":"text","json_schema":{"name":"person","schema":{"type":"object"}}}"#,
+    )
+    .unwrap();
+    assert!(response_format.validate().is_err());
+
+    // the name is restricted
+    let response_format = ChatResponseFormat::json_schema(JsonSchema {
+        name: "a person".to_string(),
+        schema: serde_json::json!({"type": "object"}),
+        strict: None,
+    });
+    assert!(response_format.validate().is_err());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .with_reponse_format(ChatResponseFormat {
+            ty: "json_schema".to_string(),
+            schema_hint: None,
+            json_schema: None,
+        })
+        .build();
+    assert!(request.validate().is_err());
+}
+
+/// Options for streaming response. Only set this when you set stream: `true``.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct StreamOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_usage: Option<bool>,
+    /// If set, a chunk with the usage so far is emitted every `usage_interval` generated tokens, e.g. for progress bars. Must be positive.
+    ///
+    /// This is an extension to the OpenAI API. Servers not supporting it ignore the field.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_interval: Option<u32>,
+}
+impl StreamOptions {
+    /// Checks that `usage_interval` is positive.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if self.usage_interval == Some(0) {
+            return Err(EndpointError::InvalidArgument(
+                "The usage interval must be positive.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Builder for creating [StreamOptions].
+#[derive(Debug, Default)]
+pub struct StreamOptionsBuilder {
+    options: StreamOptions,
 }
-impl Default for ChatResponseFormat {
-    fn default() -> Self {
-        Self {
-            ty: "text".to_string(),
-        }
+impl StreamOptionsBuilder {
+    /// Creates a new builder without any options set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to include a chunk with the usage of the whole request before the end of the stream.
+    pub fn with_usage(mut self, include_usage: bool) -> Self {
+        self.options.include_usage = Some(include_usage);
+        self
+    }
+
+    /// Emits a chunk with the usage so far every `interval` generated tokens.
+    pub fn with_usage_interval(mut self, interval: u32) -> Self {
+        self.options.usage_interval = Some(interval);
+        self
+    }
+
+    /// Builds the [StreamOptions].
+    ///
+    /// Returns an error if the usage interval is not positive.
+    pub fn build(self) -> Result<StreamOptions, EndpointError> {
+        self.options.validate()?;
+
+        Ok(self.options)
     }
 }
 
 #[test]
-fn test_chat_serialize_response_format() {
-    let response_format = ChatResponseFormat {
-        ty: "text".to_string(),
-    };
-    let json = serde_json::to_string(&response_format).unwrap();
-    assert_eq!(json, r#"{"type":"text"}"#);
+fn test_chat_stream_options_builder() {
+    let options = StreamOptionsBuilder::new()
+        .with_usage(true)
+        .with_usage_interval(16)
+        .build()
+        .unwrap();
+    assert_eq!(
+        options,
+        StreamOptions {
+            include_usage: Some(true),
+            usage_interval: Some(16),
+        }
+    );
 
-    let response_format = ChatResponseFormat {
-        ty: "json_object".to_string(),
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .enable_stream(true)
+        .with_stream_options(options.clone())
+        .build();
+    assert_eq!(request.stream_options, Some(options));
+    assert!(request.validate().is_ok());
+
+    assert_eq!(
+        StreamOptionsBuilder::new().build().unwrap(),
+        StreamOptions::default()
+    );
+    assert_eq!(
+        StreamOptionsBuilder::new().with_usage_interval(0).build(),
+        Err(EndpointError::InvalidArgument(
+            "The usage interval must be positive.".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_chat_serialize_stream_options() {
+    let options = StreamOptions {
+        include_usage: Some(true),
+        usage_interval: Some(16),
     };
-    let json = serde_json::to_string(&response_format).unwrap();
-    assert_eq!(json, r#"{"type":"json_object"}"#);
+    let json = serde_json::to_string(&options).unwrap();
+    assert_eq!(json, r#"{"include_usage":true,"usage_interval":16}"#);
+    let options: StreamOptions = serde_json::from_str(&json).unwrap();
+    assert_eq!(options.usage_interval, Some(16));
+    assert!(options.validate().is_ok());
+
+    let options: StreamOptions = serde_json::from_str(r#"{"include_usage":true}"#).unwrap();
+    assert_eq!(options.usage_interval, None);
+    assert!(options.validate().is_ok());
+
+    let options: StreamOptions = serde_json::from_str(r#"{"usage_interval":0}"#).unwrap();
+    assert!(options.validate().is_err());
+
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .enable_stream(true)
+        .with_usage_interval(8)
+        .include_usage()
+        .build();
+    assert_eq!(
+        request.stream_options,
+        Some(StreamOptions {
+            include_usage: Some(true),
+            usage_interval: Some(8),
+        })
+    );
+    let request = ChatCompletionRequestBuilder::new("model-id", vec![])
+        .enable_stream(true)
+        .with_usage_interval(0)
+        .build();
+    assert!(request.validate().is_err());
 }
 
-/// Options for streaming response. Only set this when you set stream: `true``.
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct StreamOptions {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub include_usage: Option<bool>,
+/// A condition, in addition to the stop sequences, on which the generation stops.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopCondition {
+    /// Stops once the top-level JSON object or array is closed. Only valid if the `response_format` is `json_object`.
+    BalancedJson,
+}
+
+#[test]
+fn test_chat_serialize_stop_condition() {
+    let json = serde_json::to_string(&StopCondition::BalancedJson).unwrap();
+    assert_eq!(json, r#""balanced_json""#);
+
+    let stop_condition: StopCondition = serde_json::from_str(r#""balanced_json""#).unwrap();
+    assert_eq!(stop_condition, StopCondition::BalancedJson);
+}
+
+/// An output type that the model generates.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Modality {
+    /// Text output.
+    Text,
+    /// Audio output. Requires the `audio` parameters of the request.
+    Audio,
+}
+
+/// Parameters for the audio output of the model.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AudioOutputConfig {
+    /// The voice the model uses to respond, such as `alloy`.
+    pub voice: String,
+    /// The format of the output audio, such as `wav`, `mp3`, `flac`, `opus` or `pcm16`.
+    pub format: String,
+}
+
+#[test]
+fn test_chat_serialize_modality() {
+    let json = serde_json::to_string(&vec![Modality::Text, Modality::Audio]).unwrap();
+    assert_eq!(json, r#"["text","audio"]"#);
+
+    let modalities: Vec<Modality> = serde_json::from_str(r#"["audio","text"]"#).unwrap();
+    assert_eq!(modalities, vec![Modality::Audio, Modality::Text]);
 }
 
 /// Controls which (if any) function is called by the model. Defaults to `None`.
@@ -1041,6 +2383,10 @@ fn test_chat_serialize_tool_choice() {
     let json = serde_json::to_string(&tool_choice).unwrap();
     assert_eq!(json, r#""auto""#);
 
+    let tool_choice = ToolChoice::Required;
+    let json = serde_json::to_string(&tool_choice).unwrap();
+    assert_eq!(json, r#""required""#);
+
     let tool_choice = ToolChoice::Tool(ToolChoiceTool {
         ty: "function".to_string(),
         function: ToolChoiceToolFunction {
@@ -1064,6 +2410,13 @@ fn test_chat_deserialize_tool_choice() {
     let tool_choice: ToolChoice = serde_json::from_str(json).unwrap();
     assert_eq!(tool_choice, ToolChoice::Auto);
 
+    let json = r#""required""#;
+    let tool_choice: ToolChoice = serde_json::from_str(json).unwrap();
+    assert_eq!(tool_choice, ToolChoice::Required);
+
+    // unknown modes are rejected rather than taken for a tool
+    assert!(serde_json::from_str::<ToolChoice>(r#""any""#).is_err());
+
     let json = r#"{"type":"function","function":{"name":"my_function"}}"#;
     let tool_choice: ToolChoice = serde_json::from_str(json).unwrap();
     assert_eq!(
@@ -1095,7 +2448,7 @@ pub struct ToolChoiceToolFunction {
 }
 
 /// Represents a tool the model may generate JSON inputs for.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Tool {
     /// The type of the tool. Currently, only `function` is supported.
     #[serde(rename = "type")]
@@ -1103,6 +2456,152 @@ pub struct Tool {
     /// Function the model may generate JSON inputs for.
     pub function: ToolFunction,
 }
+impl Tool {
+    /// Creates a new function tool without parameters. Use [Tool::with_parameters] to set the parameters, e.g. built by [ParametersBuilder].
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the function.
+    ///
+    /// * `description` - A description of what the function does.
+    pub fn function(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            ty: "function".to_string(),
+            function: ToolFunction {
+                name: name.into(),
+                description: Some(description.into()),
+                parameters: None,
+            },
+        }
+    }
+
+    /// Sets the parameters of the function.
+    pub fn with_parameters(mut self, parameters: ToolFunctionParameters) -> Self {
+        self.function.parameters = Some(parameters);
+        self
+    }
+
+    /// Checks that the tool is well-formed: the type is `function`, the function name is valid, and the `parameters` is a JSON-schema object whose `required` properties are all defined.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if self.ty != "function" {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The type of the tool must be `function`, but got `{}`.",
+                self.ty
+            )));
+        }
+
+        let name = &self.function.name;
+        if name.is_empty()
+            || name.len() > 64
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(EndpointError::InvalidArgument(format!(
+                "Invalid function name `{}`. It must be 1 to 64 characters of a-z, A-Z, 0-9, underscores and dashes.",
+                name
+            )));
+        }
+
+        if let Some(parameters) = &self.function.parameters {
+            if parameters.schema_type != JSONSchemaType::Object {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The parameters of the function `{}` must be a JSON schema of type `object`.",
+                    name
+                )));
+            }
+
+            validate_schema_required(name, parameters.required.as_deref(), |key| {
+                parameters
+                    .properties
+                    .as_ref()
+                    .is_some_and(|properties| properties.contains_key(key))
+            })?;
+
+            for schema in parameters.properties.iter().flat_map(|p| p.values()) {
+                validate_schema_define(name, schema)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the `required` properties of a JSON schema are all defined.
+fn validate_schema_required(
+    function_name: &str,
+    required: Option<&[String]>,
+    is_defined: impl Fn(&str) -> bool,
+) -> Result<(), EndpointError> {
+    if let Some(key) = required.into_iter().flatten().find(|key| !is_defined(key)) {
+        return Err(EndpointError::InvalidArgument(format!(
+            "The required property `{}` is not defined in the parameters of the function `{}`.",
+            key, function_name
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks the nested definitions of a JSON schema recursively.
+fn validate_schema_define(
+    function_name: &str,
+    schema: &JSONSchemaDefine,
+) -> Result<(), EndpointError> {
+    validate_schema_required(function_name, schema.required.as_deref(), |key| {
+        schema
+            .properties
+            .as_ref()
+            .is_some_and(|properties| properties.contains_key(key))
+    })?;
+
+    for nested in schema.properties.iter().flat_map(|p| p.values()) {
+        validate_schema_define(function_name, nested)?;
+    }
+    if let Some(items) = &schema.items {
+        validate_schema_define(function_name, items)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_chat_validate_tool() {
+    let json = r#"{"type":"function","function":{"name":"get_current_weather","description":"Get the current weather in a given location","parameters":{"type":"object","properties":{"location":{"type":"string","description":"The city and state, e.g. San Francisco, CA"},"unit":{"type":"string","enum":["celsius","fahrenheit"]}},"required":["location"]}}}"#;
+    let tool: Tool = serde_json::from_str(json).unwrap();
+    assert!(tool.validate().is_ok());
+
+    // no parameters
+    let json = r#"{"type":"function","function":{"name":"get_time"}}"#;
+    let tool: Tool = serde_json::from_str(json).unwrap();
+    assert!(tool.validate().is_ok());
+
+    // the parameters is not an object
+    let json =
+        r#"{"type":"function","function":{"name":"get_time","parameters":{"type":"string"}}}"#;
+    let tool: Tool = serde_json::from_str(json).unwrap();
+    assert!(tool.validate().is_err());
+
+    // a required property is not defined
+    let json = r#"{"type":"function","function":{"name":"get_current_weather","parameters":{"type":"object","properties":{"unit":{"type":"string"}},"required":["location"]}}}"#;
+    let tool: Tool = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        tool.validate(),
+        Err(EndpointError::InvalidArgument(
+            "The required property `location` is not defined in the parameters of the function `get_current_weather`.".to_string()
+        ))
+    );
+
+    // a required property of a nested object is not defined
+    let json = r#"{"type":"function","function":{"name":"book","parameters":{"type":"object","properties":{"guest":{"type":"object","properties":{"name":{"type":"string"}},"required":["email"]}}}}}"#;
+    let tool: Tool = serde_json::from_str(json).unwrap();
+    assert!(tool.validate().is_err());
+
+    // invalid function name
+    let json = r#"{"type":"function","function":{"name":"get time"}}"#;
+    let tool: Tool = serde_json::from_str(json).unwrap();
+    assert!(tool.validate().is_err());
+}
 
 #[test]
 fn test_chat_serialize_tool() {
@@ -1293,8 +2792,121 @@ fn test_chat_deserialize_tool() {
     assert_eq!(required[0], "location");
 }
 
+/// Builder for the JSON-schema object describing the parameters of a function.
+#[derive(Debug, Default)]
+pub struct ParametersBuilder {
+    properties: IndexMap<String, Box<JSONSchemaDefine>>,
+    required: Vec<String>,
+}
+impl ParametersBuilder {
+    /// Creates a new builder without any parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a string parameter.
+    pub fn add_string_param(self, name: impl Into<String>, description: impl Into<String>) -> Self {
+        self.add_param(name, JSONSchemaType::String, description, None)
+    }
+
+    /// Adds a number parameter.
+    pub fn add_number_param(self, name: impl Into<String>, description: impl Into<String>) -> Self {
+        self.add_param(name, JSONSchemaType::Number, description, None)
+    }
+
+    /// Adds a string parameter whose value must be one of `values`.
+    pub fn add_enum_param(
+        self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        values: Vec<String>,
+    ) -> Self {
+        self.add_param(name, JSONSchemaType::String, description, Some(values))
+    }
+
+    /// Marks the parameter as required.
+    pub fn required(mut self, name: impl Into<String>) -> Self {
+        self.required.push(name.into());
+        self
+    }
+
+    pub fn build(self) -> ToolFunctionParameters {
+        ToolFunctionParameters {
+            schema_type: JSONSchemaType::Object,
+            properties: Some(self.properties),
+            required: match self.required.is_empty() {
+                true => None,
+                false => Some(self.required),
+            },
+        }
+    }
+
+    fn add_param(
+        mut self,
+        name: impl Into<String>,
+        schema_type: JSONSchemaType,
+        description: impl Into<String>,
+        enum_values: Option<Vec<String>>,
+    ) -> Self {
+        self.properties.insert(
+            name.into(),
+            Box::new(JSONSchemaDefine {
+                schema_type: Some(schema_type),
+                description: Some(description.into()),
+                enum_values,
+                properties: None,
+                required: None,
+                items: None,
+                default: None,
+                maximum: None,
+                minimum: None,
+                title: None,
+                examples: None,
+            }),
+        );
+        self
+    }
+}
+
+#[test]
+fn test_chat_build_tool() {
+    let tool = Tool::function(
+        "get_current_weather",
+        "Get the current weather in a given location",
+    )
+    .with_parameters(
+        ParametersBuilder::new()
+            .add_string_param("location", "The city and state, e.g. San Francisco, CA")
+            .add_enum_param(
+                "unit",
+                "The unit of the temperature",
+                vec!["celsius".to_string(), "fahrenheit".to_string()],
+            )
+            .add_number_param("days", "The number of days to forecast")
+            .required("location")
+            .build(),
+    );
+    assert!(tool.validate().is_ok());
+
+    let json = serde_json::to_string(&tool).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"function","function":{"name":"get_current_weather","description":"Get the current weather in a given location","parameters":{"type":"object","properties":{"location":{"type":"string","description":"The city and state, e.g. San Francisco, CA"},"unit":{"type":"string","description":"The unit of the temperature","enum":["celsius","fahrenheit"]},"days":{"type":"number","description":"The number of days to forecast"}},"required":["location"]}}}"#
+    );
+    let round_trip: Tool = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, tool);
+
+    let tool = Tool::function("get_time", "Get the current time")
+        .with_parameters(ParametersBuilder::new().build());
+    let json = serde_json::to_string(&tool).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"function","function":{"name":"get_time","description":"Get the current time","parameters":{"type":"object","properties":{}}}}"#
+    );
+}
+
 /// Function the model may generate JSON inputs for.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ToolFunction {
     /// The name of the function to be called. Must be a-z, A-Z, 0-9, or contain underscores and dashes, with a maximum length of 64.
     pub name: String,
@@ -1370,7 +2982,7 @@ fn test_chat_serialize_tool_function() {
 ///
 /// To describe a function that accepts no parameters, provide the value
 /// `{"type": "object", "properties": {}}`.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ToolFunctionParameters {
     #[serde(rename = "type")]
     pub schema_type: JSONSchemaType,
@@ -1673,6 +3285,25 @@ impl ChatCompletionRequestMessage {
         ChatCompletionRequestMessage::Tool(ChatCompletionToolMessage::new(content, tool_call_id))
     }
 
+    /// Creates a new tool message carrying the result of a tool call, correlated with the call by its id.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The result of the tool call.
+    ///
+    /// * `tool_call_id` - The id of the [ToolCall] this message is responding to.
+    ///
+    /// * `name` - An optional name of the function that was called.
+    pub fn new_tool_result_message(
+        content: impl Into<String>,
+        tool_call_id: impl Into<String>,
+        name: Option<String>,
+    ) -> Self {
+        ChatCompletionRequestMessage::Tool(
+            ChatCompletionToolMessage::new(content, Some(tool_call_id.into())).with_name(name),
+        )
+    }
+
     /// The role of the messages author.
     pub fn role(&self) -> ChatCompletionRole {
         match self {
@@ -1689,11 +3320,142 @@ impl ChatCompletionRequestMessage {
             ChatCompletionRequestMessage::System(message) => message.name(),
             ChatCompletionRequestMessage::User(message) => message.name(),
             ChatCompletionRequestMessage::Assistant(message) => message.name(),
-            ChatCompletionRequestMessage::Tool(_) => None,
+            ChatCompletionRequestMessage::Tool(message) => message.name(),
+        }
+    }
+
+    /// Replaces the matches of `patterns` in the text content of the message with `replacement`, e.g. to redact PII before embedding or logging the message. See [pii_patterns] for a built-in set of patterns.
+    ///
+    /// The role, name, tool call id and image parts are left untouched, and so are the tool calls of an assistant message, whose arguments must stay valid JSON. `replacement` is inserted literally, so `$` needs no escaping.
+    pub fn redact(&mut self, patterns: &[Regex], replacement: &str) {
+        match self {
+            ChatCompletionRequestMessage::System(message) => {
+                redact_text(&mut message.content, patterns, replacement)
+            }
+            ChatCompletionRequestMessage::User(message) => match &mut message.content {
+                ChatCompletionUserMessageContent::Text(text) => {
+                    redact_text(text, patterns, replacement)
+                }
+                ChatCompletionUserMessageContent::Parts(parts) => {
+                    for part in parts {
+                        if let ContentPart::Text(part) = part {
+                            redact_text(&mut part.text, patterns, replacement);
+                        }
+                    }
+                }
+            },
+            ChatCompletionRequestMessage::Assistant(message) => {
+                if let Some(content) = message.content.as_mut() {
+                    redact_text(content, patterns, replacement);
+                }
+            }
+            ChatCompletionRequestMessage::Tool(message) => {
+                redact_text(&mut message.content, patterns, replacement)
+            }
+        }
+    }
+}
+
+/// Replaces the matches of each pattern in `text` with `replacement`, in the order of the patterns.
+fn redact_text(text: &mut String, patterns: &[Regex], replacement: &str) {
+    for pattern in patterns {
+        if let std::borrow::Cow::Owned(redacted) = pattern.replace_all(text, NoExpand(replacement))
+        {
+            *text = redacted;
         }
     }
 }
 
+/// Returns the built-in patterns for [ChatCompletionRequestMessage::redact], matching email addresses, payment card numbers and phone numbers.
+///
+/// The patterns favor common formats over completeness, e.g. phone numbers are matched in the North American format with an optional country code, so they are a baseline rather than a guarantee that no PII is left.
+///
+/// The patterns are compiled on the first call and reused afterwards.
+pub fn pii_patterns() -> &'static [Regex] {
+    static PII_PATTERNS: OnceLock<Vec<Regex>> = OnceLock::new();
+
+    PII_PATTERNS.get_or_init(|| {
+        [
+            // email addresses
+            r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+            // payment card numbers, 13 to 19 digits optionally grouped by spaces or dashes
+            r"\b(?:\d[ -]?){12,18}\d\b",
+            // phone numbers, e.g. `+1 (555) 123-4567` or `555.123.4567`
+            r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{3}\)|\b\d{3})[\s.-]?\d{3}[\s.-]?\d{4}\b",
+        ]
+        .into_iter()
+        .map(|pattern| Regex::new(pattern).expect("invalid built-in PII pattern"))
+        .collect()
+    })
+}
+
+#[test]
+fn test_chat_redact_request_message() {
+    let patterns = pii_patterns();
+    // the patterns are compiled once
+    assert!(std::ptr::eq(patterns, pii_patterns()));
+
+    let mut message = ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text(
+            "Mail jane.doe@example.com or call +1 (555) 123-4567.".to_string(),
+        ),
+        Some("jane".to_string()),
+    );
+    message.redact(patterns, "[REDACTED]");
+    assert_eq!(
+        message,
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(
+                "Mail [REDACTED] or call [REDACTED].".to_string()
+            ),
+            Some("jane".to_string()),
+        )
+    );
+
+    // only the text parts are redacted
+    let image = ContentPart::Image(ImageContentPart::new(Image {
+        url: "https://example.com/555-123-4567.png".to_string(),
+        detail: None,
+    }));
+    let mut message = ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Parts(vec![
+            ContentPart::Text(TextContentPart::new("My number is 555.123.4567")),
+            image.clone(),
+        ]),
+        None,
+    );
+    message.redact(patterns, "$PII");
+    assert_eq!(
+        message,
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Parts(vec![
+                ContentPart::Text(TextContentPart::new("My number is $PII")),
+                image,
+            ]),
+            None,
+        )
+    );
+
+    let mut message = ChatCompletionRequestMessage::new_system_message(
+        "Card 4111 1111 1111 1111 belongs to bob@example.org, order 42",
+        None,
+    );
+    message.redact(patterns, "***");
+    assert_eq!(
+        message,
+        ChatCompletionRequestMessage::new_system_message("Card *** belongs to ***, order 42", None)
+    );
+
+    // messages without matches are unchanged
+    let mut message = ChatCompletionRequestMessage::new_tool_message(
+        "The weather is sunny, 25 degrees.",
+        Some("call-1".to_string()),
+    );
+    let original = message.clone();
+    message.redact(patterns, "***");
+    assert_eq!(message, original);
+}
+
 #[test]
 fn test_chat_serialize_request_message() {
     let message = ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(
@@ -1943,6 +3705,9 @@ pub struct ChatCompletionToolMessage {
     /// Tool call that this message is responding to.
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_call_id: Option<String>,
+    /// The name of the function that was called.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 impl ChatCompletionToolMessage {
     /// Creates a new tool message.
@@ -1956,9 +3721,16 @@ impl ChatCompletionToolMessage {
         Self {
             content: content.into(),
             tool_call_id,
+            name: None,
         }
     }
 
+    /// Sets the name of the function that was called.
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
     /// The role of the messages author, in this case `tool`.
     pub fn role(&self) -> ChatCompletionRole {
         ChatCompletionRole::Tool
@@ -1973,6 +3745,43 @@ impl ChatCompletionToolMessage {
     pub fn tool_call_id(&self) -> Option<String> {
         self.tool_call_id.clone()
     }
+
+    /// The name of the function that was called.
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+}
+
+#[test]
+fn test_chat_serialize_tool_message() {
+    let message = ChatCompletionRequestMessage::new_tool_result_message(
+        r#"{"temperature":22}"#,
+        "call_abc123",
+        Some("get_current_weather".to_string()),
+    );
+    assert_eq!(
+        message.name().map(String::as_str),
+        Some("get_current_weather")
+    );
+    let json = serde_json::to_string(&message).unwrap();
+    assert_eq!(
+        json,
+        r#"{"role":"tool","content":"{\"temperature\":22}","tool_call_id":"call_abc123","name":"get_current_weather"}"#
+    );
+    let round_trip: ChatCompletionRequestMessage = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, message);
+
+    // the name is optional
+    let message =
+        ChatCompletionRequestMessage::new_tool_result_message("22 degrees", "call_abc123", None);
+    let json = serde_json::to_string(&message).unwrap();
+    assert_eq!(
+        json,
+        r#"{"role":"tool","content":"22 degrees","tool_call_id":"call_abc123"}"#
+    );
+    let round_trip: ChatCompletionRequestMessage = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, message);
+    assert!(round_trip.name().is_none());
 }
 
 /// Represents a tool call generated by the model.
@@ -2388,7 +4197,7 @@ pub enum JSONSchemaType {
     Boolean,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct JSONSchemaDefine {
     #[serde(rename = "type")]
     pub schema_type: Option<JSONSchemaType>,
@@ -4,7 +4,7 @@ use crate::common::Usage;
 use serde::{Deserialize, Serialize};
 
 /// Creates an embedding vector representing the input text.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EmbeddingRequest {
     /// ID of the model to use.
     pub model: String,
@@ -19,6 +19,11 @@ pub struct EmbeddingRequest {
     /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    /// The number of dimensions the output embeddings are truncated to. Only supported by models trained for it, such as `text-embedding-3`.
+    ///
+    /// Note that the dimension of a Qdrant collection the embeddings are stored in must match the number of dimensions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
 }
 
 #[test]
@@ -28,6 +33,7 @@ fn test_embedding_serialize_embedding_request() {
         input: "Hello, world!".into(),
         encoding_format: None,
         user: None,
+        dimensions: None,
     };
     let serialized = serde_json::to_string(&embedding_request).unwrap();
     assert_eq!(
@@ -40,6 +46,7 @@ fn test_embedding_serialize_embedding_request() {
         input: vec!["Hello, world!", "This is a test string"].into(),
         encoding_format: None,
         user: None,
+        dimensions: None,
     };
     let serialized = serde_json::to_string(&embedding_request).unwrap();
     assert_eq!(
@@ -69,6 +76,26 @@ fn test_embedding_deserialize_embedding_request() {
     assert_eq!(embedding_request.user, None);
 }
 
+#[test]
+fn test_embedding_dimensions() {
+    let serialized =
+        r#"{"model":"text-embedding-3-small","input":"Hello, world!","dimensions":256}"#;
+    let embedding_request: EmbeddingRequest = serde_json::from_str(serialized).unwrap();
+    assert_eq!(embedding_request.dimensions, Some(256));
+    assert_eq!(
+        serde_json::to_string(&embedding_request).unwrap(),
+        serialized
+    );
+
+    let serialized = r#"{"model":"text-embedding-3-small","input":"Hello, world!"}"#;
+    let embedding_request: EmbeddingRequest = serde_json::from_str(serialized).unwrap();
+    assert_eq!(embedding_request.dimensions, None);
+    assert_eq!(
+        serde_json::to_string(&embedding_request).unwrap(),
+        serialized
+    );
+}
+
 /// Defines the input text for the embedding request.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(untagged)]
@@ -82,6 +109,22 @@ pub enum InputText {
     /// The array of arrays containing integers that will be turned into an embedding.
     ArrayOfTokenArrays(Vec<Vec<i64>>),
 }
+impl InputText {
+    /// Returns the number of inputs to embed. An array of tokens is a single input.
+    pub fn len(&self) -> usize {
+        match self {
+            InputText::String(_) => 1,
+            InputText::ArrayOfStrings(texts) => texts.len(),
+            InputText::ArrayOfTokens(_) => 1,
+            InputText::ArrayOfTokenArrays(token_arrays) => token_arrays.len(),
+        }
+    }
+
+    /// Returns `true` if there is no input to embed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 impl From<&str> for InputText {
     fn from(s: &str) -> Self {
         InputText::String(s.to_string())
@@ -136,6 +179,69 @@ pub struct EmbeddingsResponse {
     pub model: String,
     pub usage: Usage,
 }
+impl EmbeddingsResponse {
+    /// Sorts the embeddings by `index` so that the order of `data` matches the order of the inputs.
+    pub fn sort_by_index(&mut self) {
+        self.data.sort_by_key(|embedding| embedding.index);
+    }
+
+    /// Reassembles the responses of sub-batches into a single response whose `data` is ordered by `index`.
+    ///
+    /// The `index` of each embedding must be its position in the original (unbatched) input, so the sub-batches can be passed in any order. The usages of the sub-batches are summed up. Returns `None` if `batches` is empty.
+    pub fn from_batches(batches: Vec<EmbeddingsResponse>) -> Option<Self> {
+        let mut batches = batches.into_iter();
+        let mut response = batches.next()?;
+        for batch in batches {
+            response.data.extend(batch.data);
+            response.usage.prompt_tokens += batch.usage.prompt_tokens;
+            response.usage.completion_tokens += batch.usage.completion_tokens;
+            response.usage.total_tokens += batch.usage.total_tokens;
+        }
+        response.sort_by_index();
+
+        debug_assert!(
+            response
+                .data
+                .iter()
+                .enumerate()
+                .all(|(i, embedding)| embedding.index == i as u64),
+            "The indices of the reassembled embeddings must be contiguous and start from 0."
+        );
+
+        Some(response)
+    }
+}
+
+#[test]
+fn test_embedding_reassemble_batches() {
+    let embedding = |index: u64| EmbeddingObject {
+        index,
+        object: "embedding".to_string(),
+        embedding: vec![index as f64],
+    };
+    let batch = |indices: &[u64], tokens: u64| EmbeddingsResponse {
+        object: "list".to_string(),
+        data: indices.iter().map(|i| embedding(*i)).collect(),
+        model: "model".to_string(),
+        usage: Usage {
+            prompt_tokens: tokens,
+            completion_tokens: 0,
+            total_tokens: tokens,
+        },
+    };
+
+    // the second sub-batch finishes first, and its items are out of order as well
+    let response =
+        EmbeddingsResponse::from_batches(vec![batch(&[3, 2], 5), batch(&[1, 0], 7)]).unwrap();
+    let indices: Vec<u64> = response.data.iter().map(|e| e.index).collect();
+    assert_eq!(indices, vec![0, 1, 2, 3]);
+    let vectors: Vec<f64> = response.data.iter().map(|e| e.embedding[0]).collect();
+    assert_eq!(vectors, vec![0.0, 1.0, 2.0, 3.0]);
+    assert_eq!(response.usage.prompt_tokens, 12);
+    assert_eq!(response.usage.total_tokens, 12);
+
+    assert!(EmbeddingsResponse::from_batches(vec![]).is_none());
+}
 
 /// Represents an embedding vector returned by embedding endpoint.
 #[derive(Debug, Clone, Deserialize, Serialize)]
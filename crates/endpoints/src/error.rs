@@ -0,0 +1,55 @@
+//! Define error types for the `endpoints` crate.
+
+use thiserror::Error;
+
+/// Error types for validating requests and responses.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum EndpointError {
+    /// Errors in the value of a request field or a function argument.
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+/// Error types for building a [RagChatCompletionsRequest](crate::rag::RagChatCompletionsRequest) with [RagChatCompletionRequestBuilder::try_build](crate::rag::RagChatCompletionRequestBuilder::try_build), with a variant per violated constraint. Also listed by [RagChatCompletionRequestBuilder::validation_issues](crate::rag::RagChatCompletionRequestBuilder::validation_issues).
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum RagBuildError {
+    /// `temperature` is not between 0.0 and 2.0.
+    #[error("`temperature` must be between 0.0 and 2.0, but got {0}.")]
+    TemperatureOutOfRange(f64),
+    /// `top_p` is not between 0.0 and 1.0.
+    #[error("`top_p` must be between 0.0 and 1.0, but got {0}.")]
+    TopPOutOfRange(f64),
+    /// `presence_penalty` is not between -2.0 and 2.0.
+    #[error("`presence_penalty` must be between -2.0 and 2.0, but got {0}.")]
+    PresencePenaltyOutOfRange(f64),
+    /// `frequency_penalty` is not between -2.0 and 2.0.
+    #[error("`frequency_penalty` must be between -2.0 and 2.0, but got {0}.")]
+    FrequencyPenaltyOutOfRange(f64),
+    /// `messages` is empty.
+    #[error("The conversation must contain at least one message.")]
+    EmptyMessages,
+    /// `stop` has more than 4 stop sequences.
+    #[error("Up to 4 stop sequences are allowed, but got {0}.")]
+    TooManyStopSequences(usize),
+    /// Any other invalid combination of the request fields, as reported by [RagChatCompletionsRequest::validate](crate::rag::RagChatCompletionsRequest::validate).
+    #[error(transparent)]
+    Invalid(#[from] EndpointError),
+}
+
+/// Error types for validating a [ChunksRequest](crate::rag::ChunksRequest) with [ChunksRequest::validate](crate::rag::ChunksRequest::validate).
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkError {
+    /// `chunk_overlap` is not less than the chunk capacity in use.
+    #[error(
+        "The chunk overlap must be less than the chunk capacity ({capacity}), but got {overlap}."
+    )]
+    OverlapTooLarge {
+        /// The requested overlap.
+        overlap: usize,
+        /// The capacity of the chunks, as given by the chunking strategy.
+        capacity: usize,
+    },
+    /// Any other invalid field of the request, such as an unrecognized charset or invalid parameters of the chunking strategy.
+    #[error(transparent)]
+    Invalid(#[from] EndpointError),
+}
@@ -5,8 +5,10 @@ pub mod chat;
 pub mod common;
 pub mod completions;
 pub mod embeddings;
+pub mod error;
 pub mod reranker;
 pub mod files;
 pub mod images;
 pub mod models;
+pub mod observer;
 pub mod rag;
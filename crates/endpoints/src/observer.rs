@@ -0,0 +1,140 @@
+//! Define hooks for observing the lifecycle of requests.
+//!
+//! A [RequestObserver] can be attached to the request builders, such as [ChatCompletionRequestBuilder](crate::chat::ChatCompletionRequestBuilder) and [RagChatCompletionRequestBuilder](crate::rag::RagChatCompletionRequestBuilder), to instrument the construction of requests centrally.
+
+use crate::error::EndpointError;
+use serde::Serialize;
+
+/// Callbacks invoked at the key points of the lifecycle of a request. All callbacks do nothing by default.
+pub trait RequestObserver: Send + Sync {
+    /// Called when a builder builds a request.
+    ///
+    /// * `request_type` - The name of the type of the built request.
+    fn on_build(&self, _request_type: &str) {}
+
+    /// Called when the validation of a built request fails.
+    ///
+    /// * `request_type` - The name of the type of the invalid request.
+    ///
+    /// * `error` - The validation error.
+    fn on_validate_error(&self, _request_type: &str, _error: &EndpointError) {}
+
+    /// Called for each advisory warning about a built request, such as a combination of fields some backends do not support.
+    ///
+    /// * `request_type` - The name of the type of the built request.
+    ///
+    /// * `warning` - The warning message.
+    fn on_warning(&self, _request_type: &str, _warning: &str) {}
+
+    /// Called when a request is serialized by [to_json].
+    ///
+    /// Note that the serialized request may contain credentials, such as the API key of a [VectorDbConfig](crate::rag::VectorDbConfig), so it should not be logged as is.
+    ///
+    /// * `request_type` - The name of the type of the serialized request.
+    ///
+    /// * `json` - The serialized request.
+    fn on_serialize(&self, _request_type: &str, _json: &str) {}
+}
+
+/// An observer that ignores all events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+impl RequestObserver for NoopObserver {}
+
+/// An observer that emits the events with the `tracing` crate. The serialized requests are traced by their size only.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingObserver;
+#[cfg(feature = "tracing")]
+impl RequestObserver for TracingObserver {
+    fn on_build(&self, request_type: &str) {
+        tracing::debug!(request_type, "request built");
+    }
+
+    fn on_validate_error(&self, request_type: &str, error: &EndpointError) {
+        tracing::warn!(request_type, %error, "request validation failed");
+    }
+
+    fn on_warning(&self, request_type: &str, warning: &str) {
+        tracing::warn!(request_type, warning, "request warning");
+    }
+
+    fn on_serialize(&self, request_type: &str, json: &str) {
+        // the body is not traced, since it may contain credentials
+        tracing::trace!(request_type, bytes = json.len(), "request serialized");
+    }
+}
+
+/// Serializes the request to a JSON string, and notifies the observer of the serialization.
+pub fn to_json<T: Serialize>(
+    request: &T,
+    observer: &dyn RequestObserver,
+) -> Result<String, serde_json::Error> {
+    let json = serde_json::to_string(request)?;
+    observer.on_serialize(short_type_name::<T>(), &json);
+    Ok(json)
+}
+
+/// Returns the name of the type without the module path.
+pub(crate) fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+#[test]
+fn test_observer_invocations() {
+    use crate::{chat::ChatCompletionRequestMessage, rag::RagChatCompletionRequestBuilder};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct CountingObserver {
+        builds: AtomicUsize,
+        validate_errors: AtomicUsize,
+        serializations: AtomicUsize,
+    }
+    impl RequestObserver for CountingObserver {
+        fn on_build(&self, request_type: &str) {
+            assert_eq!(request_type, "RagChatCompletionsRequest");
+            self.builds.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_validate_error(&self, _request_type: &str, _error: &EndpointError) {
+            self.validate_errors.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_serialize(&self, request_type: &str, json: &str) {
+            assert_eq!(request_type, "RagChatCompletionsRequest");
+            assert!(json.starts_with('{'));
+            self.serializations.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let observer = Arc::new(CountingObserver::default());
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+
+    let request = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        "default",
+        1,
+    )
+    .with_observer(observer.clone())
+    .build();
+    to_json(&request, observer.as_ref()).unwrap();
+
+    // the `balanced_json` stop condition without the `json_object` response format is invalid
+    let _ = RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+        .with_stop_condition(crate::chat::StopCondition::BalancedJson)
+        .with_observer(observer.clone())
+        .build();
+
+    assert_eq!(observer.builds.load(Ordering::SeqCst), 2);
+    assert_eq!(observer.validate_errors.load(Ordering::SeqCst), 1);
+    assert_eq!(observer.serializations.load(Ordering::SeqCst), 1);
+}
@@ -2,22 +2,73 @@
 
 use crate::{
     chat::{
-        ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionRequestSampling,
-        ChatResponseFormat, StreamOptions, Tool, ToolChoice,
+        stop_with_grammar_warnings, stream_with_tools_warning, validate_audio_output,
+        validate_grammar, validate_logprobs, validate_prompt_template, validate_stop_condition,
+        AudioOutputConfig, ChatCompletionObject, ChatCompletionRequest,
+        ChatCompletionRequestMessage, ChatCompletionRequestSampling,
+        ChatCompletionUserMessageContent, ChatResponseFormat, ContentPart, JsonSchema, Modality,
+        StopCondition, StreamOptions, Tool, ToolChoice,
     },
-    embeddings::EmbeddingRequest,
+    embeddings::{EmbeddingRequest, InputText},
+    error::{ChunkError, EndpointError, RagBuildError},
+    observer::{short_type_name, RequestObserver},
+};
+use base64::{engine::general_purpose, Engine as _};
+use encoding_rs::{Encoding, UTF_8};
+#[cfg(feature = "strict-deserialize")]
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
 };
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The max value of `max_parallel` of a [RagEmbeddingRequest].
+pub const MAX_PARALLEL_EMBEDDING_WORKERS: u32 = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RagEmbeddingRequest {
     #[serde(rename = "embeddings")]
     pub embedding_request: EmbeddingRequest,
-    #[serde(rename = "url")]
+    /// **Deprecated.** Use `vector_db` instead. The URL of the Qdrant server.
+    #[serde(rename = "url", default)]
     pub qdrant_url: String,
-    #[serde(rename = "collection_name")]
+    /// **Deprecated.** Use `vector_db` instead. The name of the collection in Qdrant.
+    #[serde(rename = "collection_name", default)]
     pub qdrant_collection_name: String,
+    /// Whether to return the upserted embeddings in the response. Defaults to `false`.
+    ///
+    /// Note that each embedding is a vector of floats with the dimension of the embedding model (e.g. 768 or 1024), so returning them can considerably increase the size of the response for large batches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_embeddings: Option<bool>,
+    /// How to pool the embeddings of the inputs into a single vector, e.g. to represent a whole document with one vector.
+    /// If set to a value other than `none`, a single point is stored for the whole batch, so any per-point data, such as point ids, must have length 1.
+    /// Defaults to None, which means each input is stored as a separate point.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pooling: Option<Pooling>,
+    /// The configuration of the collection to create if it does not exist yet, so that the collection does not need to be created in advance.
+    /// Defaults to None, which means the collection is created with the dimension of the computed embeddings and the cosine distance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_collection: Option<CreateCollectionConfig>,
+    /// The vector database to store the embeddings in. Takes precedence over `url` and `collection_name`, which are kept for backward compatibility. See [RagEmbeddingRequest::vector_db_config].
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vector_db: Option<VectorDbConfig>,
+    /// Embeds the whole document at once with the long context of the model and pools the token embeddings per chunk, which keeps the context of the document in the embedding of each chunk. Requires a single input, and one point is stored for each chunk.
+    /// Defaults to None, which means each input is embedded separately.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub late_chunking: Option<LateChunkingConfig>,
+    /// The number of concurrent embedding workers to use for the batch, between 1 and [MAX_PARALLEL_EMBEDDING_WORKERS]. The hint is advisory: servers may ignore it.
+    /// Defaults to None, which means the server decides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_parallel: Option<u32>,
+    /// The payloads upserted to Qdrant along with the embeddings, such as the metadata of the source documents, one for each input in the same order. The text of each input is stored as `source` in addition.
+    /// Defaults to None, which means only the text of the inputs is stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payloads: Option<Vec<HashMap<String, Value>>>,
 }
 impl RagEmbeddingRequest {
     pub fn new(
@@ -27,13 +78,21 @@ impl RagEmbeddingRequest {
     ) -> Self {
         RagEmbeddingRequest {
             embedding_request: EmbeddingRequest {
-                model: "dummy-embedding-model".to_string(),
+                model: DUMMY_EMBEDDING_MODEL.to_string(),
                 input: input.into(),
                 encoding_format: None,
                 user: None,
+                dimensions: None,
             },
             qdrant_url: qdrant_url.as_ref().to_string(),
             qdrant_collection_name: qdrant_collection_name.as_ref().to_string(),
+            return_embeddings: None,
+            payloads: None,
+            max_parallel: None,
+            late_chunking: None,
+            vector_db: None,
+            create_collection: None,
+            pooling: None,
         }
     }
 
@@ -46,10 +105,683 @@ impl RagEmbeddingRequest {
             embedding_request,
             qdrant_url: qdrant_url.as_ref().to_string(),
             qdrant_collection_name: qdrant_collection_name.as_ref().to_string(),
+            return_embeddings: None,
+            payloads: None,
+            max_parallel: None,
+            late_chunking: None,
+            vector_db: None,
+            create_collection: None,
+            pooling: None,
+        }
+    }
+
+    /// Returns the name of the embedding model, or `default` if the model is empty or the [DUMMY_EMBEDDING_MODEL] placeholder, e.g. to report the model the server actually uses.
+    pub fn resolved_model(&self, default: &str) -> String {
+        match self.embedding_request.model.as_str() {
+            "" | DUMMY_EMBEDDING_MODEL => default.to_string(),
+            model => model.to_string(),
+        }
+    }
+
+    /// Whether the embeddings of the inputs are pooled into a single vector.
+    pub fn is_pooled(&self) -> bool {
+        !matches!(self.pooling, None | Some(Pooling::None))
+    }
+
+    /// Checks that the combination of the request fields is valid.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if self.is_pooled() && self.embedding_request.input.is_empty() {
+            return Err(EndpointError::InvalidArgument(
+                "Pooling requires at least one input to embed.".to_string(),
+            ));
+        }
+
+        if let Some(config) = &self.create_collection {
+            config.validate()?;
+
+            if let Some(dimensions) = self.embedding_request.dimensions {
+                if config.dimension != dimensions as usize {
+                    return Err(EndpointError::InvalidArgument(format!(
+                        "The dimension of the collection ({}) must match the number of dimensions of the embeddings ({}).",
+                        config.dimension, dimensions
+                    )));
+                }
+            }
+        }
+
+        match &self.vector_db {
+            Some(config) => config.validate()?,
+            None => {
+                if self.qdrant_url.trim().is_empty()
+                    || self.qdrant_collection_name.trim().is_empty()
+                {
+                    return Err(EndpointError::InvalidArgument(
+                        "Either `vector_db` or both `url` and `collection_name` must be set."
+                            .to_string(),
+                    ));
+                }
+            }
+        }
+
+        if let Some(payloads) = &self.payloads {
+            if payloads.len() != self.embedding_request.input.len() {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The number of payloads must match the number of inputs, {}, but got {}.",
+                    self.embedding_request.input.len(),
+                    payloads.len()
+                )));
+            }
+            if self.is_pooled() {
+                return Err(EndpointError::InvalidArgument(
+                    "Payloads cannot be combined with pooling.".to_string(),
+                ));
+            }
+        }
+
+        if let Some(max_parallel) = self.max_parallel {
+            if !(1..=MAX_PARALLEL_EMBEDDING_WORKERS).contains(&max_parallel) {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The max number of parallel embedding workers must be between 1 and {}, but got {}.",
+                    MAX_PARALLEL_EMBEDDING_WORKERS, max_parallel
+                )));
+            }
+        }
+
+        if let Some(config) = &self.late_chunking {
+            if self.embedding_request.input.len() != 1 {
+                return Err(EndpointError::InvalidArgument(
+                    "Late chunking requires a single input to embed.".to_string(),
+                ));
+            }
+            if self.is_pooled() {
+                return Err(EndpointError::InvalidArgument(
+                    "Late chunking cannot be combined with pooling.".to_string(),
+                ));
+            }
+
+            // the number of tokens of a text input is only known after tokenization
+            let num_tokens = match &self.embedding_request.input {
+                InputText::ArrayOfTokens(tokens) => Some(tokens.len()),
+                InputText::ArrayOfTokenArrays(token_arrays) => {
+                    token_arrays.first().map(|tokens| tokens.len())
+                }
+                InputText::String(_) | InputText::ArrayOfStrings(_) => None,
+            };
+            config.validate(num_tokens)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the body of a Qdrant upsert request storing the embeddings, in the shape `{"points": [{"id", "vector", "payload"}]}`, e.g. for the official `qdrant-client` crate.
+    ///
+    /// Like the server, each point has the index of its input as the id and a payload containing the input text as `source`, merged into the payload of the input in `payloads`, if any. A pooled request is stored as a single point, whose source is the input texts joined by newlines.
+    ///
+    /// Returns an error if the input is not text, or if the number of vectors does not match the number of points.
+    pub fn to_qdrant_points(&self, vectors: Vec<Vec<f32>>) -> Result<Value, EndpointError> {
+        let texts = match &self.embedding_request.input {
+            InputText::String(text) => vec![text.clone()],
+            InputText::ArrayOfStrings(texts) => texts.clone(),
+            InputText::ArrayOfTokens(_) | InputText::ArrayOfTokenArrays(_) => {
+                return Err(EndpointError::InvalidArgument(
+                    "Qdrant points can only be created from text inputs.".to_string(),
+                ))
+            }
+        };
+        let sources = match self.is_pooled() {
+            true => vec![texts.join("\n")],
+            false => texts,
+        };
+
+        if sources.len() != vectors.len() {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The number of vectors must match the number of points, {}, but got {}.",
+                sources.len(),
+                vectors.len()
+            )));
+        }
+
+        let points: Vec<Value> = sources
+            .into_iter()
+            .zip(vectors)
+            .enumerate()
+            .map(|(index, (source, vector))| {
+                let mut payload: serde_json::Map<String, Value> = self
+                    .payloads
+                    .as_ref()
+                    .and_then(|payloads| payloads.get(index))
+                    .map(|payload| payload.clone().into_iter().collect())
+                    .unwrap_or_default();
+                payload.insert("source".to_string(), Value::String(source));
+
+                serde_json::json!({
+                    "id": index,
+                    "vector": vector,
+                    "payload": payload,
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({ "points": points }))
+    }
+
+    /// Returns the vector database to store the embeddings in, i.e. `vector_db` if set, or else the Qdrant collection given by `url` and `collection_name`.
+    pub fn vector_db_config(&self) -> VectorDbConfig {
+        match &self.vector_db {
+            Some(config) => config.clone(),
+            None => VectorDbConfig::Qdrant {
+                url: self.qdrant_url.clone(),
+                collection_name: self.qdrant_collection_name.clone(),
+            },
+        }
+    }
+
+    /// Splits the request into batches whose serialized size stays under `max_bytes`, e.g. for servers capping the size of the request body. The inputs keep their order across the batches.
+    ///
+    /// An input that is too large to fit into a batch with other inputs is put into a batch of its own, which may exceed `max_bytes`. A single string or token array input is returned as is.
+    ///
+    /// The `payloads`, if any, are split along with the inputs, and count towards the size of the batches.
+    ///
+    /// Returns an error if `max_bytes` is too small for a batch with a single empty input, if the request is pooled, since pooling the batches separately would change the result, or if the number of payloads does not match the number of inputs.
+    pub fn into_byte_batches(
+        self,
+        max_bytes: usize,
+    ) -> Result<Vec<RagEmbeddingRequest>, EndpointError> {
+        if self.is_pooled() {
+            return Err(EndpointError::InvalidArgument(
+                "A pooled embedding request cannot be split into batches.".to_string(),
+            ));
+        }
+        if let Some(payloads) = &self.payloads {
+            if payloads.len() != self.embedding_request.input.len() {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The number of payloads must match the number of inputs, {}, but got {}.",
+                    self.embedding_request.input.len(),
+                    payloads.len()
+                )));
+            }
+        }
+
+        let minimal = self.with_input(
+            InputText::ArrayOfStrings(vec![String::new()]),
+            self.payloads.as_ref().map(|_| vec![HashMap::new()]),
+        );
+        let minimal_bytes = serialized_len(&minimal);
+        if max_bytes < minimal_bytes {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The max number of bytes must be at least {} to fit a single input, but got {}.",
+                minimal_bytes, max_bytes
+            )));
+        }
+
+        match self.embedding_request.input.clone() {
+            InputText::ArrayOfStrings(texts) => {
+                Ok(self.pack_inputs(texts, max_bytes, InputText::ArrayOfStrings))
+            }
+            InputText::ArrayOfTokenArrays(token_arrays) => {
+                Ok(self.pack_inputs(token_arrays, max_bytes, InputText::ArrayOfTokenArrays))
+            }
+            InputText::String(_) | InputText::ArrayOfTokens(_) => Ok(vec![self]),
+        }
+    }
+
+    /// Returns a copy of the request with the given input and payloads.
+    fn with_input(
+        &self,
+        input: InputText,
+        payloads: Option<Vec<HashMap<String, Value>>>,
+    ) -> RagEmbeddingRequest {
+        let mut request = self.clone();
+        request.embedding_request.input = input;
+        request.payloads = payloads;
+        request
+    }
+
+    /// Packs the inputs into as few requests as possible, each of which stays under `max_bytes` unless it holds a single oversized input.
+    fn pack_inputs<T: Serialize>(
+        &self,
+        inputs: Vec<T>,
+        max_bytes: usize,
+        to_input: impl Fn(Vec<T>) -> InputText,
+    ) -> Vec<RagEmbeddingRequest> {
+        // the payloads of the inputs, if any
+        let mut payloads = self.payloads.clone().into_iter().flatten();
+        let empty_payloads = self.payloads.as_ref().map(|_| vec![]);
+
+        // the size of the request with an empty list of inputs
+        let base_bytes = serialized_len(&self.with_input(to_input(vec![]), empty_payloads.clone()));
+
+        let mut batches = vec![];
+        let mut batch = vec![];
+        let mut batch_payloads = vec![];
+        let mut batch_bytes = base_bytes;
+        for input in inputs {
+            let payload = payloads.next();
+            let input_bytes = serialized_len(&input) + payload.as_ref().map_or(0, serialized_len);
+            // the inputs, and the payloads if any, are separated by commas
+            let added_bytes = match batch.is_empty() {
+                true => input_bytes,
+                false => input_bytes + 1 + usize::from(payload.is_some()),
+            };
+            if !batch.is_empty() && batch_bytes + added_bytes > max_bytes {
+                batches.push(
+                    self.with_input(
+                        to_input(std::mem::take(&mut batch)),
+                        empty_payloads
+                            .as_ref()
+                            .map(|_| std::mem::take(&mut batch_payloads)),
+                    ),
+                );
+                batch_bytes = base_bytes + input_bytes;
+            } else {
+                batch_bytes += added_bytes;
+            }
+            batch.push(input);
+            batch_payloads.extend(payload);
+        }
+        if !batch.is_empty() {
+            batches.push(self.with_input(to_input(batch), empty_payloads.map(|_| batch_payloads)));
+        }
+
+        batches
+    }
+}
+
+/// Returns the length of the compact JSON serialization of the value.
+fn serialized_len<T: Serialize>(value: &T) -> usize {
+    serde_json::to_string(value).map_or(0, |json| json.len())
+}
+
+#[test]
+fn test_rag_embedding_request_into_byte_batches() {
+    let input: Vec<String> = ["aaaa", "bbbb", "cccc", "dddd", "eeee"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    let full_bytes = serialized_len(&request);
+
+    // everything fits
+    let batches = request.clone().into_byte_batches(full_bytes).unwrap();
+    assert_eq!(batches, vec![request.clone()]);
+
+    // each input takes 7 bytes (`"aaaa",`), so two inputs fit
+    let max_bytes = full_bytes - 3 * 7;
+    let batches = request.clone().into_byte_batches(max_bytes).unwrap();
+    assert_eq!(batches.len(), 3);
+    for batch in batches.iter() {
+        assert!(serialized_len(batch) <= max_bytes);
+        assert_eq!(batch.qdrant_collection_name, "default");
+    }
+    let inputs: Vec<InputText> = batches
+        .into_iter()
+        .map(|batch| batch.embedding_request.input)
+        .collect();
+    assert_eq!(
+        inputs,
+        vec![
+            vec!["aaaa", "bbbb"].into(),
+            vec!["cccc", "dddd"].into(),
+            vec!["eeee"].into(),
+        ]
+    );
+
+    // too small for a single minimal input
+    assert!(request.clone().into_byte_batches(10).is_err());
+}
+
+#[test]
+fn test_rag_embedding_request_into_byte_batches_oversized() {
+    let input = vec!["a".to_string(), "b".repeat(100), "c".to_string()];
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    let max_bytes = serialized_len(&request) - 90;
+
+    let batches = request.into_byte_batches(max_bytes).unwrap();
+    let inputs: Vec<InputText> = batches
+        .iter()
+        .map(|batch| batch.embedding_request.input.clone())
+        .collect();
+    assert_eq!(
+        inputs,
+        vec![
+            vec!["a".to_string()].into(),
+            vec!["b".repeat(100)].into(),
+            vec!["c".to_string()].into(),
+        ]
+    );
+    // the oversized input is in a batch of its own, exceeding the budget
+    assert!(serialized_len(&batches[1]) > max_bytes);
+    assert!(serialized_len(&batches[0]) <= max_bytes);
+
+    // pooled requests cannot be split
+    let mut request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    request.pooling = Some(Pooling::Mean);
+    assert!(request.into_byte_batches(1024).is_err());
+}
+
+/// Defines how the embeddings of multiple inputs are pooled into a single vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// No pooling. Each input is stored as a separate point.
+    None,
+    /// The element-wise mean of the embeddings.
+    Mean,
+    /// The element-wise max of the embeddings.
+    Max,
+    /// The embedding of the first input, analogous to the `[CLS]` token.
+    Cls,
+}
+
+/// The chunks of a document embedded with late chunking.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LateChunkingConfig {
+    /// The token spans of the chunks, as `(start, end)` pairs with an exclusive `end`. The spans must be non-empty, in ascending order and must not overlap.
+    pub chunk_boundaries: Vec<(usize, usize)>,
+}
+impl LateChunkingConfig {
+    /// Checks that the chunk boundaries are ordered, and within the `num_tokens` tokens of the document if known.
+    pub fn validate(&self, num_tokens: Option<usize>) -> Result<(), EndpointError> {
+        if self.chunk_boundaries.is_empty() {
+            return Err(EndpointError::InvalidArgument(
+                "Late chunking requires at least one chunk boundary.".to_string(),
+            ));
+        }
+
+        let mut previous_end = 0;
+        for &(start, end) in self.chunk_boundaries.iter() {
+            if start >= end {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The chunk boundary ({}, {}) is empty or reversed.",
+                    start, end
+                )));
+            }
+            if start < previous_end {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The chunk boundary ({}, {}) overlaps or precedes the previous one.",
+                    start, end
+                )));
+            }
+            if let Some(num_tokens) = num_tokens {
+                if end > num_tokens {
+                    return Err(EndpointError::InvalidArgument(format!(
+                        "The chunk boundary ({}, {}) exceeds the {} tokens of the document.",
+                        start, end, num_tokens
+                    )));
+                }
+            }
+            previous_end = end;
         }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rag_late_chunking() {
+    let config = LateChunkingConfig {
+        chunk_boundaries: vec![(0, 3), (3, 5), (6, 8)],
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(json, r#"{"chunk_boundaries":[[0,3],[3,5],[6,8]]}"#);
+    assert_eq!(
+        serde_json::from_str::<LateChunkingConfig>(&json).unwrap(),
+        config
+    );
+    assert!(config.validate(None).is_ok());
+    assert!(config.validate(Some(8)).is_ok());
+    assert!(config.validate(Some(7)).is_err());
+
+    let invalid = |chunk_boundaries: Vec<(usize, usize)>| {
+        LateChunkingConfig { chunk_boundaries }
+            .validate(None)
+            .is_err()
+    };
+    assert!(invalid(vec![]));
+    assert!(invalid(vec![(0, 3), (3, 3)]));
+    assert!(invalid(vec![(0, 3), (5, 4)]));
+    assert!(invalid(vec![(0, 3), (2, 5)]));
+    assert!(invalid(vec![(3, 5), (0, 3)]));
+
+    // the request
+    let tokens: Vec<i64> = (0..8).collect();
+    let mut request = RagEmbeddingRequest::from_embedding_request(
+        EmbeddingRequest {
+            model: "model".to_string(),
+            input: tokens.into(),
+            encoding_format: None,
+            user: None,
+            dimensions: None,
+        },
+        "http://localhost:6333",
+        "default",
+    );
+    request.late_chunking = Some(config.clone());
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""late_chunking":{"chunk_boundaries":[[0,3],[3,5],[6,8]]}"#));
+    let round_trip: RagEmbeddingRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, request);
+
+    // out of range of the token input
+    request.late_chunking = Some(LateChunkingConfig {
+        chunk_boundaries: vec![(0, 9)],
+    });
+    assert!(request.validate().is_err());
+
+    // a text input is only checked for the order
+    let input = vec!["A long document.".to_string()];
+    let mut request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("late_chunking"));
+    request.late_chunking = Some(LateChunkingConfig {
+        chunk_boundaries: vec![(0, 100)],
+    });
+    assert!(request.validate().is_ok());
+    request.pooling = Some(Pooling::Mean);
+    assert!(request.validate().is_err());
+
+    // multiple inputs
+    let input = vec!["chunk 1".to_string(), "chunk 2".to_string()];
+    let mut request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    request.late_chunking = Some(config);
+    assert!(request.validate().is_err());
+}
+
+/// The configuration of a Qdrant collection. Serialized in the shape of the vector parameters of Qdrant's create-collection body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateCollectionConfig {
+    /// The dimension of the vectors in the collection. Must be greater than 0.
+    #[serde(rename = "size")]
+    pub dimension: usize,
+    /// The distance metric used to compare the vectors.
+    pub distance: Distance,
+    /// Whether to store the vectors on disk instead of in memory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_disk: Option<bool>,
+}
+impl CreateCollectionConfig {
+    /// Creates a new configuration for vectors of the given dimension, compared with the cosine distance.
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            dimension,
+            distance: Distance::Cosine,
+            on_disk: None,
+        }
+    }
+
+    /// Checks that the dimension is greater than 0.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if self.dimension == 0 {
+            return Err(EndpointError::InvalidArgument(
+                "The dimension of the collection must be greater than 0.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The distance metric used by Qdrant to compare vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Distance {
+    /// Cosine similarity.
+    #[default]
+    Cosine,
+    /// Euclidean distance.
+    Euclid,
+    /// Dot product.
+    Dot,
+    /// Manhattan distance.
+    Manhattan,
+}
+impl Distance {
+    /// Converts a raw score returned by Qdrant for this metric into a similarity between 0.0 and 1.0, where higher is more similar.
+    ///
+    /// * `Cosine` and `Dot` scores are similarities in `-1.0..=1.0` (for normalized vectors) and are mapped linearly.
+    ///
+    /// * `Euclid` and `Manhattan` scores are distances in `0.0..` and are mapped to `1 / (1 + distance)`.
+    pub fn normalize_score(&self, score: f32) -> f32 {
+        let similarity = match self {
+            Distance::Cosine | Distance::Dot => (score + 1.0) / 2.0,
+            Distance::Euclid | Distance::Manhattan => 1.0 / (1.0 + score.max(0.0)),
+        };
+        similarity.clamp(0.0, 1.0)
+    }
+
+    /// Whether the raw scores of this metric are similarities, where higher is more similar, rather than distances, where lower is more similar.
+    pub fn is_similarity(&self) -> bool {
+        matches!(self, Distance::Cosine | Distance::Dot)
     }
 }
 
+#[test]
+fn test_rag_create_collection_config() {
+    let config = CreateCollectionConfig {
+        dimension: 768,
+        distance: Distance::Dot,
+        on_disk: Some(true),
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(json, r#"{"size":768,"distance":"Dot","on_disk":true}"#);
+    assert!(config.validate().is_ok());
+
+    let config: CreateCollectionConfig =
+        serde_json::from_str(r#"{"size":1024,"distance":"Cosine"}"#).unwrap();
+    assert_eq!(config, CreateCollectionConfig::new(1024));
+
+    let mut request =
+        RagEmbeddingRequest::new(&["chunk 1".to_string()], "http://localhost:6333", "default");
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("create_collection"));
+
+    request.create_collection = Some(CreateCollectionConfig::new(384));
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.ends_with(r#""create_collection":{"size":384,"distance":"Cosine"}}"#));
+    assert!(request.validate().is_ok());
+
+    request.create_collection = Some(CreateCollectionConfig::new(0));
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_embedding_request_to_qdrant_points() {
+    let input = vec!["chunk 1".to_string(), "chunk 2".to_string()];
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+
+    let points = request
+        .to_qdrant_points(vec![vec![0.5, 0.25], vec![0.125, 1.0]])
+        .unwrap();
+    assert_eq!(
+        points,
+        serde_json::json!({
+            "points": [
+                {"id": 0, "vector": [0.5, 0.25], "payload": {"source": "chunk 1"}},
+                {"id": 1, "vector": [0.125, 1.0], "payload": {"source": "chunk 2"}},
+            ]
+        })
+    );
+
+    // the number of vectors does not match
+    assert!(request.to_qdrant_points(vec![vec![0.5, 0.25]]).is_err());
+    assert!(request.to_qdrant_points(vec![]).is_err());
+
+    // a pooled request is stored as a single point
+    let mut request = request;
+    request.pooling = Some(Pooling::Mean);
+    let points = request.to_qdrant_points(vec![vec![0.5, 0.25]]).unwrap();
+    assert_eq!(points["points"].as_array().unwrap().len(), 1);
+    assert_eq!(points["points"][0]["payload"]["source"], "chunk 1\nchunk 2");
+
+    // token inputs have no source text
+    let request = RagEmbeddingRequest::from_embedding_request(
+        EmbeddingRequest {
+            model: "model".to_string(),
+            input: vec![1_i64, 2, 3].into(),
+            encoding_format: None,
+            user: None,
+            dimensions: None,
+        },
+        "http://localhost:6333",
+        "default",
+    );
+    assert!(request.to_qdrant_points(vec![vec![0.5]]).is_err());
+}
+
+#[test]
+fn test_rag_embedding_request_max_parallel() {
+    let input = vec!["chunk 1".to_string(), "chunk 2".to_string()];
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("max_parallel"));
+
+    let request = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_max_parallel(8)
+        .build();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""max_parallel":8"#));
+    let round_trip: RagEmbeddingRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, request);
+
+    let mut request = request;
+    request.max_parallel = Some(MAX_PARALLEL_EMBEDDING_WORKERS);
+    assert!(request.validate().is_ok());
+    request.max_parallel = Some(MAX_PARALLEL_EMBEDDING_WORKERS + 1);
+    assert!(request.validate().is_err());
+    request.max_parallel = Some(0);
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_embedding_request_pooling() {
+    let mut request = RagEmbeddingRequest::new(
+        &["chunk 1".to_string(), "chunk 2".to_string()],
+        "http://localhost:6333",
+        "default",
+    );
+    assert!(!request.is_pooled());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("pooling"));
+
+    request.pooling = Some(Pooling::None);
+    assert!(!request.is_pooled());
+
+    request.pooling = Some(Pooling::Mean);
+    assert!(request.is_pooled());
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.ends_with(r#""pooling":"mean"}"#));
+    let request: RagEmbeddingRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.pooling, Some(Pooling::Mean));
+
+    let json = r#"{"embeddings":{"model":"model","input":[]},"url":"http://localhost:6333","collection_name":"default","pooling":"cls"}"#;
+    let request: RagEmbeddingRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.pooling, Some(Pooling::Cls));
+    assert!(request.validate().is_err());
+}
+
 #[test]
 fn test_rag_serialize_embedding_request() {
     let embedding_request = EmbeddingRequest {
@@ -57,6 +789,7 @@ fn test_rag_serialize_embedding_request() {
         input: "Hello, world!".into(),
         encoding_format: None,
         user: None,
+        dimensions: None,
     };
     let qdrant_url = "http://localhost:6333".to_string();
     let qdrant_collection_name = "qdrant_collection_name".to_string();
@@ -64,6 +797,13 @@ fn test_rag_serialize_embedding_request() {
         embedding_request,
         qdrant_url,
         qdrant_collection_name,
+        return_embeddings: None,
+        payloads: None,
+        max_parallel: None,
+        late_chunking: None,
+        vector_db: None,
+        create_collection: None,
+        pooling: None,
     };
     let json = serde_json::to_string(&rag_embedding_request).unwrap();
     assert_eq!(
@@ -88,303 +828,6666 @@ fn test_rag_deserialize_embedding_request() {
     );
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct RagChatCompletionsRequest {
-    /// The model to use for generating completions.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub chat_model: Option<String>,
-    /// A list of messages comprising the conversation so far.
-    pub messages: Vec<ChatCompletionRequestMessage>,
-    /// ID of the embedding model to use.
-    pub embedding_model: String,
-    /// The format to return the embeddings in. Can be either float or base64.
-    /// Defaults to float.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub encoding_format: Option<String>,
-    /// The URL of the Qdrant server.
-    pub qdrant_url: String,
-    /// The name of the collection in Qdrant.
-    pub qdrant_collection_name: String,
-    /// Max number of retrieved results.
-    pub limit: u64,
-    /// Adjust the randomness of the generated text. Between 0.0 and 2.0. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
-    ///
-    /// We generally recommend altering this or top_p but not both.
-    /// Defaults to 1.0.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub temperature: Option<f64>,
-    /// Limit the next token selection to a subset of tokens with a cumulative probability above a threshold P. The value should be between 0.0 and 1.0.
+/// Request builder for creating a new RAG embedding request.
+pub struct RagEmbeddingRequestBuilder {
+    req: RagEmbeddingRequest,
+}
+impl RagEmbeddingRequestBuilder {
+    /// Creates a new builder.
     ///
-    /// Top-p sampling, also known as nucleus sampling, is another text generation method that selects the next token from a subset of tokens that together have a cumulative probability of at least p. This method provides a balance between diversity and quality by considering both the probabilities of tokens and the number of tokens to sample from. A higher value for top_p (e.g., 0.95) will lead to more diverse text, while a lower value (e.g., 0.5) will generate more focused and conservative text.
+    /// # Arguments
     ///
-    /// We generally recommend altering this or temperature but not both.
-    /// Defaults to 1.0.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub top_p: Option<f64>,
-    /// How many chat completion choices to generate for each input message.
-    /// Defaults to 1.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub n_choice: Option<u64>,
-    /// Whether to stream the results as they are generated. Useful for chatbots.
-    /// Defaults to false.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub stream: Option<bool>,
-    /// Options for streaming response. Only set this when you set `stream: true`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub stream_options: Option<StreamOptions>,
-    /// A list of tokens at which to stop generation. If None, no stop tokens are used. Up to 4 sequences where the API will stop generating further tokens.
-    /// Defaults to None
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub stop: Option<Vec<String>>,
-    /// The maximum number of tokens to generate. The value should be no less than 1.
-    /// Defaults to 1024.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub max_tokens: Option<u64>,
-    /// Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far, increasing the model's likelihood to talk about new topics.
-    /// Defaults to 0.0.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub presence_penalty: Option<f64>,
-    /// Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far, decreasing the model's likelihood to repeat the same line verbatim.
-    /// Defaults to 0.0.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub frequency_penalty: Option<f64>,
-    /// Modify the likelihood of specified tokens appearing in the completion.
+    /// * `input` - The texts to embed.
     ///
-    /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
-    /// Defaults to None.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub logit_bias: Option<HashMap<String, f64>>,
-    /// A unique identifier representing your end-user.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub user: Option<String>,
-    /// Format that the model must output
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub response_format: Option<ChatResponseFormat>,
-    /// A list of tools the model may call.
+    /// * `qdrant_url` - The URL of the Qdrant server.
     ///
-    /// Currently, only functions are supported as a tool. Use this to provide a list of functions the model may generate JSON inputs for.
-    pub tools: Option<Vec<Tool>>,
-    /// Controls which (if any) function is called by the model.
-    pub tool_choice: Option<ToolChoice>,
+    /// * `qdrant_collection_name` - The name of the collection in Qdrant.
+    pub fn new(
+        input: &[String],
+        qdrant_url: impl AsRef<str>,
+        qdrant_collection_name: impl AsRef<str>,
+    ) -> Self {
+        Self {
+            req: RagEmbeddingRequest::new(input, qdrant_url, qdrant_collection_name),
+        }
+    }
 
-    /// Number of user messages to use for context retrieval. Defaults to 1.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub context_window: Option<u64>,
-}
-impl RagChatCompletionsRequest {
-    pub fn as_chat_completions_request(&self) -> ChatCompletionRequest {
-        ChatCompletionRequest {
-            model: self.chat_model.clone(),
-            messages: self.messages.clone(),
-            temperature: self.temperature,
-            top_p: self.top_p,
-            n_choice: self.n_choice,
-            stream: self.stream,
-            stream_options: self.stream_options.clone(),
-            stop: self.stop.clone(),
-            max_tokens: self.max_tokens,
+    /// Sets the embedding model. Defaults to [DUMMY_EMBEDDING_MODEL], which means the model the server is started with.
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.req.embedding_request.model = model.into();
+        self
+    }
+
+    /// Sets the format to return the embeddings in, either `float` or `base64`.
+    pub fn with_encoding_format(mut self, encoding_format: impl Into<String>) -> Self {
+        self.req.embedding_request.encoding_format = Some(encoding_format.into());
+        self
+    }
+
+    /// Sets the unique identifier representing the end-user.
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.req.embedding_request.user = Some(user.into());
+        self
+    }
+
+    /// Sets the number of dimensions the embeddings are truncated to, which must match the dimension of the collection.
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.req.embedding_request.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Sets the payloads upserted along with the embeddings, one for each input in the same order.
+    pub fn with_payloads(mut self, payloads: Vec<HashMap<String, Value>>) -> Self {
+        self.req.payloads = Some(payloads);
+        self
+    }
+
+    /// Sets the vector database to store the embeddings in.
+    pub fn with_vector_db(mut self, config: VectorDbConfig) -> Self {
+        self.req.vector_db = Some(config);
+        self
+    }
+
+    /// Sets the number of concurrent embedding workers the server should use.
+    pub fn with_max_parallel(mut self, max_parallel: u32) -> Self {
+        self.req.max_parallel = Some(max_parallel);
+        self
+    }
+
+    pub fn build(self) -> RagEmbeddingRequest {
+        self.req
+    }
+
+    /// Builds the request, returning an error if it fails [RagEmbeddingRequest::validate], e.g. because the number of payloads does not match the number of inputs.
+    pub fn try_build(self) -> Result<RagEmbeddingRequest, EndpointError> {
+        self.req.validate()?;
+
+        Ok(self.req)
+    }
+}
+
+#[test]
+fn test_rag_embedding_request_payloads() {
+    let input = vec!["chunk 1".to_string(), "chunk 2".to_string()];
+    let payload = |title: &str, page: u64| -> HashMap<String, Value> {
+        let mut payload = HashMap::new();
+        payload.insert("title".to_string(), Value::from(title));
+        payload.insert("page".to_string(), Value::from(page));
+        payload
+    };
+
+    let request = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_payloads(vec![payload("LlamaEdge", 1), payload("WasmEdge", 2)])
+        .try_build()
+        .unwrap();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""payloads":[{"#));
+    assert_eq!(
+        serde_json::from_str::<RagEmbeddingRequest>(&json).unwrap(),
+        request
+    );
+
+    // the payloads are upserted along with the sources
+    let points = request
+        .to_qdrant_points(vec![vec![0.5, 0.25], vec![0.125, 1.0]])
+        .unwrap();
+    assert_eq!(
+        points["points"][1]["payload"],
+        serde_json::json!({ "source": "chunk 2", "title": "WasmEdge", "page": 2 })
+    );
+
+    // the payloads are split along with the inputs
+    let batches = request.clone().into_byte_batches(json.len() - 1).unwrap();
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[1].payloads, Some(vec![payload("WasmEdge", 2)]));
+    assert_eq!(
+        batches[1].embedding_request.input,
+        InputText::from(vec!["chunk 2"])
+    );
+
+    // omitted by default
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("payloads"));
+
+    // one payload per input
+    let result = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_payloads(vec![payload("LlamaEdge", 1)])
+        .try_build();
+    assert_eq!(
+        result,
+        Err(EndpointError::InvalidArgument(
+            "The number of payloads must match the number of inputs, 2, but got 1.".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_rag_embedding_request_resolved_model() {
+    let input = vec!["Hello, world!".to_string()];
+
+    // the dummy model is substituted
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    assert_eq!(
+        request.resolved_model("nomic-embed-text-v1.5"),
+        "nomic-embed-text-v1.5"
+    );
+    let request = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_model("")
+        .build();
+    assert_eq!(
+        request.resolved_model("nomic-embed-text-v1.5"),
+        "nomic-embed-text-v1.5"
+    );
+
+    // an explicit model is kept
+    let request = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_model("all-MiniLM-L6-v2")
+        .build();
+    assert_eq!(
+        request.resolved_model("nomic-embed-text-v1.5"),
+        "all-MiniLM-L6-v2"
+    );
+}
+
+#[test]
+fn test_rag_embedding_request_dimensions() {
+    let input = vec!["Hello, world!".to_string()];
+
+    let mut request = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_model("text-embedding-3-small")
+        .with_dimensions(256)
+        .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""dimensions":256"#));
+    assert_eq!(
+        serde_json::from_str::<RagEmbeddingRequest>(&json).unwrap(),
+        request
+    );
+    assert!(request.validate().is_ok());
+
+    // the collection to create must have the same dimension
+    request.create_collection = Some(CreateCollectionConfig::new(256));
+    assert!(request.validate().is_ok());
+    request.create_collection = Some(CreateCollectionConfig::new(1536));
+    assert!(request.validate().is_err());
+
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("dimensions"));
+}
+
+#[test]
+fn test_rag_embedding_request_builder() {
+    let input = vec!["Hello, world!".to_string(), "Bye!".to_string()];
+
+    let request = RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default")
+        .with_model("nomic-embed-text-v1.5")
+        .with_encoding_format("base64")
+        .with_user("user-123")
+        .build();
+    let expected = RagEmbeddingRequest::from_embedding_request(
+        EmbeddingRequest {
+            model: "nomic-embed-text-v1.5".to_string(),
+            input: input.as_slice().into(),
+            encoding_format: Some("base64".to_string()),
+            user: Some("user-123".to_string()),
+            dimensions: None,
+        },
+        "http://localhost:6333",
+        "default",
+    );
+    assert_eq!(
+        serde_json::to_string(&request).unwrap(),
+        serde_json::to_string(&expected).unwrap()
+    );
+
+    // the model defaults to the dummy model, like `RagEmbeddingRequest::new`
+    let request =
+        RagEmbeddingRequestBuilder::new(&input, "http://localhost:6333", "default").build();
+    assert_eq!(request.embedding_request.model, DUMMY_EMBEDDING_MODEL);
+    assert_eq!(
+        serde_json::to_string(&request).unwrap(),
+        serde_json::to_string(&RagEmbeddingRequest::new(
+            &input,
+            "http://localhost:6333",
+            "default"
+        ))
+        .unwrap()
+    );
+}
+
+/// The vector database storing the embeddings, tagged by `backend`.
+///
+/// The `Debug` output redacts the credentials, i.e. the Milvus `token` and the Pinecone `api_key`.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum VectorDbConfig {
+    /// A collection in a [Qdrant](https://qdrant.tech) server.
+    Qdrant {
+        /// The URL of the Qdrant server.
+        url: String,
+        /// The name of the collection.
+        collection_name: String,
+    },
+    /// A collection in a [Milvus](https://milvus.io) server.
+    Milvus {
+        /// The URI of the Milvus server.
+        uri: String,
+        /// The name of the collection.
+        collection: String,
+        /// The token to authenticate with, if the server requires authentication.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// An index in [Pinecone](https://www.pinecone.io).
+    Pinecone {
+        /// The name of the index.
+        index: String,
+        /// The namespace within the index. Defaults to the default namespace.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+        /// The API key to authenticate with.
+        api_key: String,
+    },
+    /// A local vector file, e.g. for offline or embedded use without a vector database server.
+    #[serde(rename = "local_file")]
+    LocalFile {
+        /// The path to the vector file.
+        path: String,
+    },
+}
+impl VectorDbConfig {
+    /// Returns the name of the collection the embeddings are stored in, i.e. the index for Pinecone and the path for a local vector file.
+    pub fn collection_name(&self) -> &str {
+        match self {
+            VectorDbConfig::Qdrant {
+                collection_name, ..
+            } => collection_name,
+            VectorDbConfig::Milvus { collection, .. } => collection,
+            VectorDbConfig::Pinecone { index, .. } => index,
+            VectorDbConfig::LocalFile { path } => path,
+        }
+    }
+
+    /// Checks that the path of a local vector file is not empty.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if let VectorDbConfig::LocalFile { path } = self {
+            if path.trim().is_empty() {
+                return Err(EndpointError::InvalidArgument(
+                    "The path of the local vector file must not be empty.".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+impl fmt::Debug for VectorDbConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const REDACTED: &str = "<redacted>";
+
+        match self {
+            VectorDbConfig::Qdrant {
+                url,
+                collection_name,
+            } => f
+                .debug_struct("Qdrant")
+                .field("url", url)
+                .field("collection_name", collection_name)
+                .finish(),
+            VectorDbConfig::Milvus {
+                uri,
+                collection,
+                token,
+            } => f
+                .debug_struct("Milvus")
+                .field("uri", uri)
+                .field("collection", collection)
+                .field("token", &token.as_ref().map(|_| REDACTED))
+                .finish(),
+            VectorDbConfig::Pinecone {
+                index,
+                namespace,
+                api_key: _,
+            } => f
+                .debug_struct("Pinecone")
+                .field("index", index)
+                .field("namespace", namespace)
+                .field("api_key", &REDACTED)
+                .finish(),
+            VectorDbConfig::LocalFile { path } => {
+                f.debug_struct("LocalFile").field("path", path).finish()
+            }
+        }
+    }
+}
+impl From<&RagEmbeddingRequest> for VectorDbConfig {
+    fn from(request: &RagEmbeddingRequest) -> Self {
+        request.vector_db_config()
+    }
+}
+impl From<&RagChatCompletionsRequest> for VectorDbConfig {
+    fn from(request: &RagChatCompletionsRequest) -> Self {
+        request.vector_db_config()
+    }
+}
+
+#[test]
+fn test_rag_serialize_vector_db_config() {
+    let config = VectorDbConfig::Qdrant {
+        url: "http://localhost:6333".to_string(),
+        collection_name: "default".to_string(),
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(
+        json,
+        r#"{"backend":"qdrant","url":"http://localhost:6333","collection_name":"default"}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<VectorDbConfig>(&json).unwrap(),
+        config
+    );
+
+    let config = VectorDbConfig::Milvus {
+        uri: "http://localhost:19530".to_string(),
+        collection: "docs".to_string(),
+        token: Some("root:Milvus".to_string()),
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(
+        json,
+        r#"{"backend":"milvus","uri":"http://localhost:19530","collection":"docs","token":"root:Milvus"}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<VectorDbConfig>(&json).unwrap(),
+        config
+    );
+    let config: VectorDbConfig = serde_json::from_str(
+        r#"{"backend":"milvus","uri":"http://localhost:19530","collection":"docs"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config,
+        VectorDbConfig::Milvus {
+            uri: "http://localhost:19530".to_string(),
+            collection: "docs".to_string(),
+            token: None,
+        }
+    );
+
+    let config = VectorDbConfig::Pinecone {
+        index: "docs".to_string(),
+        namespace: Some("en".to_string()),
+        api_key: "pc-key".to_string(),
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(
+        json,
+        r#"{"backend":"pinecone","index":"docs","namespace":"en","api_key":"pc-key"}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<VectorDbConfig>(&json).unwrap(),
+        config
+    );
+
+    assert!(serde_json::from_str::<VectorDbConfig>(r#"{"backend":"chroma","url":"x"}"#).is_err());
+
+    // the credentials are redacted in the debug output
+    assert_eq!(
+        format!("{:?}", config),
+        r#"Pinecone { index: "docs", namespace: Some("en"), api_key: "<redacted>" }"#
+    );
+    let config = VectorDbConfig::Milvus {
+        uri: "http://localhost:19530".to_string(),
+        collection: "docs".to_string(),
+        token: Some("root:Milvus".to_string()),
+    };
+    assert!(!format!("{:?}", config).contains("root:Milvus"));
+    let request = RagEmbeddingRequestBuilder::new(&[], "http://localhost:6333", "default")
+        .with_vector_db(config)
+        .build();
+    assert!(!format!("{:?}", request).contains("root:Milvus"));
+}
+
+#[test]
+fn test_rag_local_file_vector_db() {
+    let config = VectorDbConfig::LocalFile {
+        path: "/data/vectors.db".to_string(),
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(
+        json,
+        r#"{"backend":"local_file","path":"/data/vectors.db"}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<VectorDbConfig>(&json).unwrap(),
+        config
+    );
+    assert!(config.validate().is_ok());
+
+    // the Qdrant fields are not required
+    let json = r#"{"messages":[],"embedding_model":"model","limit":3,"vector_db":{"backend":"local_file","path":"/data/vectors.db"}}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert!(request.qdrant_url.is_empty());
+    assert_eq!(request.vector_db_config(), config);
+    assert!(request.validate().is_ok());
+
+    let request = RagChatCompletionsRequest::from_local_file(vec![], "/data/vectors.db", 3);
+    assert_eq!(request.vector_db_config(), config);
+    assert_eq!(request.limit, 3);
+    assert!(request.validate().is_ok());
+
+    let json = r#"{"embeddings":{"model":"model","input":"Hello, world!"},"vector_db":{"backend":"local_file","path":"/data/vectors.db"}}"#;
+    let mut request: RagEmbeddingRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.vector_db_config(), config);
+    assert!(request.validate().is_ok());
+
+    // the path must not be empty
+    request.vector_db = Some(VectorDbConfig::LocalFile {
+        path: " ".to_string(),
+    });
+    assert!(request.validate().is_err());
+    let request = RagChatCompletionsRequest::from_local_file(vec![], "", 3);
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_vector_db_compatibility() {
+    // the old payloads map to the Qdrant backend
+    let json = r#"{"embeddings":{"model":"model","input":"Hello, world!"},"url":"http://localhost:6333","collection_name":"default"}"#;
+    let mut request: RagEmbeddingRequest = serde_json::from_str(json).unwrap();
+    assert!(request.vector_db.is_none());
+    assert_eq!(
+        VectorDbConfig::from(&request),
+        VectorDbConfig::Qdrant {
+            url: "http://localhost:6333".to_string(),
+            collection_name: "default".to_string(),
+        }
+    );
+    assert!(request.validate().is_ok());
+
+    // without `vector_db`, both Qdrant fields are required
+    request.qdrant_collection_name.clear();
+    assert!(request.validate().is_err());
+    let json = r#"{"embeddings":{"model":"model","input":"Hello, world!"}}"#;
+    let request: RagEmbeddingRequest = serde_json::from_str(json).unwrap();
+    assert!(request.validate().is_err());
+
+    let json = r#"{"messages":[],"embedding_model":"model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        VectorDbConfig::from(&request),
+        VectorDbConfig::Qdrant {
+            url: "http://localhost:6333".to_string(),
+            collection_name: "default".to_string(),
+        }
+    );
+
+    // the new payloads may omit the Qdrant fields
+    let milvus = VectorDbConfig::Milvus {
+        uri: "http://localhost:19530".to_string(),
+        collection: "docs".to_string(),
+        token: None,
+    };
+    let json = r#"{"embeddings":{"model":"model","input":"Hello, world!"},"vector_db":{"backend":"milvus","uri":"http://localhost:19530","collection":"docs"}}"#;
+    let request: RagEmbeddingRequest = serde_json::from_str(json).unwrap();
+    assert!(request.qdrant_url.is_empty());
+    assert_eq!(request.vector_db_config(), milvus);
+    assert!(request.validate().is_ok());
+
+    let json = r#"{"messages":[],"embedding_model":"model","limit":3,"vector_db":{"backend":"milvus","uri":"http://localhost:19530","collection":"docs"}}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.vector_db_config(), milvus);
+
+    let request = RagEmbeddingRequestBuilder::new(&[], "http://localhost:6333", "default")
+        .with_vector_db(milvus.clone())
+        .build();
+    assert_eq!(request.vector_db_config(), milvus);
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_vector_db(milvus.clone())
+            .build();
+    assert_eq!(request.vector_db_config(), milvus);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""vector_db":{"backend":"milvus""#));
+}
+
+/// Represents the result of upserting the embeddings of a [RagEmbeddingRequest].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RagEmbeddingResponse {
+    /// The name of the collection the embeddings were upserted to, see [VectorDbConfig::collection_name].
+    pub collection_name: String,
+    /// The number of upserted points.
+    pub count: usize,
+    /// The upserted embeddings, in the same order as the inputs. Present only if `return_embeddings` of the request is `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embeddings: Option<Vec<Vec<f32>>>,
+}
+impl RagEmbeddingResponse {
+    /// Creates a new response for the given request and upserted embeddings.
+    ///
+    /// The embeddings are kept in the response only if `return_embeddings` of the request is `true`.
+    pub fn new(request: &RagEmbeddingRequest, embeddings: Vec<Vec<f32>>) -> Self {
+        let count = embeddings.len();
+        let embeddings = match request.return_embeddings {
+            Some(true) => Some(embeddings),
+            _ => None,
+        };
+
+        Self {
+            collection_name: request.vector_db_config().collection_name().to_string(),
+            count,
+            embeddings,
+        }
+    }
+}
+
+#[test]
+fn test_rag_serialize_embedding_response() {
+    let mut request = RagEmbeddingRequest::new(
+        &["Hello".to_string(), "world".to_string()],
+        "http://localhost:6333",
+        "default",
+    );
+    let embeddings = vec![vec![0.5, 0.25], vec![0.125, 1.0]];
+
+    let response = RagEmbeddingResponse::new(&request, embeddings.clone());
+    let json = serde_json::to_string(&response).unwrap();
+    assert_eq!(json, r#"{"collection_name":"default","count":2}"#);
+
+    request.return_embeddings = Some(true);
+    let response = RagEmbeddingResponse::new(&request, embeddings);
+    let json = serde_json::to_string(&response).unwrap();
+    assert_eq!(
+        json,
+        r#"{"collection_name":"default","count":2,"embeddings":[[0.5,0.25],[0.125,1.0]]}"#
+    );
+
+    // the collection is taken from `vector_db` if set
+    let json = r#"{"embeddings":{"model":"model","input":"Hello, world!"},"vector_db":{"backend":"milvus","uri":"http://localhost:19530","collection":"docs"}}"#;
+    let request: RagEmbeddingRequest = serde_json::from_str(json).unwrap();
+    let response = RagEmbeddingResponse::new(&request, vec![vec![0.5, 0.25]]);
+    assert_eq!(response.collection_name, "docs");
+    assert_eq!(response.count, 1);
+
+    let request = RagEmbeddingRequestBuilder::new(&[], "http://localhost:6333", "default")
+        .with_vector_db(VectorDbConfig::Pinecone {
+            index: "index".to_string(),
+            namespace: None,
+            api_key: "pc-key".to_string(),
+        })
+        .build();
+    let response = RagEmbeddingResponse::new(&request, vec![]);
+    assert_eq!(response.collection_name, "index");
+}
+
+#[test]
+fn test_rag_deserialize_embedding_response() {
+    let json = r#"{"collection_name":"default","count":2}"#;
+    let response: RagEmbeddingResponse = serde_json::from_str(json).unwrap();
+    assert_eq!(response.count, 2);
+    assert!(response.embeddings.is_none());
+
+    let json = r#"{"collection_name":"default","count":2,"embeddings":[[0.5,0.25],[0.125,1.0]]}"#;
+    let response: RagEmbeddingResponse = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        response.embeddings,
+        Some(vec![vec![0.5, 0.25], vec![0.125, 1.0]])
+    );
+}
+
+/// Represents a chat completion response along with the retrieved points the context was built from, e.g. for tracing an answer back to its sources.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RagChatCompletionResponse {
+    /// The chat completion, whose fields are serialized inline.
+    #[serde(flatten)]
+    pub completion: ChatCompletionObject,
+    /// The retrieved points used as the context, in the order they were given to the model.
+    pub retrieved: Vec<RagScoredPoint>,
+    /// The score threshold the retrieved points were filtered by.
+    pub score_threshold: f32,
+    /// How many retrieved points made it into the context and why the others were dropped. Omitted if not reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_budget: Option<ContextBudgetReport>,
+}
+impl RagChatCompletionResponse {
+    /// Creates a new response from the chat completion and the retrieval it is based on.
+    pub fn new(completion: ChatCompletionObject, retrieve_object: RetrieveObject) -> Self {
+        Self {
+            completion,
+            retrieved: retrieve_object.points.unwrap_or_default(),
+            score_threshold: retrieve_object.score_threshold,
+            context_budget: None,
+        }
+    }
+
+    /// Attaches the report of how the context was assembled.
+    pub fn with_context_budget(mut self, report: ContextBudgetReport) -> Self {
+        self.context_budget = Some(report);
+        self
+    }
+}
+
+/// The `dropped_reason` key recorded once for each point that scores below `score_threshold` or `min_context_score`.
+pub const DROPPED_BELOW_THRESHOLD: &str = "below_threshold";
+/// The `dropped_reason` key recorded once for each point left out because adding its source would exceed the character budget of the context.
+pub const DROPPED_OVER_CHAR_BUDGET: &str = "over_char_budget";
+/// The `dropped_reason` key recorded once for each point removed as a duplicate of a higher-scoring point with the same id, e.g. by [RetrieveObject::dedup_by_id].
+pub const DROPPED_DEDUPED: &str = "deduped";
+/// The `dropped_reason` key recorded once for each point of a retrieval rejected because its points come from fewer than `min_distinct_sources` documents, even after fetching more candidates.
+pub const DROPPED_FAILED_DISTINCT_SOURCES: &str = "failed_distinct_sources";
+
+/// Tallies the retrieved points included in the context and the ones dropped, by reason.
+///
+/// The report is not filled in by the retrieval itself: whoever assembles the context records the points it includes and drops with [ContextBudgetReport::record_included] and [ContextBudgetReport::record_dropped], following the counting convention documented on each reason. The reasons are keyed by [DROPPED_BELOW_THRESHOLD], [DROPPED_OVER_CHAR_BUDGET], [DROPPED_DEDUPED] and [DROPPED_FAILED_DISTINCT_SOURCES]; a reason with no drops is absent. `dropped` is always the sum of the counts in `dropped_reason`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextBudgetReport {
+    /// The number of retrieved points included in the context.
+    pub included: usize,
+    /// The number of retrieved points left out of the context.
+    pub dropped: usize,
+    /// The number of dropped points per reason.
+    #[serde(default)]
+    pub dropped_reason: HashMap<String, usize>,
+}
+impl ContextBudgetReport {
+    /// Records `count` points included in the context.
+    pub fn record_included(&mut self, count: usize) {
+        self.included += count;
+    }
+
+    /// Records `count` points dropped for `reason`. Recording no points leaves the report unchanged.
+    pub fn record_dropped(&mut self, reason: impl Into<String>, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        self.dropped += count;
+        *self.dropped_reason.entry(reason.into()).or_default() += count;
+    }
+}
+
+#[test]
+fn test_rag_serialize_chat_completion_response() {
+    let json = r#"{"id":"chatcmpl-abc123","object":"chat.completion","created":1699896916,"model":"llama-3-8b","choices":[{"index":0,"message":{"content":"LlamaEdge runs LLMs locally.","role":"assistant"},"finish_reason":"stop","logprobs":null}],"usage":{"prompt_tokens":82,"completion_tokens":17,"total_tokens":99}}"#;
+    let completion: ChatCompletionObject = serde_json::from_str(json).unwrap();
+    let retrieve_object = RetrieveObject {
+        points: Some(vec![RagScoredPoint {
+            source: "LlamaEdge is a runtime for local LLMs.".to_string(),
+            score: 0.75,
+            ..Default::default()
+        }]),
+        limit: 3,
+        score_threshold: 0.5,
+        filter: None,
+    };
+
+    let response = RagChatCompletionResponse::new(completion, retrieve_object);
+    let json = serde_json::to_string(&response).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":"chatcmpl-abc123","object":"chat.completion","created":1699896916,"model":"llama-3-8b","choices":[{"index":0,"message":{"content":"LlamaEdge runs LLMs locally.","role":"assistant"},"finish_reason":"stop","logprobs":null}],"usage":{"prompt_tokens":82,"completion_tokens":17,"total_tokens":99},"retrieved":[{"source":"LlamaEdge is a runtime for local LLMs.","score":0.75}],"score_threshold":0.5}"#
+    );
+
+    let round_trip: RagChatCompletionResponse = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip.completion.id, "chatcmpl-abc123");
+    assert_eq!(round_trip.retrieved, response.retrieved);
+    assert_eq!(round_trip.score_threshold, 0.5);
+    assert_eq!(serde_json::to_string(&round_trip).unwrap(), json);
+
+    // no points retrieved
+    let completion: ChatCompletionObject = serde_json::from_str(
+        r#"{"id":"chatcmpl-abc123","object":"chat.completion","created":1699896916,"model":"llama-3-8b","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":0,"total_tokens":0}}"#,
+    )
+    .unwrap();
+    let response = RagChatCompletionResponse::new(completion, RetrieveObject::default());
+    assert!(response.retrieved.is_empty());
+    assert!(serde_json::to_string(&response)
+        .unwrap()
+        .ends_with(r#""retrieved":[],"score_threshold":0.0}"#));
+}
+
+#[test]
+fn test_rag_context_budget_report() {
+    let mut report = ContextBudgetReport::default();
+    report.record_included(3);
+    report.record_dropped(DROPPED_BELOW_THRESHOLD, 2);
+    report.record_dropped(DROPPED_DEDUPED, 1);
+    report.record_dropped(DROPPED_BELOW_THRESHOLD, 1);
+    report.record_dropped(DROPPED_OVER_CHAR_BUDGET, 0);
+    assert_eq!(report.included, 3);
+    assert_eq!(report.dropped, 4);
+    assert_eq!(report.dropped_reason.len(), 2);
+    assert_eq!(report.dropped_reason[DROPPED_BELOW_THRESHOLD], 3);
+    assert_eq!(report.dropped_reason[DROPPED_DEDUPED], 1);
+
+    let completion: ChatCompletionObject = serde_json::from_str(
+        r#"{"id":"chatcmpl-abc123","object":"chat.completion","created":1699896916,"model":"llama-3-8b","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":0,"total_tokens":0}}"#,
+    )
+    .unwrap();
+    let response = RagChatCompletionResponse::new(completion, RetrieveObject::default())
+        .with_context_budget(report.clone());
+    let json = serde_json::to_string(&response).unwrap();
+    let value: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        value["context_budget"],
+        serde_json::json!({
+            "included": 3,
+            "dropped": 4,
+            "dropped_reason": { "below_threshold": 3, "deduped": 1 }
+        })
+    );
+    let round_trip: RagChatCompletionResponse = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip.context_budget, Some(report));
+
+    // the report is optional
+    let json = r#"{"id":"chatcmpl-abc123","object":"chat.completion","created":1699896916,"model":"llama-3-8b","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":0,"total_tokens":0},"retrieved":[],"score_threshold":0.0}"#;
+    let response: RagChatCompletionResponse = serde_json::from_str(json).unwrap();
+    assert!(response.context_budget.is_none());
+    assert_eq!(serde_json::to_string(&response).unwrap(), json);
+
+    // reasons without drops may be omitted
+    let report: ContextBudgetReport =
+        serde_json::from_str(r#"{"included":2,"dropped":0}"#).unwrap();
+    assert_eq!(report.included, 2);
+    assert!(report.dropped_reason.is_empty());
+}
+
+/// The default max number of messages in the conversation of a [RagChatCompletionsRequest].
+pub const DEFAULT_MAX_MESSAGES: usize = 10_000;
+
+/// The answer returned by default when the retrieved context does not reach the `min_context_score` of a [RagChatCompletionsRequest].
+pub const DEFAULT_REFUSAL_MESSAGE: &str =
+    "I'm sorry, I couldn't find enough relevant information to answer your question.";
+
+/// The floor of `max_tokens` when fitted to the context window by [RagChatCompletionsRequest::autofit_max_tokens].
+pub const MIN_AUTOFIT_MAX_TOKENS: u64 = 16;
+
+/// The payload field identifying the source document of a point, used to count distinct documents for `min_distinct_sources`.
+pub const DOCUMENT_ID_PAYLOAD_KEY: &str = "file_id";
+
+/// The placeholder chat model set by [RagChatCompletionRequestBuilder::new].
+pub const DUMMY_CHAT_MODEL: &str = "dummy-chat-model";
+
+/// The placeholder embedding model set by [RagChatCompletionRequestBuilder::new] and [RagEmbeddingRequest::new].
+pub const DUMMY_EMBEDDING_MODEL: &str = "dummy-embedding-model";
+
+/// Returns `true` if the chat model is absent or the [DUMMY_CHAT_MODEL] placeholder.
+fn is_unset_chat_model(chat_model: &Option<String>) -> bool {
+    chat_model
+        .as_deref()
+        .is_none_or(|model| model == DUMMY_CHAT_MODEL)
+}
+
+/// Serializes the embedding model, warning if it is still the [DUMMY_EMBEDDING_MODEL] placeholder.
+fn serialize_embedding_model<S: Serializer>(
+    embedding_model: &str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[cfg(feature = "tracing")]
+    if embedding_model == DUMMY_EMBEDDING_MODEL {
+        tracing::warn!(
+            embedding_model,
+            "serializing a request with the placeholder embedding model"
+        );
+    }
+
+    serializer.serialize_str(embedding_model)
+}
+
+/// Deserializes the chat model, rejecting the [DUMMY_CHAT_MODEL] placeholder.
+#[cfg(feature = "strict-deserialize")]
+fn deserialize_chat_model<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let chat_model = Option::<String>::deserialize(deserializer)?;
+    if chat_model.as_deref() == Some(DUMMY_CHAT_MODEL) {
+        return Err(de::Error::custom(format!(
+            "`chat_model` must not be the `{}` placeholder; omit it to use the default chat model of the server",
+            DUMMY_CHAT_MODEL
+        )));
+    }
+
+    Ok(chat_model)
+}
+
+/// Deserializes the embedding model, rejecting the [DUMMY_EMBEDDING_MODEL] placeholder.
+#[cfg(feature = "strict-deserialize")]
+fn deserialize_embedding_model<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let embedding_model = String::deserialize(deserializer)?;
+    if embedding_model == DUMMY_EMBEDDING_MODEL {
+        return Err(de::Error::custom(format!(
+            "`embedding_model` must not be the `{}` placeholder; set it to the name of an embedding model",
+            DUMMY_EMBEDDING_MODEL
+        )));
+    }
+
+    Ok(embedding_model)
+}
+
+/// Represents a chat completion request with retrieval-augmented generation.
+///
+/// Implements `PartialEq` but not `Eq`, because the sampling parameters are floats: a request containing a NaN is not equal to itself.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RagChatCompletionsRequest {
+    /// The model to use for generating completions. Omitted from the serialized request if it is the [DUMMY_CHAT_MODEL] placeholder, so that the server picks its default model.
+    ///
+    /// With the `strict-deserialize` feature, deserializing a request with the [DUMMY_CHAT_MODEL] placeholder fails.
+    #[serde(skip_serializing_if = "is_unset_chat_model")]
+    #[cfg_attr(
+        feature = "strict-deserialize",
+        serde(default, deserialize_with = "deserialize_chat_model")
+    )]
+    pub chat_model: Option<String>,
+    /// A list of messages comprising the conversation so far.
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// ID of the embedding model to use. The [DUMMY_EMBEDDING_MODEL] placeholder is reported by [RagChatCompletionsRequest::warnings], and a warning is emitted with the `tracing` feature if the request is serialized with it.
+    ///
+    /// With the `strict-deserialize` feature, deserializing a request with the [DUMMY_EMBEDDING_MODEL] placeholder fails.
+    #[serde(serialize_with = "serialize_embedding_model")]
+    #[cfg_attr(
+        feature = "strict-deserialize",
+        serde(deserialize_with = "deserialize_embedding_model")
+    )]
+    pub embedding_model: String,
+    /// The format to return the embeddings in. Can be either float or base64.
+    /// Defaults to float.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<String>,
+    /// **Deprecated.** Use `vector_db` instead. The URL of the Qdrant server.
+    #[serde(default)]
+    pub qdrant_url: String,
+    /// **Deprecated.** Use `vector_db` instead. The name of the collection in Qdrant.
+    #[serde(default)]
+    pub qdrant_collection_name: String,
+    /// Max number of retrieved results.
+    pub limit: u64,
+    /// Adjust the randomness of the generated text. Between 0.0 and 2.0. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
+    ///
+    /// We generally recommend altering this or top_p but not both.
+    /// Defaults to 1.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Limit the next token selection to a subset of tokens with a cumulative probability above a threshold P. The value should be between 0.0 and 1.0.
+    ///
+    /// Top-p sampling, also known as nucleus sampling, is another text generation method that selects the next token from a subset of tokens that together have a cumulative probability of at least p. This method provides a balance between diversity and quality by considering both the probabilities of tokens and the number of tokens to sample from. A higher value for top_p (e.g., 0.95) will lead to more diverse text, while a lower value (e.g., 0.5) will generate more focused and conservative text.
+    ///
+    /// We generally recommend altering this or temperature but not both.
+    /// Defaults to 1.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// How many chat completion choices to generate for each input message.
+    /// Defaults to 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_choice: Option<u64>,
+    /// Whether to stream the results as they are generated. Useful for chatbots.
+    /// Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Options for streaming response. Only set this when you set `stream: true`; [RagChatCompletionsRequest::validate] rejects stream options on a non-streaming request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    /// A list of tokens at which to stop generation. If None, no stop tokens are used. Up to 4 sequences where the API will stop generating further tokens.
+    /// Defaults to None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// The maximum number of tokens to generate. The value should be no less than 1.
+    /// Defaults to 1024.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far, increasing the model's likelihood to talk about new topics.
+    /// Defaults to 0.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    /// Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far, decreasing the model's likelihood to repeat the same line verbatim.
+    /// Defaults to 0.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    /// Modify the likelihood of specified tokens appearing in the completion.
+    ///
+    /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<String, f64>>,
+    /// A unique identifier representing your end-user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Format that the model must output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ChatResponseFormat>,
+    /// A list of tools the model may call.
+    ///
+    /// Currently, only functions are supported as a tool. Use this to provide a list of functions the model may generate JSON inputs for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// Controls which (if any) function is called by the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+
+    /// Number of user messages to use for context retrieval. Defaults to 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u64>,
+    /// A condition, in addition to `stop`, on which to stop generation.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_condition: Option<StopCondition>,
+    /// Whether to inject the retrieved context into the prompt. If `false`, the retrieval still runs and the retrieved sources are returned in the response, but the prompt is left untouched.
+    /// Defaults to true.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inject_context: Option<bool>,
+    /// The calibration that maps the raw similarity scores of the retrieved points to confidence values between 0.0 and 1.0.
+    /// Defaults to None, which means the raw scores are used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_calibration: Option<ScoreCalibration>,
+    /// The normalization steps applied in order to the query text before it is embedded for retrieval.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_preprocessing: Option<Vec<QueryStep>>,
+    /// Output types that the model generates. Defaults to `["text"]` if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<Modality>>,
+    /// Parameters for the audio output. Required if `audio` is in `modalities`, and must not be set otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOutputConfig>,
+    /// Whether to return the breakdown of the score of each retrieved point by scoring component, such as `dense`, `sparse`, `recency` or `rerank`, for debugging.
+    /// Defaults to None, which means no breakdown is returned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<bool>,
+    /// A unique key identifying the request, which allows the request to be safely retried without duplicate generations. Mirrors the `Idempotency-Key` HTTP header. See [generate_idempotency_key](crate::chat::generate_idempotency_key).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// The keywords the retrieved points must or must not contain, applied before the vector search.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword_filter: Option<KeywordFilter>,
+    /// The names of multiple collections in Qdrant to retrieve from. Takes precedence over `qdrant_collection_name` if set.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qdrant_collection_names: Option<Vec<String>>,
+    /// The minimum score of the top retrieved point, between 0.0 and 1.0. If no retrieved point reaches it, the server answers with `refusal_message` instead of generating, to prevent hallucination when the retrieval is weak. For `Euclid` and `Manhattan` collections whose points are not all reranked, it is the maximum distance of the closest point instead, see [RagChatCompletionsRequest::refusal].
+    /// Note that `score_threshold` is applied first: if it filters out all points, the request is refused as well.
+    /// Defaults to None, which means the answer is always generated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_context_score: Option<f32>,
+    /// The answer returned when the retrieved context does not reach `min_context_score`.
+    /// Defaults to None, which means [DEFAULT_REFUSAL_MESSAGE] is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal_message: Option<String>,
+    /// Whether to stop generating as soon as a tool call is emitted, so that the server returns promptly with the `tool_calls` finish reason, e.g. in agent loops.
+    /// Defaults to None, which means `true` if `tools` are provided.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_on_tool_call: Option<bool>,
+    /// The embedding model each collection was built with, by collection name, overriding `embedding_model` for that collection. If set, every collection in `qdrant_collection_names` must have a mapping.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection_embedding_models: Option<HashMap<String, String>>,
+    /// The language to answer in, as a BCP-47 tag such as `en` or `zh-Hans`, regardless of the language of the retrieved documents. The server appends the [instruction](RagChatCompletionsRequest::answer_language_instruction) to answer in this language to the prompt.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_language: Option<String>,
+    /// An id correlating the request with a [CancelRequest](crate::chat::CancelRequest), which allows a separate endpoint to abort the generation. Use `ensure_request_id` to generate one if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Whether to prepend the assistant reply preceding the latest user message to the retrieval query, e.g. to resolve pronouns in follow-up questions.
+    /// Defaults to None, which means `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_assistant_context: Option<bool>,
+    /// The minimum number of distinct documents the retrieved points must come from, for well-rounded answers. If fewer are represented, the server fetches more candidates, up to its `fetch_k` limit, until enough documents are present or the candidates run out. See [RagChatCompletionsRequest::next_fetch_limit].
+    /// Documents are identified by the [DOCUMENT_ID_PAYLOAD_KEY] payload field. Must be positive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_distinct_sources: Option<usize>,
+    /// Max number of retrieved results per collection, by collection name, overriding `limit` for that collection. Only used with `qdrant_collection_names`.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_per_collection: Option<HashMap<String, u64>>,
+    /// The vector database to retrieve from. Takes precedence over `qdrant_url` and `qdrant_collection_name`, which are kept for backward compatibility. See [RagChatCompletionsRequest::vector_db_config].
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vector_db: Option<VectorDbConfig>,
+    /// The minimum score of the retrieved points, passed to the vector search. Points scoring below it are not retrieved, even if fewer than `limit` points remain. The meaning of the score depends on the distance metric of the collection, see [RetrieveObject::score_threshold].
+    /// Defaults to None, which means no filtering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_threshold: Option<f32>,
+    /// A Qdrant payload filter passed to the vector search, e.g. `{"must": [{"key": "doc_type", "match": {"value": "policy"}}]}` to only retrieve the points of policy documents. See the [Qdrant documentation](https://qdrant.tech/documentation/concepts/filtering/) for the syntax.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Value>,
+    /// Whether to turn off streaming in [RagChatCompletionsRequest::as_chat_completions_request] if tools are provided, for backends that cannot stream with tools.
+    /// Defaults to None, which means `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_non_stream_with_tools: Option<bool>,
+    /// If set, the sampling is deterministic: repeated requests with the same seed and parameters return the same result, e.g. for regression testing.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// A [GBNF grammar](https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md) constraining the structure of the generated text, which overrides the grammar the model is served with. Generation still halts at the first `stop` sequence.
+    /// Setting both `grammar` and a JSON response format is invalid.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    /// How the retrieved points are searched: by dense vectors, sparse vectors, or both.
+    /// Defaults to None, which means dense search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_mode: Option<SearchMode>,
+    /// The reranking stage run after the retrieval: `limit` points are retrieved, reranked by a cross-encoder model, and the best `top_n` of them are kept.
+    /// Defaults to None, which means no reranking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank: Option<RerankConfig>,
+    /// The name of the prompt template to apply to the messages, such as `llama-3-chat` or `chatml`, for servers hosting multiple templates. Must not be empty.
+    /// Defaults to None, which means the template the model is served with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<String>,
+    /// Discard tokens whose probability is below `min_p` times the probability of the most likely token. The value should be between 0.0 and 1.0; 0.0 disables min-p sampling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f64>,
+    /// Limit the next token selection to the `top_k` most likely tokens.
+    ///
+    /// `top_k` can be combined with `top_p`: the candidates are first limited to the `top_k` most likely tokens, and then to the ones within the cumulative probability `top_p`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Penalize the tokens repeated within the last `repeat_last_n` tokens, as in llama.cpp. 1.0 disables the penalty; higher values discourage repetition more.
+    ///
+    /// Unlike `presence_penalty` and `frequency_penalty`, the penalty scales the logits of the repeated tokens rather than subtracting from them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    /// The number of most recent tokens considered by `repeat_penalty`; 0 disables the penalty. The -1 of llama.cpp, i.e. the whole context, is not supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_last_n: Option<u32>,
+    /// Whether to return the log probabilities of the output tokens in the `logprobs` of each choice.
+    /// Defaults to None, which means false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// The number of most likely tokens to return at each token position, each with its log probability, between 0 and 20. Requires `logprobs` to be true.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// Whether the model may call several of the `tools` in a single turn. Only used if `tools` is set.
+    /// Defaults to None, which the server should treat as true, as in the OpenAI API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    /// The number of dimensions the query embedding is truncated to, which must match the dimension of the collection, e.g. when the documents were embedded with the same `dimensions`. See [EmbeddingRequest::dimensions](crate::embeddings::EmbeddingRequest::dimensions).
+    /// Defaults to None, which means the full dimension of the embedding model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+}
+impl RagChatCompletionsRequest {
+    /// Creates a new request with the same defaults as [RagChatCompletionRequestBuilder::new].
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - A list of messages comprising the conversation so far.
+    ///
+    /// * `qdrant_url` - The URL of the Qdrant server.
+    ///
+    /// * `qdrant_collections` - The name of the collection in Qdrant, or the names of multiple collections.
+    ///
+    /// * `limit` - Max number of retrieved results.
+    pub fn new(
+        messages: Vec<ChatCompletionRequestMessage>,
+        qdrant_url: impl Into<String>,
+        qdrant_collections: impl Into<QdrantCollections>,
+        limit: u64,
+    ) -> Self {
+        RagChatCompletionRequestBuilder::new(messages, qdrant_url, qdrant_collections, limit)
+            .build()
+    }
+
+    /// Creates a new RAG chat completion request retrieving from a local vector file instead of a Qdrant server.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The messages to generate a response for.
+    ///
+    /// * `path` - The path to the local vector file.
+    ///
+    /// * `limit` - Max number of retrieved results.
+    pub fn from_local_file(
+        messages: Vec<ChatCompletionRequestMessage>,
+        path: impl Into<String>,
+        limit: u64,
+    ) -> Self {
+        RagChatCompletionRequestBuilder::new(messages, "", "", limit)
+            .with_vector_db(VectorDbConfig::LocalFile { path: path.into() })
+            .build()
+    }
+
+    pub fn as_chat_completions_request(&self) -> ChatCompletionRequest {
+        let forces_non_stream = self.forces_non_stream();
+        ChatCompletionRequest {
+            model: self.chat_model.clone(),
+            messages: self.messages.clone(),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            n_choice: self.n_choice,
+            stream: match forces_non_stream {
+                true => Some(false),
+                false => self.stream,
+            },
+            stream_options: match forces_non_stream {
+                true => None,
+                false => self.stream_options.clone(),
+            },
+            stop: self.stop.clone(),
+            max_tokens: self.max_tokens,
             presence_penalty: self.presence_penalty,
             frequency_penalty: self.frequency_penalty,
             logit_bias: self.logit_bias.clone(),
             user: self.user.clone(),
             functions: None,
             function_call: None,
-            response_format: self.response_format.clone(),
-            tool_choice: self.tool_choice.clone(),
-            tools: self.tools.clone(),
-            context_window: self.context_window,
+            response_format: self.response_format.clone(),
+            tool_choice: self.tool_choice.clone(),
+            tools: self.tools.clone(),
+            context_window: self.context_window,
+            stop_condition: self.stop_condition,
+            modalities: self.modalities.clone(),
+            audio: self.audio.clone(),
+            idempotency_key: self.idempotency_key.clone(),
+            stop_on_tool_call: self.stop_on_tool_call,
+            request_id: self.request_id.clone(),
+            seed: self.seed,
+            grammar: self.grammar.clone(),
+            prompt_template: self.prompt_template.clone(),
+            min_p: self.min_p,
+            top_k: self.top_k,
+            repeat_penalty: self.repeat_penalty,
+            repeat_last_n: self.repeat_last_n,
+            logprobs: self.logprobs,
+            top_logprobs: self.top_logprobs,
+            parallel_tool_calls: self.parallel_tool_calls,
+        }
+    }
+
+    pub fn from_chat_completions_request(
+        chat_completions_request: ChatCompletionRequest,
+        qdrant_url: impl Into<String>,
+        qdrant_collections: impl Into<QdrantCollections>,
+        limit: u64,
+    ) -> Self {
+        let (qdrant_collection_name, qdrant_collection_names) =
+            qdrant_collections.into().into_fields();
+        RagChatCompletionsRequest {
+            chat_model: chat_completions_request.model,
+            messages: chat_completions_request.messages,
+            embedding_model: DUMMY_EMBEDDING_MODEL.to_string(),
+            encoding_format: None,
+            qdrant_url: qdrant_url.into(),
+            qdrant_collection_name,
+            limit,
+            temperature: chat_completions_request.temperature,
+            top_p: chat_completions_request.top_p,
+            n_choice: chat_completions_request.n_choice,
+            stream: chat_completions_request.stream,
+            stream_options: chat_completions_request.stream_options,
+            stop: chat_completions_request.stop,
+            max_tokens: chat_completions_request.max_tokens,
+            presence_penalty: chat_completions_request.presence_penalty,
+            frequency_penalty: chat_completions_request.frequency_penalty,
+            logit_bias: chat_completions_request.logit_bias,
+            user: chat_completions_request.user,
+            response_format: chat_completions_request.response_format,
+            tool_choice: chat_completions_request.tool_choice,
+            tools: chat_completions_request.tools,
+            context_window: chat_completions_request.context_window,
+            stop_condition: chat_completions_request.stop_condition,
+            inject_context: None,
+            score_calibration: None,
+            query_preprocessing: None,
+            modalities: chat_completions_request.modalities,
+            audio: chat_completions_request.audio,
+            explain: None,
+            idempotency_key: chat_completions_request.idempotency_key,
+            keyword_filter: None,
+            qdrant_collection_names,
+            min_context_score: None,
+            refusal_message: None,
+            stop_on_tool_call: chat_completions_request.stop_on_tool_call,
+            collection_embedding_models: None,
+            answer_language: None,
+            request_id: chat_completions_request.request_id,
+            include_assistant_context: None,
+            min_distinct_sources: None,
+            limit_per_collection: None,
+            vector_db: None,
+            score_threshold: None,
+            filter: None,
+            force_non_stream_with_tools: None,
+            seed: chat_completions_request.seed,
+            grammar: chat_completions_request.grammar,
+            search_mode: None,
+            rerank: None,
+            prompt_template: chat_completions_request.prompt_template,
+            min_p: chat_completions_request.min_p,
+            top_k: chat_completions_request.top_k,
+            repeat_penalty: chat_completions_request.repeat_penalty,
+            repeat_last_n: chat_completions_request.repeat_last_n,
+            logprobs: chat_completions_request.logprobs,
+            top_logprobs: chat_completions_request.top_logprobs,
+            parallel_tool_calls: chat_completions_request.parallel_tool_calls,
+            dimensions: None,
+        }
+    }
+
+    /// Creates a new request whose conversation is a single user message with the given prompt, for clients migrating from the completions API.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt to use as the content of the user message.
+    ///
+    /// * `qdrant_url` - The URL of the Qdrant server.
+    ///
+    /// * `qdrant_collections` - The name of the collection in Qdrant, or the names of multiple collections.
+    ///
+    /// * `limit` - Max number of retrieved results.
+    pub fn from_prompt(
+        prompt: impl Into<String>,
+        qdrant_url: impl Into<String>,
+        qdrant_collections: impl Into<QdrantCollections>,
+        limit: u64,
+    ) -> Self {
+        let messages = vec![ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(prompt.into()),
+            None,
+        )];
+
+        RagChatCompletionRequestBuilder::new(messages, qdrant_url, qdrant_collections, limit)
+            .build()
+    }
+
+    /// Checks that the conversation contains no more than `max_messages` messages.
+    pub fn validate_max_messages(&self, max_messages: usize) -> Result<(), EndpointError> {
+        if self.messages.len() > max_messages {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The conversation contains {} messages, which exceeds the limit of {} messages.",
+                self.messages.len(),
+                max_messages
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the text of the most recent user message, which is the question to embed for retrieval.
+    ///
+    /// Tool results and assistant turns following the question are skipped, as well as user messages without any text, e.g. image-only messages.
+    pub fn latest_human_query(&self) -> Option<&str> {
+        self.latest_human_query_position().map(|(_, query)| query)
+    }
+
+    /// Returns the position in `messages` and the text of the latest human query.
+    fn latest_human_query_position(&self) -> Option<(usize, &str)> {
+        self.messages
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(position, message)| match message {
+                ChatCompletionRequestMessage::User(message) => {
+                    let text = match message.content() {
+                        ChatCompletionUserMessageContent::Text(text) => Some(text.as_str()),
+                        ChatCompletionUserMessageContent::Parts(parts) => {
+                            parts.iter().find_map(|part| match part {
+                                ContentPart::Text(part) => Some(part.text()),
+                                _ => None,
+                            })
+                        }
+                    };
+                    text.filter(|text| !text.trim().is_empty())
+                        .map(|text| (position, text))
+                }
+                _ => None,
+            })
+    }
+
+    /// Returns the text to embed for retrieval: the [latest human query](RagChatCompletionsRequest::latest_human_query), preceded by the previous assistant reply if `include_assistant_context` is enabled, and preprocessed by the `query_preprocessing` steps.
+    ///
+    /// The previous assistant reply is the last assistant message with text before the query, skipping tool results. It is not included if another user message comes in between.
+    pub fn retrieval_query_text(&self) -> Option<String> {
+        let (position, query) = self.latest_human_query_position()?;
+
+        let assistant_reply = match self.include_assistant_context {
+            Some(true) => self.messages[..position]
+                .iter()
+                .rev()
+                .find_map(|message| match message {
+                    ChatCompletionRequestMessage::Assistant(message) => Some(
+                        message
+                            .content()
+                            .map(|content| content.as_str())
+                            .filter(|content| !content.trim().is_empty()),
+                    ),
+                    ChatCompletionRequestMessage::User(_) => Some(None),
+                    _ => None,
+                })
+                .flatten(),
+            _ => None,
+        };
+
+        let text = match assistant_reply {
+            Some(reply) => format!("{}\n{}", reply, query),
+            None => query.to_string(),
+        };
+        Some(self.preprocess_query(&text))
+    }
+
+    /// Applies the `query_preprocessing` steps of the request to the query text. Returns the query as is if no steps are configured.
+    pub fn preprocess_query(&self, query: &str) -> String {
+        match &self.query_preprocessing {
+            Some(steps) => preprocess_query(query, steps),
+            None => query.to_string(),
+        }
+    }
+
+    /// Returns a key for caching the embedding of the retrieval query, so that it can be reused across requests.
+    ///
+    /// The key is the hex-encoded SHA-256 hash of the `embedding_model`, the `collection_embedding_models`, the `dimensions` and the [retrieval query text](RagChatCompletionsRequest::retrieval_query_text), i.e. of everything that determines the query embeddings. The fields that only affect the search, such as `limit`, are not part of the key.
+    pub fn embedding_cache_key(&self) -> String {
+        let query = self.retrieval_query_text().unwrap_or_default();
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.embedding_model.as_bytes());
+        hasher.update([0]);
+        if let Some(models) = &self.collection_embedding_models {
+            // sorted, since the order of a `HashMap` is arbitrary
+            let mut models: Vec<_> = models.iter().collect();
+            models.sort();
+            for (collection_name, model) in models {
+                hasher.update(collection_name.as_bytes());
+                hasher.update([0]);
+                hasher.update(model.as_bytes());
+                hasher.update([0]);
+            }
+        }
+        hasher.update([0]);
+        if let Some(dimensions) = self.dimensions {
+            hasher.update(dimensions.to_le_bytes());
+        }
+        hasher.update([0]);
+        hasher.update(query.as_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Returns the request id, generating one by [generate_request_id](crate::chat::generate_request_id) if absent.
+    pub fn ensure_request_id(&mut self) -> &str {
+        self.request_id
+            .get_or_insert_with(crate::chat::generate_request_id)
+    }
+
+    /// Fills the fields which are empty or set to a placeholder with the given defaults, e.g. provided by the environment. Explicitly set fields are preserved.
+    pub fn apply_defaults(&mut self, defaults: &RagDefaults) {
+        if let Some(chat_model) = &defaults.chat_model {
+            if self
+                .chat_model
+                .as_deref()
+                .is_none_or(|model| model.is_empty() || model == DUMMY_CHAT_MODEL)
+            {
+                self.chat_model = Some(chat_model.clone());
+            }
+        }
+        if let Some(embedding_model) = &defaults.embedding_model {
+            if self.embedding_model.is_empty() || self.embedding_model == DUMMY_EMBEDDING_MODEL {
+                self.embedding_model = embedding_model.clone();
+            }
+        }
+        if let Some(qdrant_url) = &defaults.qdrant_url {
+            if self.qdrant_url.is_empty() {
+                self.qdrant_url = qdrant_url.clone();
+            }
+        }
+        if let Some(qdrant_collection_name) = &defaults.qdrant_collection_name {
+            if self.qdrant_collection_name.is_empty() {
+                self.qdrant_collection_name = qdrant_collection_name.clone();
+            }
+        }
+    }
+
+    /// Checks that the combination of the request fields is valid.
+    ///
+    /// The conversation is limited to [DEFAULT_MAX_MESSAGES] messages. Use [RagChatCompletionsRequest::validate_max_messages] to enforce a different limit.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        self.checks().into_iter().collect()
+    }
+
+    /// Runs the checks of [RagChatCompletionsRequest::validate], in order.
+    fn checks(&self) -> Vec<Result<(), EndpointError>> {
+        vec![
+            self.validate_max_messages(DEFAULT_MAX_MESSAGES),
+            validate_stop_condition(self.stop_condition, self.response_format.as_ref()),
+            validate_audio_output(self.modalities.as_deref(), self.audio.as_ref()),
+            self.stream_options
+                .as_ref()
+                .map_or(Ok(()), |stream_options| stream_options.validate()),
+            self.keyword_filter
+                .as_ref()
+                .map_or(Ok(()), |filter| filter.validate()),
+            self.score_calibration
+                .as_ref()
+                .map_or(Ok(()), |calibration| calibration.validate()),
+            self.validate_min_context_score(),
+            self.validate_collection_embedding_models(),
+            self.validate_answer_language(),
+            self.validate_min_distinct_sources(),
+            self.validate_collections(),
+            self.vector_db
+                .as_ref()
+                .map_or(Ok(()), |config| config.validate()),
+            self.resolved_search_mode().validate(),
+            self.rerank
+                .as_ref()
+                .map_or(Ok(()), |rerank| rerank.validate(self.limit)),
+            validate_prompt_template(self.prompt_template.as_deref()),
+            validate_grammar(self.grammar.as_deref(), self.response_format.as_ref()),
+            self.validate_stream_options_with_stream(),
+            validate_logprobs(self.logprobs, self.top_logprobs),
+            self.response_format
+                .as_ref()
+                .map_or(Ok(()), |response_format| response_format.validate()),
+        ]
+    }
+
+    fn validate_stream_options_with_stream(&self) -> Result<(), EndpointError> {
+        if self.stream_options.is_some() && self.stream != Some(true) {
+            return Err(EndpointError::InvalidArgument(
+                "The `stream_options` can only be set when `stream` is true.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_min_distinct_sources(&self) -> Result<(), EndpointError> {
+        match self.min_distinct_sources {
+            Some(0) => Err(EndpointError::InvalidArgument(
+                "The min number of distinct sources must be positive.".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the larger limit to fetch the candidates with if the retrieved points come from fewer than `min_distinct_sources` documents, or `None` if no more fetching is needed.
+    ///
+    /// The limit doubles on each call, capped at `fetch_k`. No more fetching is needed if enough documents are present, if fewer points than `retrieved.limit` were returned, i.e. the candidates ran out, or if `retrieved.limit` already reached `fetch_k`.
+    ///
+    /// * `retrieved` - The points retrieved with the current limit.
+    ///
+    /// * `fetch_k` - The max number of candidates to fetch.
+    pub fn next_fetch_limit(&self, retrieved: &RetrieveObject, fetch_k: usize) -> Option<usize> {
+        let min_distinct_sources = self.min_distinct_sources?;
+
+        let fetched = retrieved.points.as_ref().map_or(0, |points| points.len());
+        if retrieved.distinct_documents() >= min_distinct_sources
+            || fetched < retrieved.limit
+            || retrieved.limit >= fetch_k
+        {
+            return None;
+        }
+
+        Some((retrieved.limit.max(1) * 2).min(fetch_k))
+    }
+
+    fn validate_answer_language(&self) -> Result<(), EndpointError> {
+        match &self.answer_language {
+            Some(language) if language.trim().is_empty() => Err(EndpointError::InvalidArgument(
+                "The answer language must not be empty.".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the instruction to answer in `answer_language`, if set.
+    pub fn answer_language_instruction(&self) -> Option<String> {
+        self.answer_language.as_ref().map(|language| {
+            format!(
+                "Answer in the language with the BCP-47 tag `{}`, regardless of the language of the context.",
+                language.trim()
+            )
+        })
+    }
+
+    fn validate_collection_embedding_models(&self) -> Result<(), EndpointError> {
+        if let Some(models) = &self.collection_embedding_models {
+            if let Some(name) = self
+                .collection_names()
+                .into_iter()
+                .find(|name| !models.contains_key(*name))
+            {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "No embedding model is mapped to the collection `{}`.",
+                    name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the embedding model to query the given collection with, i.e. the model mapped to the collection in `collection_embedding_models`, or else `embedding_model`.
+    pub fn embedding_model_for(&self, collection_name: &str) -> &str {
+        self.collection_embedding_models
+            .as_ref()
+            .and_then(|models| models.get(collection_name))
+            .unwrap_or(&self.embedding_model)
+    }
+
+    fn validate_min_context_score(&self) -> Result<(), EndpointError> {
+        match self.min_context_score {
+            Some(score) if !(0.0..=1.0).contains(&score) => {
+                Err(EndpointError::InvalidArgument(format!(
+                    "The min context score must be between 0.0 and 1.0, but got {}.",
+                    score
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the answer to respond with instead of generating if no retrieved point reaches `min_context_score`, or `None` if the answer should be generated.
+    ///
+    /// The points are compared by their [ranking score](RagScoredPoint::ranking_score) if all of them are reranked, where higher is better. Otherwise their raw scores are compared in the direction of the metric: a point reaches `min_context_score` if its similarity is at least `min_context_score` for `Cosine` and `Dot`, or if its distance is at most `min_context_score` for `Euclid` and `Manhattan`.
+    ///
+    /// # Arguments
+    ///
+    /// * `retrieved` - The retrieved points.
+    ///
+    /// * `distance` - The distance metric of the collection the points are retrieved from.
+    pub fn refusal(&self, retrieved: &RetrieveObject, distance: Distance) -> Option<&str> {
+        let min_context_score = self.min_context_score?;
+
+        let points: Vec<&RagScoredPoint> = retrieved.points.iter().flatten().collect();
+        let all_reranked = all_reranked(points.iter().copied());
+        // rerank scores are similarities whatever the metric of the collection
+        let higher_is_better = all_reranked || distance.is_similarity();
+        let reached = points.iter().any(|point| {
+            let score = point.ranking_score_among(all_reranked);
+            match higher_is_better {
+                true => score >= min_context_score,
+                false => score <= min_context_score,
+            }
+        });
+        match reached {
+            true => None,
+            false => Some(
+                self.refusal_message
+                    .as_deref()
+                    .unwrap_or(DEFAULT_REFUSAL_MESSAGE),
+            ),
+        }
+    }
+
+    /// Whether the retrieved context is injected into the prompt. Defaults to `true` if `inject_context` is not set.
+    pub fn injects_context(&self) -> bool {
+        self.inject_context.unwrap_or(true)
+    }
+
+    /// Returns the names of the collections to retrieve from, i.e. `qdrant_collection_names` if set, or else `qdrant_collection_name`.
+    pub fn collection_names(&self) -> Vec<&str> {
+        match &self.qdrant_collection_names {
+            Some(names) => names.iter().map(|name| name.as_str()).collect(),
+            None => vec![self.qdrant_collection_name.as_str()],
+        }
+    }
+
+    /// Whether a non-empty list of tools is provided.
+    fn has_tools(&self) -> bool {
+        self.tools.as_ref().is_some_and(|tools| !tools.is_empty())
+    }
+
+    /// Whether streaming is turned off because of `force_non_stream_with_tools`, in which case `stream_options` are dropped as well.
+    fn forces_non_stream(&self) -> bool {
+        self.force_non_stream_with_tools == Some(true) && self.has_tools()
+    }
+
+    /// Returns the advisory warnings about the request, i.e. valid combinations of fields that some backends do not support.
+    ///
+    /// Streaming with tools is not reported if `force_non_stream_with_tools` is enabled, since streaming is then turned off. Stop sequences containing characters that `grammar` never generates are reported as well, since they can never match, and so is the [DUMMY_EMBEDDING_MODEL] placeholder, since it is serialized as is.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = match self.force_non_stream_with_tools {
+            Some(true) => vec![],
+            _ => stream_with_tools_warning(self.stream, self.tools.as_deref())
+                .into_iter()
+                .collect(),
+        };
+        warnings.extend(stop_with_grammar_warnings(
+            self.stop.as_deref(),
+            self.grammar.as_deref(),
+        ));
+        if self.embedding_model == DUMMY_EMBEDDING_MODEL {
+            warnings.push(format!(
+                "`embedding_model` is still the `{}` placeholder, which servers may not recognize.",
+                DUMMY_EMBEDDING_MODEL
+            ));
+        }
+
+        warnings
+    }
+
+    /// Returns how the points are searched, i.e. `search_mode` if set, or else [SearchMode::Dense].
+    pub fn resolved_search_mode(&self) -> SearchMode {
+        self.search_mode.unwrap_or_default()
+    }
+
+    /// Returns the vector database to retrieve from, i.e. `vector_db` if set, or else the Qdrant collection given by `qdrant_url` and `qdrant_collection_name`.
+    pub fn vector_db_config(&self) -> VectorDbConfig {
+        match &self.vector_db {
+            Some(config) => config.clone(),
+            None => VectorDbConfig::Qdrant {
+                url: self.qdrant_url.clone(),
+                collection_name: self.qdrant_collection_name.clone(),
+            },
+        }
+    }
+
+    /// Returns the max number of retrieved results for the collection, i.e. its entry in `limit_per_collection` if any, or else `limit`.
+    pub fn limit_for(&self, collection_name: &str) -> u64 {
+        self.limit_per_collection
+            .as_ref()
+            .and_then(|limits| limits.get(collection_name))
+            .copied()
+            .unwrap_or(self.limit)
+    }
+
+    /// Returns the max number of retrieved points that can end up in the context, e.g. for budgeting the prompt.
+    ///
+    /// Each collection contributes up to its [limit](RagChatCompletionsRequest::limit_for), reranking keeps at most `top_n` of the retrieved points, and no points end up in the context if `inject_context` is disabled. `min_distinct_sources` and `min_context_score` never add points, so they do not change the bound.
+    pub fn effective_limit(&self) -> usize {
+        if !self.injects_context() {
+            return 0;
+        }
+
+        let retrieved = self
+            .collection_names()
+            .into_iter()
+            .map(|name| self.limit_for(name))
+            .fold(0_u64, u64::saturating_add);
+        let retrieved = usize::try_from(retrieved).unwrap_or(usize::MAX);
+
+        match &self.rerank {
+            Some(rerank) => retrieved.min(rerank.top_n),
+            None => retrieved,
+        }
+    }
+
+    fn validate_collections(&self) -> Result<(), EndpointError> {
+        if self
+            .qdrant_collection_names
+            .as_ref()
+            .is_some_and(|names| names.is_empty())
+        {
+            return Err(EndpointError::InvalidArgument(
+                "The collection names must not be empty.".to_string(),
+            ));
+        }
+
+        if let Some(limits) = &self.limit_per_collection {
+            let names = self.collection_names();
+            for (name, limit) in limits {
+                if !names.contains(&name.as_str()) {
+                    return Err(EndpointError::InvalidArgument(format!(
+                        "The limit is set for the collection `{}`, which is not retrieved from.",
+                        name
+                    )));
+                }
+                if *limit == 0 {
+                    return Err(EndpointError::InvalidArgument(format!(
+                        "The limit of the collection `{}` must be positive.",
+                        name
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Splits a multi-collection request into one request per collection, for clients that query each collection separately.
+    ///
+    /// Each request is a copy of this one with `qdrant_collection_name` set to a single collection, `qdrant_collection_names` and `limit_per_collection` unset, and `embedding_model` and `limit` set to the [model](RagChatCompletionsRequest::embedding_model_for) and the [limit](RagChatCompletionsRequest::limit_for) of the collection. A single-collection request is returned as is.
+    pub fn split_by_collection(self) -> Vec<RagChatCompletionsRequest> {
+        match &self.qdrant_collection_names {
+            Some(names) => names
+                .iter()
+                .map(|name| RagChatCompletionsRequest {
+                    embedding_model: self.embedding_model_for(name).to_string(),
+                    qdrant_collection_name: name.clone(),
+                    qdrant_collection_names: None,
+                    limit: self.limit_for(name),
+                    limit_per_collection: None,
+                    ..self.clone()
+                })
+                .collect(),
+            None => vec![self],
+        }
+    }
+
+    /// Reduces `max_tokens` so that the prompt and the generation fit into the context window of the model.
+    ///
+    /// The prompt tokens are estimated by counting the tokens of the text of all messages and of the sources of the retrieved points, unless the context is not [injected](RagChatCompletionsRequest::injects_context). Call this before the retrieved context is merged into the messages, so that it is not counted twice. `max_tokens` is only ever reduced, and never below [MIN_AUTOFIT_MAX_TOKENS]. If `max_tokens` is not set, it is set to the remaining budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_ctx` - The size of the context window of the model, in tokens.
+    ///
+    /// * `retrieved` - The retrieved context, one [RetrieveObject] per collection.
+    ///
+    /// * `counter` - Counts the tokens of a text.
+    pub fn autofit_max_tokens(
+        &mut self,
+        model_ctx: usize,
+        retrieved: &[RetrieveObject],
+        counter: impl Fn(&str) -> usize,
+    ) {
+        let context_tokens: usize = match self.injects_context() {
+            true => retrieved
+                .iter()
+                .flat_map(|retrieve_object| retrieve_object.points.iter().flatten())
+                .map(|point| counter(&point.source))
+                .sum(),
+            false => 0,
+        };
+        let message_tokens: usize = self
+            .messages
+            .iter()
+            .map(|message| match message {
+                ChatCompletionRequestMessage::System(message) => counter(message.content()),
+                ChatCompletionRequestMessage::User(message) => match message.content() {
+                    ChatCompletionUserMessageContent::Text(text) => counter(text),
+                    ChatCompletionUserMessageContent::Parts(parts) => parts
+                        .iter()
+                        .map(|part| match part {
+                            ContentPart::Text(part) => counter(part.text()),
+                            _ => 0,
+                        })
+                        .sum(),
+                },
+                ChatCompletionRequestMessage::Assistant(message) => {
+                    message.content().map_or(0, |content| counter(content))
+                }
+                ChatCompletionRequestMessage::Tool(message) => counter(message.content()),
+            })
+            .sum();
+        let prompt_tokens = message_tokens + context_tokens;
+
+        let budget = (model_ctx.saturating_sub(prompt_tokens) as u64).max(MIN_AUTOFIT_MAX_TOKENS);
+        self.max_tokens = Some(match self.max_tokens {
+            Some(max_tokens) => max_tokens.min(budget),
+            None => budget,
+        });
+    }
+
+    /// Reports which optional features the request makes use of.
+    pub fn features(&self) -> RequestFeatures {
+        RequestFeatures {
+            uses_tools: self.has_tools(),
+            uses_streaming: self.stream == Some(true),
+            uses_json_mode: self
+                .response_format
+                .as_ref()
+                .is_some_and(ChatResponseFormat::is_json),
+            uses_filter: self.filter.is_some() || self.keyword_filter.is_some(),
+            multi_collection: self.collection_names().len() > 1,
+        }
+    }
+}
+
+/// Converts the request into a [ChatCompletionRequest] by moving its fields, the owning counterpart of [RagChatCompletionsRequest::as_chat_completions_request].
+impl From<RagChatCompletionsRequest> for ChatCompletionRequest {
+    fn from(request: RagChatCompletionsRequest) -> Self {
+        let (stream, stream_options) = match request.forces_non_stream() {
+            true => (Some(false), None),
+            false => (request.stream, request.stream_options),
+        };
+        ChatCompletionRequest {
+            model: request.chat_model,
+            messages: request.messages,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            n_choice: request.n_choice,
+            stream,
+            stream_options,
+            stop: request.stop,
+            max_tokens: request.max_tokens,
+            presence_penalty: request.presence_penalty,
+            frequency_penalty: request.frequency_penalty,
+            logit_bias: request.logit_bias,
+            user: request.user,
+            functions: None,
+            function_call: None,
+            response_format: request.response_format,
+            tool_choice: request.tool_choice,
+            tools: request.tools,
+            context_window: request.context_window,
+            stop_condition: request.stop_condition,
+            modalities: request.modalities,
+            audio: request.audio,
+            idempotency_key: request.idempotency_key,
+            stop_on_tool_call: request.stop_on_tool_call,
+            request_id: request.request_id,
+            seed: request.seed,
+            grammar: request.grammar,
+            prompt_template: request.prompt_template,
+            min_p: request.min_p,
+            top_k: request.top_k,
+            repeat_penalty: request.repeat_penalty,
+            repeat_last_n: request.repeat_last_n,
+            logprobs: request.logprobs,
+            top_logprobs: request.top_logprobs,
+            parallel_tool_calls: request.parallel_tool_calls,
+        }
+    }
+}
+
+/// Default values applied to a [RagChatCompletionsRequest] by [RagChatCompletionsRequest::apply_defaults]. A field set to `None` leaves the request unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RagDefaults {
+    /// The default URL of the Qdrant server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qdrant_url: Option<String>,
+    /// The default name of the collection in Qdrant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qdrant_collection_name: Option<String>,
+    /// The default chat model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_model: Option<String>,
+    /// The default embedding model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding_model: Option<String>,
+}
+
+#[test]
+fn test_rag_apply_defaults() {
+    let defaults = RagDefaults {
+        qdrant_url: Some("http://qdrant:6333".to_string()),
+        qdrant_collection_name: Some("docs".to_string()),
+        chat_model: Some("llama-3-8b".to_string()),
+        embedding_model: Some("nomic-embed-text-v1.5".to_string()),
+    };
+
+    // empty and placeholder fields are replaced
+    let mut request = RagChatCompletionsRequest::new(vec![], "", "", 5);
+    request.apply_defaults(&defaults);
+    assert_eq!(request.qdrant_url, "http://qdrant:6333");
+    assert_eq!(request.qdrant_collection_name, "docs");
+    assert_eq!(request.chat_model.as_deref(), Some("llama-3-8b"));
+    assert_eq!(request.embedding_model, "nomic-embed-text-v1.5");
+
+    let mut request = RagChatCompletionsRequest::new(vec![], "", "", 5);
+    request.chat_model = None;
+    request.apply_defaults(&defaults);
+    assert_eq!(request.chat_model.as_deref(), Some("llama-3-8b"));
+
+    // explicit values are preserved
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 5);
+    request.chat_model = Some("qwen2-7b".to_string());
+    request.embedding_model = "all-minilm".to_string();
+    request.apply_defaults(&defaults);
+    assert_eq!(request.qdrant_url, "http://localhost:6333");
+    assert_eq!(request.qdrant_collection_name, "default");
+    assert_eq!(request.chat_model.as_deref(), Some("qwen2-7b"));
+    assert_eq!(request.embedding_model, "all-minilm");
+
+    // no defaults, no changes
+    let mut request = RagChatCompletionsRequest::new(vec![], "", "", 5);
+    request.apply_defaults(&RagDefaults::default());
+    assert_eq!(request, RagChatCompletionsRequest::new(vec![], "", "", 5));
+}
+
+/// Describes which optional features a [RagChatCompletionsRequest] makes use of.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestFeatures {
+    /// Whether a non-empty list of tools is provided.
+    pub uses_tools: bool,
+    /// Whether the response is streamed.
+    pub uses_streaming: bool,
+    /// Whether the model is asked to output JSON, with or without a schema.
+    pub uses_json_mode: bool,
+    /// Whether the retrieved points are filtered by a payload filter or a keyword filter.
+    pub uses_filter: bool,
+    /// Whether the context is retrieved from more than one collection.
+    pub multi_collection: bool,
+}
+
+#[test]
+fn test_rag_request_features() {
+    {
+        let messages = vec![ChatCompletionRequestMessage::new_system_message(
+            "You are a helpful assistant.",
+            None,
+        )];
+        let request =
+            RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+                .build();
+        assert_eq!(request.features(), RequestFeatures::default());
+    }
+
+    {
+        let messages = vec![ChatCompletionRequestMessage::new_system_message(
+            "You are a helpful assistant.",
+            None,
+        )];
+        let mut request =
+            RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+                .with_stream(true)
+                .build();
+        request.response_format = Some(ChatResponseFormat {
+            ty: "json_object".to_string(),
+            schema_hint: None,
+            json_schema: None,
+        });
+        request.tools = Some(vec![Tool {
+            ty: "function".to_string(),
+            function: crate::chat::ToolFunction {
+                name: "get_weather".to_string(),
+                description: None,
+                parameters: None,
+            },
+        }]);
+        assert_eq!(
+            request.features(),
+            RequestFeatures {
+                uses_tools: true,
+                uses_streaming: true,
+                uses_json_mode: true,
+                uses_filter: false,
+                multi_collection: false,
+            }
+        );
+
+        request.qdrant_collection_names = Some(vec!["docs".to_string(), "faq".to_string()]);
+        assert!(request.features().multi_collection);
+
+        request.filter = Some(serde_json::json!({
+            "must": [{ "key": "doc_type", "match": { "value": "policy" } }]
+        }));
+        assert!(request.features().uses_filter);
+
+        request.filter = None;
+        request.keyword_filter = Some(KeywordFilter {
+            must_contain: vec!["LlamaEdge".to_string()],
+            ..Default::default()
+        });
+        assert!(request.features().uses_filter);
+    }
+}
+
+#[test]
+fn test_rag_autofit_max_tokens() {
+    // one token per word
+    let counter = |text: &str| text.split_whitespace().count();
+    let messages = vec![
+        ChatCompletionRequestMessage::new_system_message(
+            "Context: LlamaEdge runs LLMs locally and securely",
+            None,
+        ),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+            None,
+        ),
+    ];
+
+    // 10 prompt tokens, so 90 tokens are left
+    let mut request =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 3);
+    assert_eq!(request.max_tokens, Some(1024));
+    request.autofit_max_tokens(100, &[], counter);
+    assert_eq!(request.max_tokens, Some(90));
+
+    // a smaller max_tokens is kept
+    request.max_tokens = Some(50);
+    request.autofit_max_tokens(100, &[], counter);
+    assert_eq!(request.max_tokens, Some(50));
+
+    // the context leaves little room
+    request.max_tokens = Some(1024);
+    request.autofit_max_tokens(20, &[], counter);
+    assert_eq!(request.max_tokens, Some(MIN_AUTOFIT_MAX_TOKENS));
+
+    // the prompt alone exceeds the context
+    request.max_tokens = None;
+    request.autofit_max_tokens(5, &[], counter);
+    assert_eq!(request.max_tokens, Some(MIN_AUTOFIT_MAX_TOKENS));
+
+    // the retrieved context counts towards the prompt: 10 + 5 + 3 prompt tokens
+    let retrieved = vec![RetrieveObject {
+        points: Some(vec![
+            RagScoredPoint {
+                source: "LlamaEdge is a lightweight runtime".to_string(),
+                score: 0.75,
+                ..Default::default()
+            },
+            RagScoredPoint {
+                source: "written in Rust".to_string(),
+                score: 0.5,
+                ..Default::default()
+            },
+        ]),
+        limit: 3,
+        score_threshold: 0.0,
+        filter: None,
+    }];
+    request.max_tokens = None;
+    request.autofit_max_tokens(100, &retrieved, counter);
+    assert_eq!(request.max_tokens, Some(82));
+
+    // unless it is not injected
+    request.max_tokens = None;
+    request.inject_context = Some(false);
+    request.autofit_max_tokens(100, &retrieved, counter);
+    assert_eq!(request.max_tokens, Some(90));
+}
+
+#[test]
+fn test_rag_collection_embedding_models() {
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 3);
+    request.embedding_model = "all-minilm".to_string();
+    request.qdrant_collection_names = Some(vec!["docs".to_string(), "faq".to_string()]);
+    assert!(request.validate().is_ok());
+    assert_eq!(request.embedding_model_for("docs"), "all-minilm");
+
+    let mut models = HashMap::new();
+    models.insert("docs".to_string(), "nomic-embed-text-v1.5".to_string());
+    request.collection_embedding_models = Some(models.clone());
+    assert_eq!(
+        request.validate(),
+        Err(EndpointError::InvalidArgument(
+            "No embedding model is mapped to the collection `faq`.".to_string()
+        ))
+    );
+
+    models.insert("faq".to_string(), "bge-small-en".to_string());
+    request.collection_embedding_models = Some(models);
+    assert!(request.validate().is_ok());
+    assert_eq!(request.embedding_model_for("docs"), "nomic-embed-text-v1.5");
+    assert_eq!(request.embedding_model_for("faq"), "bge-small-en");
+    assert_eq!(request.embedding_model_for("blog"), "all-minilm");
+    let requests = request.clone().split_by_collection();
+    assert_eq!(requests[0].embedding_model, "nomic-embed-text-v1.5");
+    assert_eq!(requests[1].embedding_model, "bge-small-en");
+
+    // a single collection must be mapped as well
+    let mut models = HashMap::new();
+    models.insert("docs".to_string(), "nomic-embed-text-v1.5".to_string());
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_collection_embedding_models(models)
+            .build();
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_effective_limit() {
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 5);
+    assert_eq!(request.effective_limit(), 5);
+
+    // each collection contributes its own limit
+    let mut limits = HashMap::new();
+    limits.insert("papers".to_string(), 2);
+    let request = RagChatCompletionRequestBuilder::new(
+        vec![],
+        "http://localhost:6333",
+        vec!["docs", "papers", "faq"],
+        5,
+    )
+    .with_limit_per_collection(limits)
+    .build();
+    assert_eq!(request.effective_limit(), 12);
+
+    // reranking keeps `top_n` points at most
+    let rerank = RerankConfig {
+        model: "bge-reranker-v2-m3".to_string(),
+        top_n: 4,
+    };
+    let request = RagChatCompletionRequestBuilder::new(
+        vec![],
+        "http://localhost:6333",
+        vec!["docs", "papers", "faq"],
+        5,
+    )
+    .with_rerank(rerank.clone())
+    .build();
+    assert_eq!(request.effective_limit(), 4);
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_rerank(rerank)
+            .build();
+    assert_eq!(request.effective_limit(), 3);
+
+    // the lower bounds do not change the max
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 5)
+            .with_min_distinct_sources(3)
+            .build();
+    assert_eq!(request.effective_limit(), 5);
+
+    // no context is injected
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 5);
+    request.inject_context = Some(false);
+    assert_eq!(request.effective_limit(), 0);
+}
+
+#[test]
+fn test_rag_split_by_collection() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+        None,
+    )];
+    let mut request =
+        RagChatCompletionsRequest::new(messages, "http://localhost:6333", "default", 3);
+    assert_eq!(request.collection_names(), vec!["default"]);
+
+    // a single-collection request is kept as is
+    let requests = request.clone().split_by_collection();
+    assert_eq!(requests, vec![request.clone()]);
+
+    let names = vec!["docs".to_string(), "faq".to_string(), "blog".to_string()];
+    request.qdrant_collection_names = Some(names.clone());
+    assert_eq!(request.collection_names(), vec!["docs", "faq", "blog"]);
+
+    let requests = request.clone().split_by_collection();
+    assert_eq!(requests.len(), 3);
+    for (split, name) in requests.iter().zip(names.iter()) {
+        assert_eq!(&split.qdrant_collection_name, name);
+        assert!(split.qdrant_collection_names.is_none());
+        assert_eq!(split.collection_names(), vec![name.as_str()]);
+        assert_eq!(split.limit, request.limit);
+        assert_eq!(split.messages, request.messages);
+    }
+}
+
+#[test]
+fn test_rag_serialize_multi_collection() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+        None,
+    )];
+
+    // the single-string form
+    let mut request =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 3);
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""qdrant_collection_name":"default""#));
+    assert!(!json.contains("qdrant_collection_names"));
+    assert!(!json.contains("limit_per_collection"));
+    let round_trip: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&round_trip).unwrap(), json);
+    assert_eq!(round_trip.collection_names(), vec!["default"]);
+
+    // the array form
+    let mut limits = HashMap::new();
+    limits.insert("faq".to_string(), 1);
+    let mut request = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        vec!["docs", "faq"],
+        3,
+    )
+    .with_limit_per_collection(limits)
+    .build();
+    request.embedding_model = "model".to_string();
+    assert!(request.validate().is_ok());
+    assert_eq!(request.qdrant_collection_name, "docs");
+    assert_eq!(request.collection_names(), vec!["docs", "faq"]);
+    assert_eq!(request.limit_for("docs"), 3);
+    assert_eq!(request.limit_for("faq"), 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""qdrant_collection_name":"docs""#));
+    assert!(json.contains(r#""qdrant_collection_names":["docs","faq"]"#));
+    assert!(json.contains(r#""limit_per_collection":{"faq":1}"#));
+    let round_trip: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&round_trip).unwrap(), json);
+    assert_eq!(
+        round_trip.limit_per_collection,
+        request.limit_per_collection
+    );
+
+    let limits: Vec<u64> = request
+        .split_by_collection()
+        .iter()
+        .map(|split| split.limit)
+        .collect();
+    assert_eq!(limits, vec![3, 1]);
+
+    // the chat completions request does not carry the collections
+    let request = RagChatCompletionsRequest::from_chat_completions_request(
+        round_trip.as_chat_completions_request(),
+        "http://localhost:6333",
+        vec!["docs".to_string(), "faq".to_string()],
+        3,
+    );
+    assert_eq!(request.collection_names(), vec!["docs", "faq"]);
+
+    // invalid collections
+    let mut request =
+        RagChatCompletionsRequest::new(messages, "http://localhost:6333", "default", 3);
+    request.limit_per_collection = Some(HashMap::from([("faq".to_string(), 1)]));
+    assert!(request.validate().is_err());
+    request.limit_per_collection = Some(HashMap::from([("default".to_string(), 0)]));
+    assert!(request.validate().is_err());
+    request.limit_per_collection = None;
+    request.qdrant_collection_names = Some(vec![]);
+    assert!(request.validate().is_err());
+}
+
+/// The Qdrant collections to retrieve from: a single collection, or multiple collections whose results are merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QdrantCollections {
+    /// The name of a single collection.
+    Single(String),
+    /// The names of multiple collections.
+    Multiple(Vec<String>),
+}
+impl QdrantCollections {
+    /// Returns the values of `qdrant_collection_name` and `qdrant_collection_names`. With multiple collections, `qdrant_collection_name` is set to the first one, for servers not supporting multiple collections.
+    fn into_fields(self) -> (String, Option<Vec<String>>) {
+        match self {
+            QdrantCollections::Single(name) => (name, None),
+            QdrantCollections::Multiple(names) => {
+                (names.first().cloned().unwrap_or_default(), Some(names))
+            }
+        }
+    }
+}
+impl From<&str> for QdrantCollections {
+    fn from(name: &str) -> Self {
+        QdrantCollections::Single(name.to_string())
+    }
+}
+impl From<&String> for QdrantCollections {
+    fn from(name: &String) -> Self {
+        QdrantCollections::Single(name.to_string())
+    }
+}
+impl From<String> for QdrantCollections {
+    fn from(name: String) -> Self {
+        QdrantCollections::Single(name)
+    }
+}
+impl From<Vec<&str>> for QdrantCollections {
+    fn from(names: Vec<&str>) -> Self {
+        QdrantCollections::Multiple(names.iter().map(|name| name.to_string()).collect())
+    }
+}
+impl From<Vec<String>> for QdrantCollections {
+    fn from(names: Vec<String>) -> Self {
+        QdrantCollections::Multiple(names)
+    }
+}
+
+/// Request builder for creating a new RAG chat completion request.
+pub struct RagChatCompletionRequestBuilder {
+    req: RagChatCompletionsRequest,
+    observer: Option<Arc<dyn RequestObserver>>,
+}
+impl RagChatCompletionRequestBuilder {
+    /// Creates a new builder with the given model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - ID of the model to use.
+    ///
+    /// * `messages` - A list of messages comprising the conversation so far.
+    ///
+    /// * `sampling` - The sampling method to use.
+    pub fn new(
+        messages: Vec<ChatCompletionRequestMessage>,
+        qdrant_url: impl Into<String>,
+        qdrant_collections: impl Into<QdrantCollections>,
+        limit: u64,
+    ) -> Self {
+        let (qdrant_collection_name, qdrant_collection_names) =
+            qdrant_collections.into().into_fields();
+        Self {
+            req: RagChatCompletionsRequest {
+                chat_model: Some(DUMMY_CHAT_MODEL.to_string()),
+                messages,
+                embedding_model: DUMMY_EMBEDDING_MODEL.to_string(),
+                encoding_format: Some("float".to_string()),
+                qdrant_url: qdrant_url.into(),
+                qdrant_collection_name,
+                limit,
+                temperature: Some(1.0),
+                top_p: Some(1.0),
+                n_choice: Some(1),
+                stream: Some(false),
+                stream_options: None,
+                stop: None,
+                max_tokens: Some(1024),
+                presence_penalty: Some(0.0),
+                frequency_penalty: Some(0.0),
+                logit_bias: None,
+                user: None,
+                response_format: None,
+                tool_choice: None,
+                tools: None,
+                context_window: Some(1),
+                stop_condition: None,
+                inject_context: None,
+                score_calibration: None,
+                query_preprocessing: None,
+                modalities: None,
+                audio: None,
+                explain: None,
+                idempotency_key: None,
+                keyword_filter: None,
+                qdrant_collection_names,
+                min_context_score: None,
+                refusal_message: None,
+                stop_on_tool_call: None,
+                collection_embedding_models: None,
+                answer_language: None,
+                request_id: None,
+                include_assistant_context: None,
+                min_distinct_sources: None,
+                limit_per_collection: None,
+                vector_db: None,
+                score_threshold: None,
+                filter: None,
+                force_non_stream_with_tools: None,
+                seed: None,
+                grammar: None,
+                search_mode: None,
+                rerank: None,
+                prompt_template: None,
+                min_p: None,
+                top_k: None,
+                repeat_penalty: None,
+                repeat_last_n: None,
+                logprobs: None,
+                top_logprobs: None,
+                parallel_tool_calls: None,
+                dimensions: None,
+            },
+            observer: None,
+        }
+    }
+
+    /// Creates a new builder whose conversation is a system prompt followed by a single user question.
+    ///
+    /// # Arguments
+    ///
+    /// * `system` - The content of the system message.
+    ///
+    /// * `user` - The content of the user message.
+    ///
+    /// * `qdrant_url` - The URL of the Qdrant server.
+    ///
+    /// * `qdrant_collections` - The name of the collection in Qdrant, or the names of multiple collections.
+    ///
+    /// * `limit` - Max number of retrieved results.
+    pub fn new_simple(
+        system: impl Into<String>,
+        user: impl Into<String>,
+        qdrant_url: impl Into<String>,
+        qdrant_collections: impl Into<QdrantCollections>,
+        limit: u64,
+    ) -> Self {
+        let messages = vec![
+            ChatCompletionRequestMessage::new_system_message(system, None),
+            ChatCompletionRequestMessage::new_user_message(
+                ChatCompletionUserMessageContent::Text(user.into()),
+                None,
+            ),
+        ];
+
+        Self::new(messages, qdrant_url, qdrant_collections, limit)
+    }
+
+    pub fn with_sampling(mut self, sampling: ChatCompletionRequestSampling) -> Self {
+        let (temperature, top_p) = match sampling {
+            ChatCompletionRequestSampling::Temperature(t) => (t, 1.0),
+            ChatCompletionRequestSampling::TopP(p) => (1.0, p),
+        };
+        self.req.temperature = Some(temperature);
+        self.req.top_p = Some(top_p);
+        self
+    }
+
+    /// Sets the number of chat completion choices to generate for each input message.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - How many chat completion choices to generate for each input message. If `n` is less than 1, then sets to `1`.
+    pub fn with_n_choices(mut self, n: u64) -> Self {
+        let n_choice = if n < 1 { 1 } else { n };
+        self.req.n_choice = Some(n_choice);
+        self
+    }
+
+    pub fn with_stream(mut self, flag: bool) -> Self {
+        self.req.stream = Some(flag);
+        self
+    }
+
+    /// Sets the options for streaming response, and enables streaming, which the options require.
+    pub fn with_stream_options(mut self, stream_options: StreamOptions) -> Self {
+        self.req.stream = Some(true);
+        self.req.stream_options = Some(stream_options);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.req.stop = Some(stop);
+        self
+    }
+
+    /// Sets the maximum number of tokens to generate in the chat completion. The total length of input tokens and generated tokens is limited by the model's context length.
+    ///
+    /// # Argument
+    ///
+    /// * `max_tokens` - The maximum number of tokens to generate in the chat completion. If `max_tokens` is less than 1, then sets to `16`.
+    pub fn with_max_tokens(mut self, max_tokens: u64) -> Self {
+        let max_tokens = if max_tokens < 1 { 16 } else { max_tokens };
+        self.req.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sets the presence penalty. Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far, increasing the model's likelihood to talk about new topics.
+    pub fn with_presence_penalty(mut self, penalty: f64) -> Self {
+        self.req.presence_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the frequency penalty. Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far, decreasing the model's likelihood to repeat the same line verbatim.
+    pub fn with_frequency_penalty(mut self, penalty: f64) -> Self {
+        self.req.frequency_penalty = Some(penalty);
+        self
+    }
+
+    pub fn with_logits_bias(mut self, map: HashMap<String, f64>) -> Self {
+        self.req.logit_bias = Some(map);
+        self
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.req.user = Some(user.into());
+        self
+    }
+
+    /// Sets the number of user messages to use for context retrieval. Values below 1 are clamped to 1, i.e. the latest user message.
+    pub fn with_context_window(mut self, context_window: u64) -> Self {
+        let context_window = if context_window < 1 {
+            1
+        } else {
+            context_window
+        };
+        self.req.context_window = Some(context_window);
+        self
+    }
+
+    /// Sets the condition, in addition to the stop sequences, on which to stop generation.
+    pub fn with_stop_condition(mut self, stop_condition: StopCondition) -> Self {
+        self.req.stop_condition = Some(stop_condition);
+        self
+    }
+
+    /// Sets the keywords the retrieved points must or must not contain.
+    pub fn with_keyword_filter(mut self, filter: KeywordFilter) -> Self {
+        self.req.keyword_filter = Some(filter);
+        self
+    }
+
+    /// Sets the minimum score of the retrieved points.
+    pub fn with_score_threshold(mut self, score_threshold: f32) -> Self {
+        self.req.score_threshold = Some(score_threshold);
+        self
+    }
+
+    /// Sets whether to turn off streaming if tools are provided.
+    pub fn with_force_non_stream_with_tools(mut self, flag: bool) -> Self {
+        self.req.force_non_stream_with_tools = Some(flag);
+        self
+    }
+
+    /// Sets the payload filter passed to the vector search.
+    pub fn with_filter(mut self, filter: Value) -> Self {
+        self.req.filter = Some(filter);
+        self
+    }
+
+    /// Sets the number of dimensions the query embedding is truncated to, which must match the dimension of the collection.
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.req.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Sets the minimum score of the top retrieved point, below which the request is refused with the refusal message.
+    pub fn with_min_context_score(mut self, score: f32) -> Self {
+        self.req.min_context_score = Some(score);
+        self
+    }
+
+    /// Sets the answer returned when the retrieved context is not relevant enough.
+    pub fn with_refusal_message(mut self, message: impl Into<String>) -> Self {
+        self.req.refusal_message = Some(message.into());
+        self
+    }
+
+    /// Sets the vector database to retrieve from.
+    pub fn with_vector_db(mut self, config: VectorDbConfig) -> Self {
+        self.req.vector_db = Some(config);
+        self
+    }
+
+    /// Sets the max number of retrieved results per collection, by collection name.
+    pub fn with_limit_per_collection(mut self, limits: HashMap<String, u64>) -> Self {
+        self.req.limit_per_collection = Some(limits);
+        self
+    }
+
+    /// Sets the minimum number of distinct documents the retrieved points must come from.
+    pub fn with_min_distinct_sources(mut self, min_distinct_sources: usize) -> Self {
+        self.req.min_distinct_sources = Some(min_distinct_sources);
+        self
+    }
+
+    /// Sets the embedding model each collection was built with, by collection name.
+    pub fn with_collection_embedding_models(mut self, models: HashMap<String, String>) -> Self {
+        self.req.collection_embedding_models = Some(models);
+        self
+    }
+
+    /// Sets the language to answer in, as a BCP-47 tag.
+    pub fn with_answer_language(mut self, language: impl Into<String>) -> Self {
+        self.req.answer_language = Some(language.into());
+        self
+    }
+
+    /// Sets whether to prepend the previous assistant reply to the retrieval query.
+    pub fn with_include_assistant_context(mut self, flag: bool) -> Self {
+        self.req.include_assistant_context = Some(flag);
+        self
+    }
+
+    /// Sets whether to return the breakdown of the score of each retrieved point.
+    pub fn with_explain(mut self, flag: bool) -> Self {
+        self.req.explain = Some(flag);
+        self
+    }
+
+    /// Sets whether to inject the retrieved context into the prompt.
+    pub fn with_inject_context(mut self, flag: bool) -> Self {
+        self.req.inject_context = Some(flag);
+        self
+    }
+
+    /// Sets the output types that the model generates.
+    pub fn with_modalities(mut self, modalities: Vec<Modality>) -> Self {
+        self.req.modalities = Some(modalities);
+        self
+    }
+
+    /// Sets the parameters for the audio output. Requires `audio` in the modalities.
+    pub fn with_audio(mut self, audio: AudioOutputConfig) -> Self {
+        self.req.audio = Some(audio);
+        self
+    }
+
+    /// Sets the key that allows the request to be safely retried. Use [generate_idempotency_key](crate::chat::generate_idempotency_key) to create a new key.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.req.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Sets whether to stop generating as soon as a tool call is emitted.
+    pub fn with_stop_on_tool_call(mut self, flag: bool) -> Self {
+        self.req.stop_on_tool_call = Some(flag);
+        self
+    }
+
+    /// Sets the id correlating the request with a cancellation request.
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.req.request_id = Some(request_id.into());
+        self
+    }
+
+    /// Sets the seed for deterministic sampling.
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.req.seed = Some(seed);
+        self
+    }
+
+    /// Sets the min-p sampling threshold.
+    pub fn with_min_p(mut self, min_p: f64) -> Self {
+        self.req.min_p = Some(min_p);
+        self
+    }
+
+    /// Sets the number of most likely tokens to sample from.
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.req.top_k = Some(top_k);
+        self
+    }
+
+    /// Sets the penalty of the tokens repeated within the last `repeat_last_n` tokens.
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f64) -> Self {
+        self.req.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    /// Sets the number of most recent tokens considered by the repeat penalty.
+    pub fn with_repeat_last_n(mut self, repeat_last_n: u32) -> Self {
+        self.req.repeat_last_n = Some(repeat_last_n);
+        self
+    }
+
+    /// Sets whether to return the log probabilities of the output tokens.
+    pub fn with_logprobs(mut self, logprobs: bool) -> Self {
+        self.req.logprobs = Some(logprobs);
+        self
+    }
+
+    /// Sets the number of most likely tokens to return at each token position. Requires `logprobs` to be true.
+    pub fn with_top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.req.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    /// Sets whether the model may call several tools in a single turn.
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.req.parallel_tool_calls = Some(parallel_tool_calls);
+        self
+    }
+
+    /// Sets the `json_schema` response format, constraining the output to the JSON schema.
+    pub fn with_json_schema(mut self, json_schema: JsonSchema) -> Self {
+        self.req.response_format = Some(ChatResponseFormat::json_schema(json_schema));
+        self
+    }
+
+    /// Sets the GBNF grammar constraining the generated text.
+    pub fn with_grammar(mut self, grammar: impl Into<String>) -> Self {
+        self.req.grammar = Some(grammar.into());
+        self
+    }
+
+    /// Sets the name of the prompt template to apply to the messages.
+    pub fn with_prompt_template(mut self, prompt_template: impl Into<String>) -> Self {
+        self.req.prompt_template = Some(prompt_template.into());
+        self
+    }
+
+    /// Sets the observer to notify of the lifecycle events of the request.
+    pub fn with_observer(mut self, observer: Arc<dyn RequestObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Sets the calibration that maps the raw similarity scores to confidence values.
+    pub fn with_score_calibration(mut self, calibration: ScoreCalibration) -> Self {
+        self.req.score_calibration = Some(calibration);
+        self
+    }
+
+    /// Sets how the points are searched in the vector database.
+    pub fn with_search_mode(mut self, search_mode: SearchMode) -> Self {
+        self.req.search_mode = Some(search_mode);
+        self
+    }
+
+    /// Sets the reranking stage run after the retrieval.
+    pub fn with_rerank(mut self, rerank: RerankConfig) -> Self {
+        self.req.rerank = Some(rerank);
+        self
+    }
+
+    /// Sets the normalization steps applied to the query text before it is embedded for retrieval.
+    pub fn with_query_preprocessing(mut self, steps: Vec<QueryStep>) -> Self {
+        self.req.query_preprocessing = Some(steps);
+        self
+    }
+
+    /// Returns all validation issues of the request in its current state, without building it, e.g. to preview them in a form-driven UI.
+    ///
+    /// In addition to the checks of [RagChatCompletionsRequest::validate], the conversation must not be empty, the sampling parameters and penalties must be in range, and there must be no more than 4 stop sequences.
+    pub fn validation_issues(&self) -> Vec<RagBuildError> {
+        let req = &self.req;
+        let mut issues = vec![];
+
+        if req.messages.is_empty() {
+            issues.push(RagBuildError::EmptyMessages);
+        }
+
+        issues.extend(self.range_errors());
+
+        if let Some(stop) = &req.stop {
+            if stop.len() > 4 {
+                issues.push(RagBuildError::TooManyStopSequences(stop.len()));
+            }
+        }
+
+        issues.extend(
+            req.checks()
+                .into_iter()
+                .filter_map(Result::err)
+                .map(RagBuildError::Invalid),
+        );
+
+        issues
+    }
+
+    /// Returns an error for each sampling parameter or penalty out of its range.
+    fn range_errors(&self) -> Vec<RagBuildError> {
+        let req = &self.req;
+        let out_of_range =
+            |value: Option<f64>, min: f64, max: f64| value.filter(|v| !(min..=max).contains(v));
+
+        let mut errors = vec![];
+        if let Some(temperature) = out_of_range(req.temperature, 0.0, 2.0) {
+            errors.push(RagBuildError::TemperatureOutOfRange(temperature));
+        }
+        if let Some(top_p) = out_of_range(req.top_p, 0.0, 1.0) {
+            errors.push(RagBuildError::TopPOutOfRange(top_p));
+        }
+        if let Some(penalty) = out_of_range(req.presence_penalty, -2.0, 2.0) {
+            errors.push(RagBuildError::PresencePenaltyOutOfRange(penalty));
+        }
+        if let Some(penalty) = out_of_range(req.frequency_penalty, -2.0, 2.0) {
+            errors.push(RagBuildError::FrequencyPenaltyOutOfRange(penalty));
+        }
+
+        errors
+    }
+
+    /// Builds the request, checking that it has none of the [validation issues](RagChatCompletionRequestBuilder::validation_issues).
+    ///
+    /// Returns the first violated constraint. Use [RagChatCompletionRequestBuilder::validation_issues] to get all of them.
+    pub fn try_build(self) -> Result<RagChatCompletionsRequest, RagBuildError> {
+        if let Some(e) = self.validation_issues().into_iter().next() {
+            return Err(e);
+        }
+
+        Ok(self.build())
+    }
+
+    pub fn build(self) -> RagChatCompletionsRequest {
+        if let Some(observer) = &self.observer {
+            let request_type = short_type_name::<RagChatCompletionsRequest>();
+            observer.on_build(request_type);
+            if let Err(e) = self.req.validate() {
+                observer.on_validate_error(request_type, &e);
+            }
+            for warning in self.req.warnings() {
+                observer.on_warning(request_type, &warning);
+            }
+        }
+
+        self.req
+    }
+}
+
+#[test]
+fn test_rag_validation_issues() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+    let builder =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1);
+    assert!(builder.validation_issues().is_empty());
+
+    let builder =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_sampling(ChatCompletionRequestSampling::Temperature(2.5))
+            .with_presence_penalty(-3.0)
+            .with_stop(
+                vec!["a", "b", "c", "d", "e"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            )
+            .with_stop_condition(StopCondition::BalancedJson);
+    let issues = builder.validation_issues();
+    assert_eq!(
+        issues,
+        vec![
+            RagBuildError::EmptyMessages,
+            RagBuildError::TemperatureOutOfRange(2.5),
+            RagBuildError::PresencePenaltyOutOfRange(-3.0),
+            RagBuildError::TooManyStopSequences(5),
+            RagBuildError::Invalid(EndpointError::InvalidArgument(
+                "The `balanced_json` stop condition requires the `json_object` response format."
+                    .to_string()
+            )),
+        ]
+    );
+    assert_eq!(
+        issues[3].to_string(),
+        "Up to 4 stop sequences are allowed, but got 5."
+    );
+
+    // the builder is not consumed
+    let request = builder.build();
+    assert_eq!(request.temperature, Some(2.5));
+}
+
+#[test]
+fn test_rag_try_build() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+    let builder = |sampling: ChatCompletionRequestSampling| {
+        RagChatCompletionRequestBuilder::new(
+            messages.clone(),
+            "http://localhost:6333",
+            "default",
+            1,
+        )
+        .with_sampling(sampling)
+    };
+
+    let request = builder(ChatCompletionRequestSampling::Temperature(0.8))
+        .try_build()
+        .unwrap();
+    assert_eq!(request.temperature, Some(0.8));
+    assert!(builder(ChatCompletionRequestSampling::Temperature(2.0))
+        .try_build()
+        .is_ok());
+
+    assert_eq!(
+        builder(ChatCompletionRequestSampling::Temperature(50.0)).try_build(),
+        Err(RagBuildError::TemperatureOutOfRange(50.0))
+    );
+    assert_eq!(
+        builder(ChatCompletionRequestSampling::TopP(1.5)).try_build(),
+        Err(RagBuildError::TopPOutOfRange(1.5))
+    );
+    assert_eq!(
+        builder(ChatCompletionRequestSampling::TopP(0.5))
+            .with_presence_penalty(2.5)
+            .try_build(),
+        Err(RagBuildError::PresencePenaltyOutOfRange(2.5))
+    );
+    let error = builder(ChatCompletionRequestSampling::TopP(0.5))
+        .with_frequency_penalty(-2.5)
+        .try_build()
+        .unwrap_err();
+    assert_eq!(error, RagBuildError::FrequencyPenaltyOutOfRange(-2.5));
+    assert_eq!(
+        error.to_string(),
+        "`frequency_penalty` must be between -2.0 and 2.0, but got -2.5."
+    );
+
+    // the other checks of the request
+    let error = builder(ChatCompletionRequestSampling::TopP(0.5))
+        .with_stop_condition(StopCondition::BalancedJson)
+        .try_build()
+        .unwrap_err();
+    assert!(matches!(error, RagBuildError::Invalid(_)));
+
+    // the checks of the builder itself
+    assert_eq!(
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .try_build(),
+        Err(RagBuildError::EmptyMessages)
+    );
+    let stop = vec!["a", "b", "c", "d", "e"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    assert_eq!(
+        builder(ChatCompletionRequestSampling::TopP(0.5))
+            .with_stop(stop)
+            .try_build(),
+        Err(RagBuildError::TooManyStopSequences(5))
+    );
+}
+
+#[test]
+fn test_rag_score_threshold() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_score_threshold(0.4)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert_eq!(request.score_threshold, Some(0.4));
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""score_threshold":0.4"#));
+    let round_trip: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip.score_threshold, Some(0.4));
+
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 3);
+    request.embedding_model = "model".to_string();
+    assert!(request.score_threshold.is_none());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("score_threshold"));
+    let round_trip: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert!(round_trip.score_threshold.is_none());
+}
+
+#[test]
+fn test_rag_min_context_score() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_min_context_score(0.5)
+            .with_refusal_message("No idea.")
+            .build();
+    request.embedding_model = "model".to_string();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""min_context_score":0.5,"refusal_message":"No idea.""#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.min_context_score, Some(0.5));
+    assert_eq!(request.refusal_message.as_deref(), Some("No idea."));
+
+    let retrieved = |scores: &[f32]| RetrieveObject {
+        points: Some(
+            scores
+                .iter()
+                .map(|score| RagScoredPoint {
+                    source: "source".to_string(),
+                    score: *score,
+                    ..Default::default()
+                })
+                .collect(),
+        ),
+        limit: 3,
+        score_threshold: 0.0,
+        filter: None,
+    };
+    assert_eq!(
+        request.refusal(&retrieved(&[0.3, 0.6]), Distance::Cosine),
+        None
+    );
+    assert_eq!(
+        request.refusal(&retrieved(&[0.3, 0.4]), Distance::Cosine),
+        Some("No idea.")
+    );
+    // distances: lower is better
+    assert_eq!(
+        request.refusal(&retrieved(&[0.3, 0.6]), Distance::Euclid),
+        None
+    );
+    assert_eq!(
+        request.refusal(&retrieved(&[0.6, 0.7]), Distance::Manhattan),
+        Some("No idea.")
+    );
+    // reranked points are compared by the rerank score, whatever the metric
+    let mut reranked = retrieved(&[0.3, 0.6]);
+    for (point, rerank_score) in reranked.points.iter_mut().flatten().zip([0.4, 0.2]) {
+        point.rerank_score = Some(rerank_score);
+    }
+    assert_eq!(
+        request.refusal(&reranked, Distance::Cosine),
+        Some("No idea.")
+    );
+    assert_eq!(
+        request.refusal(&reranked, Distance::Euclid),
+        Some("No idea.")
+    );
+    reranked.points.as_mut().unwrap()[0].rerank_score = Some(0.5);
+    assert_eq!(request.refusal(&reranked, Distance::Euclid), None);
+    // a rerank score is not compared with the vector scores of the other points
+    let mut partially_reranked = retrieved(&[0.3, 0.4]);
+    partially_reranked.points.as_mut().unwrap()[0].rerank_score = Some(0.9);
+    assert_eq!(
+        request.refusal(&partially_reranked, Distance::Cosine),
+        Some("No idea.")
+    );
+    // all points are filtered out by the score threshold
+    assert_eq!(
+        request.refusal(&RetrieveObject::default(), Distance::Cosine),
+        Some("No idea.")
+    );
+
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 3);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("min_context_score"));
+    assert!(!json.contains("refusal_message"));
+    assert_eq!(
+        request.refusal(&RetrieveObject::default(), Distance::Cosine),
+        None
+    );
+
+    request.min_context_score = Some(0.9);
+    assert_eq!(
+        request.refusal(&retrieved(&[0.3]), Distance::Cosine),
+        Some(DEFAULT_REFUSAL_MESSAGE)
+    );
+
+    request.min_context_score = Some(1.5);
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_min_distinct_sources() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 4)
+            .with_min_distinct_sources(2)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""min_distinct_sources":2"#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.min_distinct_sources, Some(2));
+
+    let retrieved = |file_ids: &[&str], limit: usize| RetrieveObject {
+        points: Some(
+            file_ids
+                .iter()
+                .map(|file_id| {
+                    let mut payload = HashMap::new();
+                    payload.insert(DOCUMENT_ID_PAYLOAD_KEY.to_string(), Value::from(*file_id));
+                    RagScoredPoint {
+                        source: "source".to_string(),
+                        score: 0.5,
+                        payload: Some(payload),
+                        ..Default::default()
+                    }
+                })
+                .collect(),
+        ),
+        limit,
+        score_threshold: 0.0,
+        filter: None,
+    };
+
+    // the top results all come from the same document
+    assert_eq!(
+        request.next_fetch_limit(&retrieved(&["a", "a", "a", "a"], 4), 32),
+        Some(8)
+    );
+    assert_eq!(
+        request.next_fetch_limit(&retrieved(&["a"; 8], 8), 32),
+        Some(16)
+    );
+    // capped at `fetch_k`
+    assert_eq!(
+        request.next_fetch_limit(&retrieved(&["a"; 16], 16), 20),
+        Some(20)
+    );
+    assert_eq!(
+        request.next_fetch_limit(&retrieved(&["a"; 20], 20), 20),
+        None
+    );
+    // more fetching found another document
+    assert_eq!(
+        request.next_fetch_limit(&retrieved(&["a", "a", "a", "b", "a", "a", "a", "a"], 8), 32),
+        None
+    );
+    // the candidates ran out
+    assert_eq!(
+        request.next_fetch_limit(&retrieved(&["a", "a"], 4), 32),
+        None
+    );
+
+    // no requirement
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 4);
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("min_distinct_sources"));
+    assert_eq!(request.next_fetch_limit(&retrieved(&["a"; 4], 4), 32), None);
+
+    request.min_distinct_sources = Some(0);
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_answer_language() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_answer_language("fr-CA")
+            .build();
+    request.embedding_model = "model".to_string();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""answer_language":"fr-CA""#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.answer_language.as_deref(), Some("fr-CA"));
+    assert_eq!(
+        request.answer_language_instruction().as_deref(),
+        Some("Answer in the language with the BCP-47 tag `fr-CA`, regardless of the language of the context.")
+    );
+
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("answer_language"));
+    assert!(request.answer_language_instruction().is_none());
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_answer_language(" ")
+            .build();
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_validate_stop_condition() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+    let mut request =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+            .with_stop_condition(StopCondition::BalancedJson)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert_eq!(
+        request.validate(),
+        Err(EndpointError::InvalidArgument(
+            "The `balanced_json` stop condition requires the `json_object` response format."
+                .to_string()
+        ))
+    );
+
+    request.response_format = Some(ChatResponseFormat {
+        ty: "json_object".to_string(),
+        schema_hint: None,
+        json_schema: None,
+    });
+    assert!(request.validate().is_ok());
+    assert_eq!(
+        request.as_chat_completions_request().stop_condition,
+        Some(StopCondition::BalancedJson)
+    );
+
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""stop_condition":"balanced_json""#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.stop_condition, Some(StopCondition::BalancedJson));
+}
+
+#[test]
+fn test_rag_retrieval_query_text() {
+    let messages = vec![
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Who created LlamaEdge?".to_string()),
+            None,
+        ),
+        ChatCompletionRequestMessage::new_assistant_message(
+            Some("Second State created LlamaEdge.".to_string()),
+            None,
+            None,
+        ),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Where are they based?".to_string()),
+            None,
+        ),
+    ];
+
+    let request = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        "default",
+        1,
+    )
+    .build();
+    assert_eq!(
+        request.retrieval_query_text().as_deref(),
+        Some("Where are they based?")
+    );
+
+    let request = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        "default",
+        1,
+    )
+    .with_include_assistant_context(true)
+    .with_query_preprocessing(vec![QueryStep::Lowercase])
+    .build();
+    assert_eq!(
+        request.retrieval_query_text().as_deref(),
+        Some("second state created llamaedge.\nwhere are they based?")
+    );
+
+    // no assistant reply right before the query
+    let messages = vec![
+        messages[1].clone(),
+        messages[0].clone(),
+        messages[2].clone(),
+    ];
+    let request =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+            .with_include_assistant_context(true)
+            .build();
+    assert_eq!(
+        request.retrieval_query_text().as_deref(),
+        Some("Where are they based?")
+    );
+
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    assert!(request.retrieval_query_text().is_none());
+}
+
+#[test]
+fn test_rag_embedding_cache_key() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+        None,
+    )];
+    let request =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 5);
+    let key = request.embedding_cache_key();
+    assert_eq!(key.len(), 64);
+
+    // `limit` does not affect the query embedding
+    let other =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 10);
+    assert_eq!(other.embedding_cache_key(), key);
+
+    // `embedding_model` does
+    let mut other =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 5);
+    other.embedding_model = "nomic-embed-text-v1.5".to_string();
+    assert_ne!(other.embedding_cache_key(), key);
+
+    // so do the models of the collections, regardless of their order
+    let mut other =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 5);
+    other.collection_embedding_models = Some(HashMap::from([
+        ("default".to_string(), "nomic-embed-text-v1.5".to_string()),
+        ("faq".to_string(), "bge-m3".to_string()),
+    ]));
+    let models_key = other.embedding_cache_key();
+    assert_ne!(models_key, key);
+    other.collection_embedding_models = Some(HashMap::from([
+        ("faq".to_string(), "bge-m3".to_string()),
+        ("default".to_string(), "nomic-embed-text-v1.5".to_string()),
+    ]));
+    assert_eq!(other.embedding_cache_key(), models_key);
+
+    // and the dimensions
+    let other = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        "default",
+        5,
+    )
+    .with_dimensions(256)
+    .build();
+    assert_ne!(other.embedding_cache_key(), key);
+
+    // so does the query text, including its preprocessing
+    let mut other = RagChatCompletionsRequest::new(messages, "http://localhost:6333", "default", 5);
+    other.query_preprocessing = Some(vec![QueryStep::Lowercase]);
+    assert_ne!(other.embedding_cache_key(), key);
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("what is llamaedge?".to_string()),
+        None,
+    )];
+    let lowercase = RagChatCompletionsRequest::new(messages, "http://localhost:6333", "default", 5);
+    assert_ne!(lowercase.embedding_cache_key(), key);
+    assert_eq!(lowercase.embedding_cache_key(), other.embedding_cache_key());
+}
+
+#[test]
+fn test_rag_stream_with_tools() {
+    let tools = vec![Tool::function("get_weather", "Gets the weather.")];
+
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_stream(true)
+            .build();
+    request.embedding_model = "model".to_string();
+    request.tools = Some(tools.clone());
+    assert_eq!(request.warnings().len(), 1);
+    assert_eq!(request.as_chat_completions_request().stream, Some(true));
+
+    // streaming is turned off, along with the stream options
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_stream_options(StreamOptions {
+                include_usage: Some(true),
+                usage_interval: None,
+            })
+            .with_force_non_stream_with_tools(true)
+            .build();
+    request.embedding_model = "model".to_string();
+    request.tools = Some(tools);
+    assert!(request.warnings().is_empty());
+    let chat_request = request.as_chat_completions_request();
+    assert_eq!(chat_request.stream, Some(false));
+    assert!(chat_request.stream_options.is_none());
+    assert!(chat_request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""force_non_stream_with_tools":true"#));
+
+    // without tools, streaming is kept
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_stream(true)
+            .with_force_non_stream_with_tools(true)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert!(request.warnings().is_empty());
+    assert_eq!(request.as_chat_completions_request().stream, Some(true));
+}
+
+#[test]
+fn test_rag_seed() {
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_seed(42)
+            .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""seed":42"#));
+
+    let chat_request = request.as_chat_completions_request();
+    assert_eq!(chat_request.seed, Some(42));
+    let json = serde_json::to_string(&chat_request).unwrap();
+    assert!(json.contains(r#""seed":42"#));
+    let chat_request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(chat_request.seed, Some(42));
+
+    let request = RagChatCompletionsRequest::from_chat_completions_request(
+        chat_request,
+        "http://localhost:6333",
+        "default",
+        1,
+    );
+    assert_eq!(request.seed, Some(42));
+
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    assert!(request.seed.is_none());
+    assert!(request.as_chat_completions_request().seed.is_none());
+    assert!(!serde_json::to_string(&request).unwrap().contains("seed"));
+}
+
+#[test]
+fn test_rag_sampling_passthrough() {
+    let sampling = |request: &RagChatCompletionsRequest| {
+        (
+            request.top_p,
+            request.min_p,
+            request.top_k,
+            request.repeat_penalty,
+            request.repeat_last_n,
+            request.logprobs,
+            request.top_logprobs,
+            request.parallel_tool_calls,
+        )
+    };
+    let chat_sampling = |request: &ChatCompletionRequest| {
+        (
+            request.top_p,
+            request.min_p,
+            request.top_k,
+            request.repeat_penalty,
+            request.repeat_last_n,
+            request.logprobs,
+            request.top_logprobs,
+            request.parallel_tool_calls,
+        )
+    };
+    let expected = (
+        Some(0.9),
+        Some(0.05),
+        Some(40),
+        Some(1.2),
+        Some(128),
+        Some(true),
+        Some(3),
+        Some(false),
+    );
+
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_sampling(ChatCompletionRequestSampling::TopP(0.9))
+            .with_min_p(0.05)
+            .with_top_k(40)
+            .with_repeat_penalty(1.2)
+            .with_repeat_last_n(128)
+            .with_logprobs(true)
+            .with_top_logprobs(3)
+            .with_parallel_tool_calls(false)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert_eq!(sampling(&request), expected);
+    let json = serde_json::to_string(&request).unwrap();
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(sampling(&request), expected);
+
+    let chat_request = request.as_chat_completions_request();
+    assert_eq!(chat_sampling(&chat_request), expected);
+    let json = serde_json::to_string(&chat_request).unwrap();
+    let chat_request: ChatCompletionRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(chat_sampling(&chat_request), expected);
+
+    let request = RagChatCompletionsRequest::from_chat_completions_request(
+        chat_request,
+        "http://localhost:6333",
+        "default",
+        1,
+    );
+    assert_eq!(sampling(&request), expected);
+    assert_eq!(
+        chat_sampling(&ChatCompletionRequest::from(request)),
+        expected
+    );
+
+    // omitted by default, except `top_p`
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    assert_eq!(
+        sampling(&request),
+        (Some(1.0), None, None, None, None, None, None, None)
+    );
+    let chat_request = request.as_chat_completions_request();
+    for json in [
+        serde_json::to_string(&request).unwrap(),
+        serde_json::to_string(&chat_request).unwrap(),
+    ] {
+        for field in [
+            "min_p",
+            "top_k",
+            "repeat_",
+            "logprobs",
+            "parallel_tool_calls",
+        ] {
+            assert!(!json.contains(field));
+        }
+    }
+}
+
+#[test]
+fn test_rag_validate_logprobs() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_logprobs(true)
+            .with_top_logprobs(3)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert!(request.validate().is_ok());
+
+    // `top_logprobs` requires `logprobs` and is at most 20
+    request.top_logprobs = Some(21);
+    assert!(request.validate().is_err());
+    request.top_logprobs = Some(20);
+    assert!(request.validate().is_ok());
+    request.logprobs = None;
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_tool_messages_round_trip() {
+    let messages = vec![
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What's the weather in Boston?".to_string()),
+            None,
+        ),
+        ChatCompletionRequestMessage::new_assistant_message(
+            None,
+            None,
+            Some(vec![crate::chat::ToolCall {
+                id: "call_abc123".to_string(),
+                ty: "function".to_string(),
+                function: crate::chat::Function {
+                    name: "get_current_weather".to_string(),
+                    arguments: r#"{"location":"Boston, MA"}"#.to_string(),
+                },
+            }]),
+        ),
+        ChatCompletionRequestMessage::new_tool_result_message(
+            "22 degrees",
+            "call_abc123",
+            Some("get_current_weather".to_string()),
+        ),
+    ];
+    let mut request =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 1);
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(
+        r#"{"role":"tool","content":"22 degrees","tool_call_id":"call_abc123","name":"get_current_weather"}"#
+    ));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.messages, messages);
+}
+
+#[test]
+fn test_rag_builder_new_simple() {
+    let messages = vec![
+        ChatCompletionRequestMessage::new_system_message("Answer from the context only.", None),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+            None,
+        ),
+    ];
+    let expected =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 3)
+            .with_max_tokens(128)
+            .build();
+
+    let request = RagChatCompletionRequestBuilder::new_simple(
+        "Answer from the context only.",
+        "What is LlamaEdge?",
+        "http://localhost:6333",
+        "default",
+        3,
+    )
+    .with_max_tokens(128)
+    .build();
+    assert_eq!(request, expected);
+    assert_eq!(request.messages.len(), 2);
+    assert_eq!(
+        request.messages[0].role(),
+        crate::chat::ChatCompletionRole::System
+    );
+    assert_eq!(
+        request.messages[1].role(),
+        crate::chat::ChatCompletionRole::User
+    );
+}
+
+#[test]
+fn test_rag_context_window() {
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_context_window(0)
+            .build();
+    assert_eq!(request.context_window, Some(1));
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_context_window(3)
+            .build();
+    assert_eq!(request.context_window, Some(3));
+}
+
+#[test]
+fn test_rag_prompt_template() {
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_prompt_template("llama-3-chat")
+            .build();
+    assert!(request.validate().is_ok());
+    let chat_request = request.as_chat_completions_request();
+    assert_eq!(
+        chat_request.prompt_template.as_deref(),
+        Some("llama-3-chat")
+    );
+    let request = RagChatCompletionsRequest::from_chat_completions_request(
+        chat_request,
+        "http://localhost:6333",
+        "default",
+        1,
+    );
+    assert_eq!(request.prompt_template.as_deref(), Some("llama-3-chat"));
+
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("prompt_template"));
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_prompt_template("")
+            .build();
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_stream_options_require_stream() {
+    let stream_options = crate::chat::StreamOptionsBuilder::new()
+        .with_usage(true)
+        .build()
+        .unwrap();
+
+    // the builder enables streaming
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_stream_options(stream_options.clone())
+            .build();
+    assert_eq!(request.stream, Some(true));
+    assert_eq!(request.stream_options.as_ref(), Some(&stream_options));
+    assert!(request.validate().is_ok());
+
+    // even if streaming was turned off before
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_stream(false)
+            .with_stream_options(stream_options.clone())
+            .build();
+    assert_eq!(request.stream, Some(true));
+
+    // stream options without streaming are rejected
+    let mut request = request;
+    request.stream = Some(false);
+    assert!(request.validate().is_err());
+    request.stream = None;
+    assert!(request.validate().is_err());
+    request.stream_options = None;
+    assert!(request.validate().is_ok());
+
+    let json = r#"{"messages":[],"embedding_model":"model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":1,"stream":false,"stream_options":{"include_usage":true}}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_json_schema() {
+    let json_schema = JsonSchema {
+        name: "answer".to_string(),
+        schema: serde_json::json!({"type": "object", "properties": {"answer": {"type": "string"}}}),
+        strict: Some(true),
+    };
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_json_schema(json_schema.clone())
+            .build();
+    assert!(request.features().uses_json_mode);
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(
+        r#""response_format":{"type":"json_schema","json_schema":{"name":"answer","schema":{"properties":{"answer":{"type":"string"}},"type":"object"},"strict":true}}"#
+    ));
+
+    let chat_request = request.as_chat_completions_request();
+    let response_format = chat_request.response_format.as_ref().unwrap();
+    assert_eq!(response_format.json_schema.as_ref(), Some(&json_schema));
+
+    // the schema conflicts with a grammar
+    let mut request = RagChatCompletionsRequest::from_chat_completions_request(
+        chat_request,
+        "http://localhost:6333",
+        "default",
+        1,
+    );
+    request.grammar = Some(r#"root ::= "Yes" | "No""#.to_string());
+    assert!(request.validate().is_err());
+
+    // the schema requires the `json_schema` type
+    request.grammar = None;
+    assert!(request.validate().is_ok());
+    request.response_format.as_mut().unwrap().ty = "text".to_string();
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_grammar() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_grammar(r#"root ::= "Yes" | "No""#)
+            .build();
+    request.embedding_model = "model".to_string();
+    assert_eq!(
+        request.as_chat_completions_request().grammar.as_deref(),
+        Some(r#"root ::= "Yes" | "No""#)
+    );
+    assert!(request.warnings().is_empty());
+
+    request.stop = Some(vec!["No".to_string(), "</s>".to_string()]);
+    assert_eq!(request.warnings().len(), 1);
+    assert!(request.validate().is_ok());
+
+    request.response_format = Some(ChatResponseFormat {
+        ty: "json_object".to_string(),
+        schema_hint: None,
+        json_schema: None,
+    });
+    assert!(request.validate().is_err());
+    request.grammar = None;
+    assert!(request.validate().is_ok());
+}
+
+#[test]
+fn test_rag_stop_on_tool_call() {
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("stop_on_tool_call"));
+
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_stop_on_tool_call(true)
+            .build();
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""stop_on_tool_call":true"#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.stop_on_tool_call, Some(true));
+    assert_eq!(
+        request.as_chat_completions_request().stop_on_tool_call,
+        Some(true)
+    );
+}
+
+#[test]
+fn test_rag_request_id() {
+    let mut request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("request_id"));
+    let request_id = request.ensure_request_id().to_string();
+    assert_eq!(
+        request.as_chat_completions_request().request_id,
+        Some(request_id)
+    );
+
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_request_id("req-1")
+            .build();
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""request_id":"req-1""#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.request_id.as_deref(), Some("req-1"));
+}
+
+#[test]
+fn test_rag_idempotency_key() {
+    let key = crate::chat::generate_idempotency_key();
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_idempotency_key(key.clone())
+            .build();
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(&format!(r#""idempotency_key":"{}""#, key)));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.idempotency_key.as_deref(), Some(key.as_str()));
+    assert_eq!(
+        request.as_chat_completions_request().idempotency_key,
+        Some(key)
+    );
+
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("idempotency_key"));
+}
+
+#[test]
+fn test_rag_audio_output() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+    let audio = AudioOutputConfig {
+        voice: "alloy".to_string(),
+        format: "mp3".to_string(),
+    };
+
+    let request = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        "default",
+        1,
+    )
+    .with_audio(audio.clone())
+    .build();
+    assert!(request.validate().is_err());
+
+    let request =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+            .with_modalities(vec![Modality::Text, Modality::Audio])
+            .with_audio(audio.clone())
+            .build();
+    assert!(request.validate().is_ok());
+
+    // carried through the conversions
+    let chat_request = request.as_chat_completions_request();
+    assert_eq!(
+        chat_request.modalities,
+        Some(vec![Modality::Text, Modality::Audio])
+    );
+    assert_eq!(chat_request.audio, Some(audio.clone()));
+    let request = RagChatCompletionsRequest::from_chat_completions_request(
+        chat_request,
+        "http://localhost:6333",
+        "default",
+        1,
+    );
+    assert_eq!(
+        request.modalities,
+        Some(vec![Modality::Text, Modality::Audio])
+    );
+    assert_eq!(request.audio, Some(audio));
+}
+
+#[test]
+fn test_rag_inject_context() {
+    let messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "Hello, world!",
+        None,
+    )];
+
+    let request = RagChatCompletionRequestBuilder::new(
+        messages.clone(),
+        "http://localhost:6333",
+        "default",
+        1,
+    )
+    .build();
+    assert!(request.inject_context.is_none());
+    assert!(request.injects_context());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("inject_context"));
+
+    let request =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+            .with_inject_context(false)
+            .build();
+    assert!(!request.injects_context());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""inject_context":false"#));
+}
+
+#[test]
+fn test_rag_request_from_prompt() {
+    let request = RagChatCompletionsRequest::from_prompt(
+        "What is LlamaEdge?",
+        "http://localhost:6333",
+        "default",
+        3,
+    );
+    assert_eq!(request.messages.len(), 1);
+    assert_eq!(
+        request.messages[0],
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+            None,
+        )
+    );
+    assert_eq!(request.qdrant_url, "http://localhost:6333");
+    assert_eq!(request.qdrant_collection_name, "default");
+    assert_eq!(request.limit, 3);
+}
+
+#[test]
+fn test_rag_serialize_request_without_tools() {
+    let request =
+        RagChatCompletionsRequest::from_prompt("Hello", "http://localhost:6333", "default", 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("tools"));
+    assert!(!json.contains("tool_choice"));
+    assert!(!json.contains("null"));
+
+    // nor does the chat completions request sent upstream
+    let json = serde_json::to_string(&request.as_chat_completions_request()).unwrap();
+    assert!(!json.contains("tools"));
+    assert!(!json.contains("tool_choice"));
+    assert!(!json.contains("null"));
+    let json = serde_json::to_string(&ChatCompletionRequest::from(request.clone())).unwrap();
+    assert!(!json.contains("tools"));
+    assert!(!json.contains("tool_choice"));
+
+    let mut request = request;
+    request.tool_choice = Some(ToolChoice::Auto);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains(r#""tools""#));
+    assert!(json.contains(r#""tool_choice":"auto""#));
+}
+
+#[test]
+fn test_rag_serialize_dummy_chat_model() {
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1).build();
+    assert_eq!(request.chat_model.as_deref(), Some(DUMMY_CHAT_MODEL));
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("chat_model"));
+    assert!(!json.contains(DUMMY_CHAT_MODEL));
+    // the embedding model is kept, so that the server can report the placeholder
+    assert!(json.contains(r#""embedding_model":"dummy-embedding-model""#));
+    // and it is reported as a warning whether or not the `tracing` feature is enabled
+    assert_eq!(
+        request.warnings(),
+        vec![
+            "`embedding_model` is still the `dummy-embedding-model` placeholder, which servers may not recognize."
+                .to_string()
+        ]
+    );
+    request.embedding_model = "model".to_string();
+    assert!(request.warnings().is_empty());
+    let json = serde_json::to_string(&request).unwrap();
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert!(request.chat_model.is_none());
+
+    let mut request = request;
+    request.chat_model = Some("llama-3-8b".to_string());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""chat_model":"llama-3-8b""#));
+}
+
+#[test]
+fn test_rag_validate_max_messages() {
+    let message = ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("Hello".to_string()),
+        None,
+    );
+
+    let mut request = RagChatCompletionRequestBuilder::new(
+        vec![message.clone(); DEFAULT_MAX_MESSAGES],
+        "http://localhost:6333",
+        "default",
+        1,
+    )
+    .build();
+    assert!(request.validate().is_ok());
+
+    request.messages.push(message);
+    assert_eq!(
+        request.validate(),
+        Err(EndpointError::InvalidArgument(
+            "The conversation contains 10001 messages, which exceeds the limit of 10000 messages."
+                .to_string()
+        ))
+    );
+
+    assert!(request.validate_max_messages(10_001).is_ok());
+    assert!(request.validate_max_messages(10).is_err());
+}
+
+#[test]
+fn test_rag_latest_human_query() {
+    use crate::chat::{Function, ImageContentPart, TextContentPart, ToolCall};
+
+    let messages = vec![
+        ChatCompletionRequestMessage::new_system_message("You are a helpful assistant.", None),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Hello".to_string()),
+            None,
+        ),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What is the weather in Paris?".to_string()),
+            None,
+        ),
+        ChatCompletionRequestMessage::new_assistant_message(
+            None,
+            None,
+            Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                ty: "function".to_string(),
+                function: Function {
+                    name: "get_weather".to_string(),
+                    arguments: r#"{"location":"Paris"}"#.to_string(),
+                },
+            }]),
+        ),
+        ChatCompletionRequestMessage::new_tool_message(
+            r#"{"temperature":20}"#,
+            Some("call_1".to_string()),
+        ),
+    ];
+    let mut request =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 1)
+            .build();
+    assert_eq!(
+        request.latest_human_query(),
+        Some("What is the weather in Paris?")
+    );
+
+    // image-only user messages are skipped, and the text of multi-part messages is used
+    request
+        .messages
+        .push(ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Parts(vec![ContentPart::Image(
+                ImageContentPart::new(crate::chat::Image {
+                    url: "https://example.com/image.png".to_string(),
+                    detail: None,
+                }),
+            )]),
+            None,
+        ));
+    assert_eq!(
+        request.latest_human_query(),
+        Some("What is the weather in Paris?")
+    );
+    request
+        .messages
+        .push(ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Parts(vec![ContentPart::Text(TextContentPart::new(
+                "And in Rome?",
+            ))]),
+            None,
+        ));
+    assert_eq!(request.latest_human_query(), Some("And in Rome?"));
+
+    request
+        .messages
+        .retain(|message| !matches!(message, ChatCompletionRequestMessage::User(_)));
+    assert_eq!(request.latest_human_query(), None);
+}
+
+/// A normalization step applied to the query text before it is embedded for retrieval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryStep {
+    /// Converts the text to lowercase.
+    Lowercase,
+    /// Removes the leading and trailing whitespace.
+    Trim,
+    /// Removes the Markdown syntax, such as headings, emphasis, inline code and links, and keeps the text.
+    StripMarkdown,
+    /// Replaces each run of whitespace, including line breaks, with a single space.
+    CollapseWhitespace,
+}
+
+/// Applies the normalization steps in order to the query text.
+///
+/// # Arguments
+///
+/// * `query` - The query text.
+///
+/// * `steps` - The normalization steps to apply.
+pub fn preprocess_query(query: &str, steps: &[QueryStep]) -> String {
+    let mut query = query.to_string();
+    for step in steps {
+        query = match step {
+            QueryStep::Lowercase => query.to_lowercase(),
+            QueryStep::Trim => query.trim().to_string(),
+            QueryStep::StripMarkdown => strip_markdown(&query),
+            QueryStep::CollapseWhitespace => query.split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+    }
+    query
+}
+
+/// Removes the common Markdown syntax from the text. Only the markers at the start of a line and the paired inline markers are removed, so that text such as `C#`, `a*b` or `x[0]` is kept as is.
+fn strip_markdown(text: &str) -> String {
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            // block-level markers: quotes, headings and list items
+            let mut line = line.trim_start();
+            while let Some(rest) = line.strip_prefix('>') {
+                line = rest.trim_start();
+            }
+            let level = line.chars().take_while(|&c| c == '#').count();
+            if (1..=6).contains(&level)
+                && line[level..].chars().next().is_none_or(char::is_whitespace)
+            {
+                line = line[level..].trim_start();
+            }
+            for marker in ["- ", "* ", "+ "] {
+                if let Some(rest) = line.strip_prefix(marker) {
+                    line = rest;
+                    break;
+                }
+            }
+
+            strip_inline_markdown(line)
+        })
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Removes the images, links, emphasis, strikethrough and inline code of a line, and keeps their text. Unpaired markers are kept.
+fn strip_inline_markdown(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut stripped = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+
+        // images and links: `![alt](url)` and `[text](url)`
+        let bracket = match c {
+            '!' if chars.get(i + 1) == Some(&'[') => Some(i + 1),
+            '[' => Some(i),
+            _ => None,
+        };
+        if let Some((text, end)) = bracket.and_then(|open| link_at(&chars, open)) {
+            stripped.push_str(&strip_inline_markdown(&text));
+            i = end;
+            continue;
+        }
+
+        // emphasis, strikethrough and inline code: a run of markers closed by a run of the same length
+        if matches!(c, '*' | '~' | '`') {
+            let len = chars[i..].iter().take_while(|&&d| d == c).count();
+            match closing_marker(&chars, i + len, c, len) {
+                Some(close) => {
+                    let inner: String = chars[i + len..close].iter().collect();
+                    match c {
+                        '`' => stripped.push_str(&inner),
+                        _ => stripped.push_str(&strip_inline_markdown(&inner)),
+                    }
+                    i = close + len;
+                }
+                None => {
+                    stripped.extend(&chars[i..i + len]);
+                    i += len;
+                }
+            }
+            continue;
+        }
+
+        stripped.push(c);
+        i += 1;
+    }
+    stripped
+}
+
+/// Returns the text of the link whose `[` is at `open`, and the position right after its closing `)`, if the brackets are followed by a url in parentheses.
+fn link_at(chars: &[char], open: usize) -> Option<(String, usize)> {
+    let close = open + chars[open..].iter().position(|&c| c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 1 + chars[close + 1..].iter().position(|&c| c == ')')?;
+
+    Some((chars[open + 1..close].iter().collect(), end + 1))
+}
+
+/// Returns the position of the run of exactly `len` markers closing the run that ends at `start`, if any. As in Markdown, an opening run must be followed and a closing run preceded by a non-whitespace character.
+fn closing_marker(chars: &[char], start: usize, marker: char, len: usize) -> Option<usize> {
+    match chars.get(start) {
+        Some(c) if !c.is_whitespace() => {}
+        _ => return None,
+    }
+
+    let mut j = start;
+    while j < chars.len() {
+        if chars[j] != marker {
+            j += 1;
+            continue;
+        }
+        let run = chars[j..].iter().take_while(|&&c| c == marker).count();
+        if run == len && !chars[j - 1].is_whitespace() {
+            return Some(j);
+        }
+        j += run;
+    }
+    None
+}
+
+#[test]
+fn test_rag_preprocess_query() {
+    assert_eq!(
+        preprocess_query("What Is LlamaEdge?", &[QueryStep::Lowercase]),
+        "what is llamaedge?"
+    );
+    assert_eq!(
+        preprocess_query("  What is LlamaEdge?\n ", &[QueryStep::Trim]),
+        "What is LlamaEdge?"
+    );
+    assert_eq!(
+        preprocess_query(
+            "## What is **LlamaEdge**?\n> See `docs` and [the guide](https://llamaedge.com).",
+            &[QueryStep::StripMarkdown]
+        ),
+        "What is LlamaEdge?\nSee docs and the guide."
+    );
+    assert_eq!(
+        preprocess_query(
+            "- ![logo](logo.png) ~~old~~ ``a `b` c`` and ***bold***",
+            &[QueryStep::StripMarkdown]
+        ),
+        "logo old a `b` c and bold"
+    );
+    // unpaired markers are part of the text
+    for query in [
+        "Is C# faster than Rust?",
+        "What is a*b?",
+        "Why does x[0] panic?",
+        "What is 2 * 3 * 4?",
+        "Is [x](y the same as ~x?",
+    ] {
+        assert_eq!(preprocess_query(query, &[QueryStep::StripMarkdown]), query);
+    }
+    assert_eq!(
+        preprocess_query("#hashtag\n####### seven", &[QueryStep::StripMarkdown]),
+        "#hashtag\n####### seven"
+    );
+    assert_eq!(
+        preprocess_query("What  is\n\tLlamaEdge?", &[QueryStep::CollapseWhitespace]),
+        "What is LlamaEdge?"
+    );
+
+    // the steps are applied in order
+    let query = "  # What is\n*LlamaEdge*?  ";
+    assert_eq!(
+        preprocess_query(
+            query,
+            &[
+                QueryStep::StripMarkdown,
+                QueryStep::CollapseWhitespace,
+                QueryStep::Lowercase,
+                QueryStep::Trim,
+            ]
+        ),
+        "what is llamaedge?"
+    );
+    assert_eq!(preprocess_query(query, &[]), query);
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_query_preprocessing(vec![QueryStep::Trim, QueryStep::Lowercase])
+            .build();
+    assert_eq!(request.preprocess_query(" Hello "), "hello");
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""query_preprocessing":["trim","lowercase"]"#));
+}
+
+/// A boolean keyword prefilter, matched against the full text of a payload field before the vector search.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeywordFilter {
+    /// The payload field to match against. Defaults to `source`, the field holding the text of the chunk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// The keywords that must all be contained in the field.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub must_contain: Vec<String>,
+    /// The keywords that must not be contained in the field.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub must_not_contain: Vec<String>,
+}
+impl KeywordFilter {
+    /// Returns the payload field to match against.
+    pub fn field(&self) -> &str {
+        self.field.as_deref().unwrap_or("source")
+    }
+
+    /// Checks that at least one of the keyword lists is not empty.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if self.must_contain.is_empty() && self.must_not_contain.is_empty() {
+            return Err(EndpointError::InvalidArgument(
+                "The keyword filter requires at least one keyword in `must_contain` or `must_not_contain`.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rag_keyword_filter() {
+    let filter = KeywordFilter {
+        must_contain: vec!["GDPR".to_string()],
+        ..Default::default()
+    };
+    assert!(filter.validate().is_ok());
+    assert_eq!(filter.field(), "source");
+    let json = serde_json::to_string(&filter).unwrap();
+    assert_eq!(json, r#"{"must_contain":["GDPR"]}"#);
+
+    let json =
+        r#"{"field":"title","must_contain":["GDPR"],"must_not_contain":["draft","obsolete"]}"#;
+    let filter: KeywordFilter = serde_json::from_str(json).unwrap();
+    assert_eq!(filter.field(), "title");
+    assert_eq!(filter.must_not_contain, vec!["draft", "obsolete"]);
+    assert_eq!(serde_json::to_string(&filter).unwrap(), json);
+
+    let filter: KeywordFilter = serde_json::from_str("{}").unwrap();
+    assert!(filter.validate().is_err());
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_keyword_filter(filter)
+            .build();
+    assert!(request.validate().is_err());
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_keyword_filter(KeywordFilter {
+                must_not_contain: vec!["draft".to_string()],
+                ..Default::default()
+            })
+            .build();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""keyword_filter":{"must_not_contain":["draft"]}"#));
+}
+
+/// Defines how the raw similarity scores of the retrieved points are mapped to confidence values between 0.0 and 1.0.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScoreCalibration {
+    /// Keeps the raw score, clamped to 0.0..=1.0.
+    Linear,
+    /// Maps the raw score with the logistic function `1 / (1 + exp(-steepness * (raw - midpoint)))`.
+    Sigmoid {
+        /// The raw score that is mapped to 0.5.
+        midpoint: f32,
+        /// How fast the confidence grows around the midpoint. Must be positive.
+        steepness: f32,
+    },
+}
+impl ScoreCalibration {
+    /// Checks that the parameters of the calibration are valid.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if let ScoreCalibration::Sigmoid {
+            midpoint,
+            steepness,
+        } = self
+        {
+            if !midpoint.is_finite() {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The midpoint of the sigmoid calibration must be finite, but got {}.",
+                    midpoint
+                )));
+            }
+            if !steepness.is_finite() || *steepness <= 0.0 {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The steepness of the sigmoid calibration must be positive and finite, but got {}.",
+                    steepness
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps a raw similarity score to a confidence value between 0.0 and 1.0.
+///
+/// # Arguments
+///
+/// * `raw` - The raw similarity score of a retrieved point.
+///
+/// * `calibration` - The calibration to apply.
+pub fn calibrate_score(raw: f32, calibration: &ScoreCalibration) -> f32 {
+    match calibration {
+        ScoreCalibration::Linear => raw.clamp(0.0, 1.0),
+        ScoreCalibration::Sigmoid {
+            midpoint,
+            steepness,
+        } => 1.0 / (1.0 + (-steepness * (raw - midpoint)).exp()),
+    }
+}
+
+#[test]
+fn test_rag_calibrate_score() {
+    let linear = ScoreCalibration::Linear;
+    assert_eq!(calibrate_score(-0.2, &linear), 0.0);
+    assert_eq!(calibrate_score(0.3, &linear), 0.3);
+    assert_eq!(calibrate_score(1.5, &linear), 1.0);
+
+    let sigmoid = ScoreCalibration::Sigmoid {
+        midpoint: 0.5,
+        steepness: 10.0,
+    };
+    assert_eq!(calibrate_score(0.5, &sigmoid), 0.5);
+    let low = calibrate_score(0.2, &sigmoid);
+    let high = calibrate_score(0.8, &sigmoid);
+    assert!((low - 0.047_425_87).abs() < 1e-6);
+    assert!((high - 0.952_574_13).abs() < 1e-6);
+    assert!((low + high - 1.0).abs() < 1e-6);
+    assert!(calibrate_score(-100.0, &sigmoid) >= 0.0);
+    assert!(calibrate_score(100.0, &sigmoid) <= 1.0);
+}
+
+/// Defines how the points are searched in the vector database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Searches by the dense embedding vectors.
+    #[default]
+    Dense,
+    /// Searches by the sparse, BM25-style vectors.
+    Sparse,
+    /// Combines the scores of the dense and sparse searches as `alpha * dense + (1 - alpha) * sparse`.
+    Hybrid {
+        /// The weight of the dense score, in the range of `0.0..=1.0`.
+        alpha: f32,
+    },
+}
+impl SearchMode {
+    /// Checks that the weight of the hybrid search is in the range of `0.0..=1.0`.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        if let SearchMode::Hybrid { alpha } = self {
+            if !(0.0..=1.0).contains(alpha) {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The alpha of the hybrid search must be in the range of 0.0..=1.0, but got {}.",
+                    alpha
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rag_search_mode() {
+    for (mode, json) in [
+        (SearchMode::Dense, r#"{"type":"dense"}"#),
+        (SearchMode::Sparse, r#"{"type":"sparse"}"#),
+        (
+            SearchMode::Hybrid { alpha: 0.75 },
+            r#"{"type":"hybrid","alpha":0.75}"#,
+        ),
+    ] {
+        assert_eq!(serde_json::to_string(&mode).unwrap(), json);
+        assert_eq!(serde_json::from_str::<SearchMode>(json).unwrap(), mode);
+        assert!(mode.validate().is_ok());
+    }
+    assert!(SearchMode::Hybrid { alpha: 1.5 }.validate().is_err());
+    assert!(SearchMode::Hybrid { alpha: f32::NAN }.validate().is_err());
+    assert!(serde_json::from_str::<SearchMode>(r#"{"type":"hybrid"}"#).is_err());
+
+    // dense by default
+    let json = r#"{"messages":[],"embedding_model":"model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert!(request.search_mode.is_none());
+    assert_eq!(request.resolved_search_mode(), SearchMode::Dense);
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_search_mode(SearchMode::Hybrid { alpha: 0.5 })
+            .build();
+    assert_eq!(
+        request.resolved_search_mode(),
+        SearchMode::Hybrid { alpha: 0.5 }
+    );
+    assert!(serde_json::to_string(&request)
+        .unwrap()
+        .contains(r#""search_mode":{"type":"hybrid","alpha":0.5}"#));
+    assert!(request.validate().is_ok());
+
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_search_mode(SearchMode::Hybrid { alpha: -0.5 })
+            .build();
+    assert!(request.validate().is_err());
+}
+
+/// Describes the reranking of the retrieved points by a cross-encoder model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RerankConfig {
+    /// The name of the reranking model.
+    pub model: String,
+    /// The number of points to keep after reranking. Must be positive and at most the number of retrieved points.
+    pub top_n: usize,
+}
+impl RerankConfig {
+    /// Checks that `top_n` is positive and at most `limit`, the number of retrieved points.
+    pub fn validate(&self, limit: u64) -> Result<(), EndpointError> {
+        if self.top_n == 0 || self.top_n as u64 > limit {
+            return Err(EndpointError::InvalidArgument(format!(
+                "The number of points to keep after reranking must be in the range of 1..={}, but got {}.",
+                limit, self.top_n
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rag_rerank() {
+    let config = RerankConfig {
+        model: "bge-reranker-v2-m3".to_string(),
+        top_n: 3,
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(json, r#"{"model":"bge-reranker-v2-m3","top_n":3}"#);
+    assert_eq!(serde_json::from_str::<RerankConfig>(&json).unwrap(), config);
+    assert!(config.validate(10).is_ok());
+    assert!(config.validate(3).is_ok());
+    assert!(config.validate(2).is_err());
+
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 10)
+            .with_rerank(config.clone())
+            .build();
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""rerank":{"model":"bge-reranker-v2-m3","top_n":3}"#));
+    let request: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(request.rerank, Some(config.clone()));
+    assert!(request.validate().is_ok());
+
+    // `top_n` must not exceed `limit`
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 2)
+            .with_rerank(config)
+            .build();
+    assert!(request.validate().is_err());
+
+    // skipped when none
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 2);
+    assert!(!serde_json::to_string(&request).unwrap().contains("rerank"));
+}
+
+/// Truncates an embedding to its first `target_dim` dimensions and L2-renormalizes it, e.g. to fit the embedding of a Matryoshka model into a collection of a lower dimension.
+///
+/// # Arguments
+///
+/// * `v` - The embedding to truncate.
+///
+/// * `target_dim` - The dimension of the truncated embedding. Must be in the range of `1..=v.len()`.
+pub fn truncate_embedding(v: &[f32], target_dim: usize) -> Result<Vec<f32>, EndpointError> {
+    if target_dim == 0 || target_dim > v.len() {
+        return Err(EndpointError::InvalidArgument(format!(
+            "The target dimension must be in the range of 1..={}, but got {}.",
+            v.len(),
+            target_dim
+        )));
+    }
+
+    let truncated = &v[..target_dim];
+    let norm = truncated.iter().map(|x| x * x).sum::<f32>().sqrt();
+    match norm > 0.0 {
+        true => Ok(truncated.iter().map(|x| x / norm).collect()),
+        false => Ok(truncated.to_vec()),
+    }
+}
+
+#[test]
+fn test_rag_truncate_embedding() {
+    let v = [0.6, 0.8, 0.0, 0.0, 0.5];
+
+    let truncated = truncate_embedding(&v, 2).unwrap();
+    assert_eq!(truncated.len(), 2);
+    assert!((truncated[0] - 0.6).abs() < 1e-6);
+    assert!((truncated[1] - 0.8).abs() < 1e-6);
+
+    let truncated = truncate_embedding(&[3.0, 4.0, 12.0], 2).unwrap();
+    assert!((truncated[0] - 0.6).abs() < 1e-6);
+    assert!((truncated[1] - 0.8).abs() < 1e-6);
+    let norm = truncated.iter().map(|x| x * x).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() < 1e-6);
+
+    // keeping all dimensions only renormalizes
+    let truncated = truncate_embedding(&[3.0, 4.0], 2).unwrap();
+    assert!((truncated[0] - 0.6).abs() < 1e-6);
+
+    // a zero vector stays as is
+    assert_eq!(truncate_embedding(&v[2..4], 1).unwrap(), vec![0.0]);
+
+    assert_eq!(
+        truncate_embedding(&v, 6),
+        Err(EndpointError::InvalidArgument(
+            "The target dimension must be in the range of 1..=5, but got 6.".to_string()
+        ))
+    );
+    assert!(truncate_embedding(&v, 0).is_err());
+}
+
+#[test]
+fn test_rag_validate_score_calibration() {
+    assert!(ScoreCalibration::Linear.validate().is_ok());
+    assert!(ScoreCalibration::Sigmoid {
+        midpoint: 0.5,
+        steepness: 10.0,
+    }
+    .validate()
+    .is_ok());
+    assert!(ScoreCalibration::Sigmoid {
+        midpoint: 0.5,
+        steepness: 0.0,
+    }
+    .validate()
+    .is_err());
+    assert!(ScoreCalibration::Sigmoid {
+        midpoint: f32::NAN,
+        steepness: 1.0,
+    }
+    .validate()
+    .is_err());
+
+    let mut request =
+        RagChatCompletionsRequest::from_prompt("Hello", "http://localhost:6333", "default", 1);
+    request.score_calibration = Some(ScoreCalibration::Sigmoid {
+        midpoint: 0.5,
+        steepness: -1.0,
+    });
+    assert!(request.validate().is_err());
+
+    let json = serde_json::to_string(&ScoreCalibration::Sigmoid {
+        midpoint: 0.5,
+        steepness: 10.0,
+    })
+    .unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"sigmoid","midpoint":0.5,"steepness":10.0}"#
+    );
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunksRequest {
+    pub id: String,
+    pub filename: String,
+    pub chunk_capacity: usize,
+    /// The charset of the file, such as `latin1` or `utf-16`, which is transcoded to UTF-8 before chunking. Any [WHATWG encoding label](https://encoding.spec.whatwg.org/#names-and-labels) is recognized.
+    /// Defaults to UTF-8, with invalid byte sequences replaced by `U+FFFD`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    /// How to split the file into chunks.
+    /// Defaults to None, which means fixed-size chunks of `chunk_capacity` tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<ChunkingStrategy>,
+    /// The number of tokens shared by adjacent chunks, which must be strictly less than the capacity of the chunks, i.e. the `capacity` of the chunking strategy if it has one, or else `chunk_capacity`.
+    /// Defaults to None, which means no overlap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_overlap: Option<usize>,
+}
+impl ChunksRequest {
+    /// Creates a new request for chunking an uploaded file.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the uploaded file.
+    ///
+    /// * `filename` - The name of the uploaded file.
+    ///
+    /// * `chunk_capacity` - The max number of tokens in each chunk.
+    pub fn new(id: impl Into<String>, filename: impl Into<String>, chunk_capacity: usize) -> Self {
+        ChunksRequest {
+            id: id.into(),
+            filename: filename.into(),
+            chunk_capacity,
+            charset: None,
+            strategy: None,
+            chunk_overlap: None,
+        }
+    }
+
+    /// Returns the chunking strategy, i.e. `strategy` if set, or else fixed-size chunks of `chunk_capacity` tokens.
+    pub fn chunking_strategy(&self) -> ChunkingStrategy {
+        self.strategy
+            .clone()
+            .unwrap_or(ChunkingStrategy::FixedCapacity {
+                capacity: self.chunk_capacity,
+            })
+    }
+
+    /// Returns the max number of tokens in each chunk, i.e. the `capacity` of the chunking strategy if it has one, or else `chunk_capacity`.
+    pub fn capacity(&self) -> usize {
+        match &self.strategy {
+            Some(ChunkingStrategy::FixedCapacity { capacity })
+            | Some(ChunkingStrategy::Recursive { capacity, .. }) => *capacity,
+            _ => self.chunk_capacity,
+        }
+    }
+
+    /// Checks that `charset` is a recognized encoding label, that `chunk_overlap` is less than the [capacity](ChunksRequest::capacity) of the chunks, and that the parameters of the chunking strategy are valid.
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        self.encoding()?;
+
+        if let Some(overlap) = self.chunk_overlap {
+            if overlap >= self.capacity() {
+                return Err(ChunkError::OverlapTooLarge {
+                    overlap,
+                    capacity: self.capacity(),
+                });
+            }
+        }
+
+        Ok(self.chunking_strategy().validate()?)
+    }
+
+    /// Transcodes the contents of the file from `charset` to UTF-8. A byte order mark takes precedence over `charset`.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, EndpointError> {
+        let (text, _, _) = self.encoding()?.decode(bytes);
+        Ok(text.into_owned())
+    }
+
+    fn encoding(&self) -> Result<&'static Encoding, EndpointError> {
+        match &self.charset {
+            Some(charset) => Encoding::for_label(charset.trim().as_bytes()).ok_or_else(|| {
+                EndpointError::InvalidArgument(format!("Unrecognized charset: {}", charset))
+            }),
+            None => Ok(UTF_8),
+        }
+    }
+}
+
+#[test]
+fn test_rag_chunks_request_charset() {
+    // "café crème" in Latin-1
+    let latin1 = b"caf\xe9 cr\xe8me";
+
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_charset("latin1")
+        .build();
+    assert!(request.validate().is_ok());
+    assert_eq!(request.decode(latin1).unwrap(), "café crème");
+    let json = serde_json::to_string(&request).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":"file_123","filename":"menu.txt","chunk_capacity":100,"charset":"latin1"}"#
+    );
+
+    // UTF-16 with a byte order mark
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_charset("UTF-16")
+        .build();
+    let utf16: Vec<u8> = [0xfeff_u16]
+        .into_iter()
+        .chain("café".encode_utf16())
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    assert_eq!(request.decode(&utf16).unwrap(), "café");
+
+    // UTF-8 by default, with a lossy fallback
+    let request = ChunksRequest::new("file_123", "menu.txt", 100);
+    assert!(request.validate().is_ok());
+    assert_eq!(request.decode("café".as_bytes()).unwrap(), "café");
+    assert_eq!(request.decode(latin1).unwrap(), "caf\u{fffd} cr\u{fffd}me");
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("charset"));
+
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_charset("klingon")
+        .build();
+    assert!(request.validate().is_err());
+    assert!(request.decode(latin1).is_err());
+}
+
+#[test]
+fn test_rag_chunks_request_overlap() {
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_chunk_overlap(20)
+        .build();
+    assert!(request.validate().is_ok());
+    let json = serde_json::to_string(&request).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":"file_123","filename":"menu.txt","chunk_capacity":100,"chunk_overlap":20}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<ChunksRequest>(&json).unwrap(),
+        request
+    );
+
+    // no overlap by default
+    let request: ChunksRequest =
+        serde_json::from_str(r#"{"id":"file_123","filename":"menu.txt","chunk_capacity":100}"#)
+            .unwrap();
+    assert!(request.chunk_overlap.is_none());
+    assert!(request.validate().is_ok());
+    assert!(!serde_json::to_string(&request)
+        .unwrap()
+        .contains("chunk_overlap"));
+
+    // the overlap must be strictly less than the capacity
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_chunk_overlap(99)
+        .build();
+    assert!(request.validate().is_ok());
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_chunk_overlap(100)
+        .build();
+    assert!(request.validate().is_err());
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_chunk_overlap(150)
+        .build();
+    assert_eq!(
+        request.validate(),
+        Err(ChunkError::OverlapTooLarge {
+            overlap: 150,
+            capacity: 100
+        })
+    );
+
+    // the capacity of the strategy takes precedence over `chunk_capacity`
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_strategy(ChunkingStrategy::FixedCapacity { capacity: 40 })
+        .with_chunk_overlap(50)
+        .build();
+    assert_eq!(request.capacity(), 40);
+    assert_eq!(
+        request.validate(),
+        Err(ChunkError::OverlapTooLarge {
+            overlap: 50,
+            capacity: 40
+        })
+    );
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 10)
+        .with_strategy(ChunkingStrategy::Recursive {
+            separators: vec!["\n".to_string()],
+            capacity: 200,
+        })
+        .with_chunk_overlap(50)
+        .build();
+    assert_eq!(request.capacity(), 200);
+    assert!(request.validate().is_ok());
+
+    // other errors are kept
+    let request = ChunksRequestBuilder::new("file_123", "menu.txt", 100)
+        .with_charset("klingon")
+        .build();
+    assert!(matches!(request.validate(), Err(ChunkError::Invalid(_))));
+}
+
+/// Request builder for creating a new chunks request.
+pub struct ChunksRequestBuilder {
+    req: ChunksRequest,
+}
+impl ChunksRequestBuilder {
+    /// Creates a new builder.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the uploaded file.
+    ///
+    /// * `filename` - The name of the uploaded file.
+    ///
+    /// * `chunk_capacity` - The max number of tokens in each chunk.
+    pub fn new(id: impl Into<String>, filename: impl Into<String>, chunk_capacity: usize) -> Self {
+        Self {
+            req: ChunksRequest::new(id, filename, chunk_capacity),
+        }
+    }
+
+    /// Sets the charset of the file.
+    pub fn with_charset(mut self, charset: impl Into<String>) -> Self {
+        self.req.charset = Some(charset.into());
+        self
+    }
+
+    /// Sets how to split the file into chunks.
+    pub fn with_strategy(mut self, strategy: ChunkingStrategy) -> Self {
+        self.req.strategy = Some(strategy);
+        self
+    }
+
+    /// Sets the number of tokens shared by adjacent chunks.
+    pub fn with_chunk_overlap(mut self, chunk_overlap: usize) -> Self {
+        self.req.chunk_overlap = Some(chunk_overlap);
+        self
+    }
+
+    pub fn build(self) -> ChunksRequest {
+        self.req
+    }
+}
+
+/// Defines how a file is split into chunks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkingStrategy {
+    /// Chunks of up to `capacity` tokens.
+    FixedCapacity {
+        /// The max number of tokens in each chunk. Must be positive.
+        capacity: usize,
+    },
+    /// Chunks of up to `max_sentences` whole sentences.
+    Sentence {
+        /// The max number of sentences in each chunk. Must be positive.
+        max_sentences: usize,
+    },
+    /// Chunks following the structure of a markdown document.
+    Markdown {
+        /// Whether to start a new chunk at each heading.
+        split_on_headings: bool,
+    },
+    /// Chunks of up to `capacity` tokens, split at the first of `separators` that yields small enough pieces, e.g. paragraphs, then lines, then words.
+    Recursive {
+        /// The separators to split at, from the coarsest to the finest. Must not be empty.
+        separators: Vec<String>,
+        /// The max number of tokens in each chunk. Must be positive.
+        capacity: usize,
+    },
+}
+impl ChunkingStrategy {
+    /// Checks that the parameters of the strategy are valid.
+    pub fn validate(&self) -> Result<(), EndpointError> {
+        match self {
+            ChunkingStrategy::FixedCapacity { capacity: 0 }
+            | ChunkingStrategy::Recursive { capacity: 0, .. } => Err(
+                EndpointError::InvalidArgument("The chunk capacity must be positive.".to_string()),
+            ),
+            ChunkingStrategy::Sentence { max_sentences: 0 } => Err(EndpointError::InvalidArgument(
+                "The max number of sentences in a chunk must be positive.".to_string(),
+            )),
+            ChunkingStrategy::Recursive { separators, .. } if separators.is_empty() => {
+                Err(EndpointError::InvalidArgument(
+                    "The recursive chunking strategy requires at least one separator.".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_rag_serialize_chunking_strategy() {
+    let strategies = [
+        (
+            ChunkingStrategy::FixedCapacity { capacity: 100 },
+            r#"{"type":"fixed_capacity","capacity":100}"#,
+        ),
+        (
+            ChunkingStrategy::Sentence { max_sentences: 5 },
+            r#"{"type":"sentence","max_sentences":5}"#,
+        ),
+        (
+            ChunkingStrategy::Markdown {
+                split_on_headings: true,
+            },
+            r#"{"type":"markdown","split_on_headings":true}"#,
+        ),
+        (
+            ChunkingStrategy::Recursive {
+                separators: vec!["\n\n".to_string(), "\n".to_string(), " ".to_string()],
+                capacity: 200,
+            },
+            r#"{"type":"recursive","separators":["\n\n","\n"," "],"capacity":200}"#,
+        ),
+    ];
+    for (strategy, expected) in strategies {
+        assert!(strategy.validate().is_ok());
+        let json = serde_json::to_string(&strategy).unwrap();
+        assert_eq!(json, expected);
+        assert_eq!(
+            serde_json::from_str::<ChunkingStrategy>(&json).unwrap(),
+            strategy
+        );
+
+        let request = ChunksRequestBuilder::new("file_123", "test.md", 100)
+            .with_strategy(strategy.clone())
+            .build();
+        let json = serde_json::to_string(&request).unwrap();
+        let request: ChunksRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(request.chunking_strategy(), strategy);
+    }
+
+    assert!(ChunkingStrategy::FixedCapacity { capacity: 0 }
+        .validate()
+        .is_err());
+    assert!(ChunkingStrategy::Sentence { max_sentences: 0 }
+        .validate()
+        .is_err());
+    assert!(ChunkingStrategy::Recursive {
+        separators: vec![],
+        capacity: 200
+    }
+    .validate()
+    .is_err());
+}
+
+#[test]
+fn test_rag_chunking_strategy_fallback() {
+    // legacy payloads without a strategy chunk by `chunk_capacity`
+    let json = r#"{"id":"file_123","filename":"test.md","chunk_capacity":100}"#;
+    let request: ChunksRequest = serde_json::from_str(json).unwrap();
+    assert!(request.strategy.is_none());
+    assert_eq!(
+        request.chunking_strategy(),
+        ChunkingStrategy::FixedCapacity { capacity: 100 }
+    );
+    assert!(request.validate().is_ok());
+    assert_eq!(serde_json::to_string(&request).unwrap(), json);
+
+    let request = ChunksRequest::new("file_123", "test.md", 0);
+    assert!(request.validate().is_err());
+}
+
+#[test]
+fn test_rag_partial_eq() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+        None,
+    )];
+    let request =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 5);
+    assert_eq!(
+        request,
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 5)
+    );
+    assert_ne!(
+        request,
+        RagChatCompletionsRequest::new(messages, "http://localhost:6333", "default", 3)
+    );
+
+    let input = vec!["chunk 1".to_string()];
+    let request = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    assert_eq!(
+        request,
+        RagEmbeddingRequest::new(&input, "http://localhost:6333", "default")
+    );
+    assert_ne!(
+        request,
+        RagEmbeddingRequest::new(&input, "http://localhost:6333", "other")
+    );
+
+    assert_eq!(
+        ChunksRequest::new("file_123", "test.md", 100),
+        ChunksRequest::new("file_123", "test.md", 100)
+    );
+    assert_ne!(
+        ChunksRequest::new("file_123", "test.md", 100),
+        ChunksRequest::new("file_123", "test.md", 50)
+    );
+
+    // a NaN score makes a point unequal to itself
+    let point = RagScoredPoint {
+        source: "source".to_string(),
+        score: f32::NAN,
+        ..Default::default()
+    };
+    assert_ne!(point, point.clone());
+}
+
+/// Guards the construction paths of the request types from drifting apart: for each type, two paths that do not delegate to each other must produce the same JSON.
+#[test]
+fn test_rag_constructor_builder_parity() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+        None,
+    )];
+
+    // `new` delegates to the builder, so it is compared with the conversion from a chat request
+    let from_new =
+        RagChatCompletionsRequest::new(messages.clone(), "http://localhost:6333", "default", 5);
+    let mut from_chat_request = RagChatCompletionsRequest::from_chat_completions_request(
+        from_new.as_chat_completions_request(),
+        "http://localhost:6333",
+        "default",
+        5,
+    );
+    // a chat request carries no embedding settings
+    assert!(from_chat_request.encoding_format.is_none());
+    from_chat_request.encoding_format = from_new.encoding_format.clone();
+    assert_eq!(
+        serde_json::to_string(&from_new).unwrap(),
+        serde_json::to_string(&from_chat_request).unwrap()
+    );
+
+    // the borrowing and the owning conversions into a chat request
+    let request = || {
+        RagChatCompletionRequestBuilder::new(
+            messages.clone(),
+            "http://localhost:6333",
+            "default",
+            5,
+        )
+        .with_sampling(ChatCompletionRequestSampling::Temperature(0.5))
+        .with_max_tokens(256)
+        .with_stop(vec!["</s>".to_string()])
+        .with_user("user")
+        .with_seed(42)
+        .build()
+    };
+    assert_eq!(
+        serde_json::to_string(&request().as_chat_completions_request()).unwrap(),
+        serde_json::to_string(&ChatCompletionRequest::from(request())).unwrap()
+    );
+
+    let input = vec!["chunk 1".to_string(), "chunk 2".to_string()];
+    let from_new = RagEmbeddingRequest::new(&input, "http://localhost:6333", "default");
+    let from_embedding_request = RagEmbeddingRequest::from_embedding_request(
+        EmbeddingRequest {
+            model: DUMMY_EMBEDDING_MODEL.to_string(),
+            input: input.as_slice().into(),
+            encoding_format: None,
+            user: None,
+            dimensions: None,
+        },
+        "http://localhost:6333",
+        "default",
+    );
+    assert_eq!(
+        serde_json::to_string(&from_new).unwrap(),
+        serde_json::to_string(&from_embedding_request).unwrap()
+    );
+
+    // the builder delegates to `new`, so it is compared with the defaults of the deserialization
+    let from_builder = ChunksRequestBuilder::new("file_123", "test.md", 100).build();
+    let deserialized: ChunksRequest =
+        serde_json::from_str(r#"{"id":"file_123","filename":"test.md","chunk_capacity":100}"#)
+            .unwrap();
+    assert_eq!(
+        serde_json::to_string(&from_builder).unwrap(),
+        serde_json::to_string(&deserialized).unwrap()
+    );
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunksResponse {
+    pub id: String,
+    pub filename: String,
+    pub chunks: Vec<String>,
+    /// The number of tokens of each chunk, i.e. `chunk_token_counts[i]` is the number of tokens of `chunks[i]`, e.g. to verify that the chunks respect the chunk capacity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_token_counts: Option<Vec<usize>>,
+}
+impl ChunksResponse {
+    /// Creates a new response from the chunks of a file along with their numbers of tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the file.
+    ///
+    /// * `filename` - The name of the file.
+    ///
+    /// * `chunks` - The chunks and their numbers of tokens, in order.
+    pub fn with_token_counts(
+        id: impl Into<String>,
+        filename: impl Into<String>,
+        chunks: Vec<(String, usize)>,
+    ) -> Self {
+        let (chunks, chunk_token_counts) = chunks.into_iter().unzip();
+
+        Self {
+            id: id.into(),
+            filename: filename.into(),
+            chunks,
+            chunk_token_counts: Some(chunk_token_counts),
+        }
+    }
+
+    /// Returns the chunks along with their stable ids and indices.
+    pub fn details(&self) -> Vec<ChunkDetail> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .map(|(index, text)| ChunkDetail::new(&self.id, index, text))
+            .collect()
+    }
+
+    /// Merges the chunks of multiple files, e.g. to build a single collection from many files, keeping track of the file each chunk comes from.
+    ///
+    /// The chunks of each file keep their order, and the files are in the order of `responses`.
+    pub fn merge_all(responses: Vec<ChunksResponse>) -> MergedChunks {
+        let chunks = responses
+            .into_iter()
+            .flat_map(|response| {
+                let ChunksResponse {
+                    id,
+                    filename,
+                    chunks,
+                    ..
+                } = response;
+                chunks.into_iter().map(move |text| ChunkWithSource {
+                    filename: filename.clone(),
+                    file_id: id.clone(),
+                    text,
+                })
+            })
+            .collect();
+
+        MergedChunks { chunks }
+    }
+}
+
+/// The chunks of multiple files merged by [ChunksResponse::merge_all].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergedChunks {
+    pub chunks: Vec<ChunkWithSource>,
+}
+
+/// A chunk along with the file it comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkWithSource {
+    /// The name of the file.
+    pub filename: String,
+    /// The id of the file.
+    pub file_id: String,
+    /// The content of the chunk.
+    pub text: String,
+}
+
+/// A chunk of a file along with its stable id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkDetail {
+    /// The id of the chunk, which is the first 16 hex digits of `sha256("{file_id}:{index}:{text}")`. Chunking the same content of the same file always yields the same ids, which makes the ingestion idempotent.
+    pub id: String,
+    /// The index of the chunk in the file.
+    pub index: usize,
+    /// The content of the chunk.
+    pub text: String,
+}
+impl ChunkDetail {
+    /// Creates a new chunk detail, deriving the id from the file id, the index and the content of the chunk.
+    pub fn new(file_id: impl AsRef<str>, index: usize, text: impl Into<String>) -> Self {
+        let text = text.into();
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}:{}:{}", file_id.as_ref(), index, text).as_bytes());
+        let id: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Self {
+            id: id[..16].to_string(),
+            index,
+            text,
+        }
+    }
+}
+
+#[test]
+fn test_rag_chunk_ids() {
+    let response = ChunksResponse {
+        id: "file_123".to_string(),
+        filename: "test.txt".to_string(),
+        chunks: vec!["Hello".to_string(), "world".to_string()],
+        chunk_token_counts: None,
+    };
+
+    let details = response.details();
+    assert_eq!(details.len(), 2);
+    assert_eq!(details[0].index, 0);
+    assert_eq!(details[1].text, "world");
+    assert_eq!(details[0].id.len(), 16);
+    assert!(details[0].id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(details[0].id, details[1].id);
+
+    // re-chunking the same content yields the same ids
+    assert_eq!(response.details(), details);
+    assert_eq!(ChunkDetail::new("file_123", 1, "world").id, details[1].id);
+
+    // changing the content, the index or the file changes the id
+    assert_ne!(ChunkDetail::new("file_123", 1, "world!").id, details[1].id);
+    assert_ne!(ChunkDetail::new("file_123", 0, "world").id, details[1].id);
+    assert_ne!(ChunkDetail::new("file_456", 1, "world").id, details[1].id);
+}
+
+#[test]
+fn test_rag_merge_chunks() {
+    let merged = ChunksResponse::merge_all(vec![
+        ChunksResponse {
+            id: "file_1".to_string(),
+            filename: "a.md".to_string(),
+            chunks: vec!["a1".to_string(), "a2".to_string()],
+            chunk_token_counts: None,
+        },
+        ChunksResponse {
+            id: "file_2".to_string(),
+            filename: "b.txt".to_string(),
+            chunks: vec!["b1".to_string()],
+            chunk_token_counts: Some(vec![1]),
+        },
+    ]);
+
+    assert_eq!(merged.chunks.len(), 3);
+    let texts: Vec<&str> = merged.chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, vec!["a1", "a2", "b1"]);
+    assert_eq!(
+        merged.chunks[1],
+        ChunkWithSource {
+            filename: "a.md".to_string(),
+            file_id: "file_1".to_string(),
+            text: "a2".to_string(),
+        }
+    );
+    assert_eq!(merged.chunks[2].filename, "b.txt");
+    assert_eq!(merged.chunks[2].file_id, "file_2");
+
+    assert!(ChunksResponse::merge_all(vec![]).chunks.is_empty());
+}
+
+#[test]
+fn test_rag_chunk_token_counts() {
+    let response = ChunksResponse::with_token_counts(
+        "file_123",
+        "test.txt",
+        vec![("Hello, world!".to_string(), 4), ("Bye!".to_string(), 2)],
+    );
+    assert_eq!(response.chunks, vec!["Hello, world!", "Bye!"]);
+    assert_eq!(response.chunk_token_counts, Some(vec![4, 2]));
+    assert_eq!(
+        response
+            .chunk_token_counts
+            .as_ref()
+            .map(|counts| counts.len()),
+        Some(response.chunks.len())
+    );
+
+    let json = serde_json::to_string(&response).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":"file_123","filename":"test.txt","chunks":["Hello, world!","Bye!"],"chunk_token_counts":[4,2]}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<ChunksResponse>(&json).unwrap(),
+        response
+    );
+
+    let response = ChunksResponse::with_token_counts("file_123", "test.txt", vec![]);
+    assert!(response.chunks.is_empty());
+    assert_eq!(response.chunk_token_counts, Some(vec![]));
+
+    // the counts are omitted unless present
+    let json = r#"{"id":"file_123","filename":"test.txt","chunks":["Hello, world!"]}"#;
+    let response: ChunksResponse = serde_json::from_str(json).unwrap();
+    assert!(response.chunk_token_counts.is_none());
+    assert_eq!(serde_json::to_string(&response).unwrap(), json);
+}
+
+/// A document to ingest into a knowledge base, independent of the provider it comes from.
+///
+/// The document refers to its content by the id of a file, which must be uploaded first, e.g. through the `/v1/files` endpoint, since it is the uploaded file that is chunked.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    /// The id of the uploaded file of the document.
+    pub id: String,
+    /// The name of the file.
+    pub filename: String,
+    /// The metadata of the document, such as the title or the URL, which is stored in the payload of each chunk.
+    #[serde(default)]
+    pub metadata: HashMap<String, Value>,
+}
+impl Document {
+    /// Creates a request for chunking the document into chunks of up to `chunk_capacity` tokens.
+    pub fn to_chunks_request(&self, chunk_capacity: usize) -> ChunksRequest {
+        ChunksRequest::new(&self.id, &self.filename, chunk_capacity)
+    }
+
+    /// Prepares the ingestion of the document: the returned [ChunksRequest] chunks the document, and the returned [PendingEmbedding] turns the resulting chunks into an embedding request.
+    pub fn prepare_for_ingestion(
+        &self,
+        cfg: &IngestionConfig,
+    ) -> (ChunksRequest, PendingEmbedding) {
+        let mut payload = self.metadata.clone();
+        payload.insert(
+            DOCUMENT_ID_PAYLOAD_KEY.to_string(),
+            Value::from(self.id.as_str()),
+        );
+        payload.insert("filename".to_string(), Value::from(self.filename.as_str()));
+
+        let pending = PendingEmbedding {
+            payload,
+            qdrant_url: cfg.qdrant_url.clone(),
+            qdrant_collection_name: cfg.qdrant_collection_name.clone(),
+        };
+
+        (self.to_chunks_request(cfg.chunk_capacity), pending)
+    }
+}
+
+/// The settings for ingesting a [Document].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestionConfig {
+    /// The max number of tokens in each chunk.
+    pub chunk_capacity: usize,
+    /// The URL of the Qdrant server.
+    pub qdrant_url: String,
+    /// The name of the collection in Qdrant.
+    pub qdrant_collection_name: String,
+}
+
+/// The embedding stage of the ingestion of a [Document], waiting for the chunks of the document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEmbedding {
+    payload: HashMap<String, Value>,
+    qdrant_url: String,
+    qdrant_collection_name: String,
+}
+impl PendingEmbedding {
+    /// Creates the builder of the request for embedding the chunks, with the metadata of the document, its [id](DOCUMENT_ID_PAYLOAD_KEY) and its filename as the payload of each chunk.
+    pub fn with_chunks(self, chunks: &ChunksResponse) -> RagEmbeddingRequestBuilder {
+        let payloads = vec![self.payload; chunks.chunks.len()];
+
+        RagEmbeddingRequestBuilder::new(
+            &chunks.chunks,
+            self.qdrant_url,
+            self.qdrant_collection_name,
+        )
+        .with_payloads(payloads)
+    }
+}
+
+#[test]
+fn test_rag_document_ingestion() {
+    let mut metadata = HashMap::new();
+    metadata.insert("title".to_string(), Value::from("LlamaEdge"));
+    metadata.insert("url".to_string(), Value::from("https://llamaedge.com"));
+    let document = Document {
+        id: "file_123".to_string(),
+        filename: "llamaedge.md".to_string(),
+        metadata,
+    };
+    let cfg = IngestionConfig {
+        chunk_capacity: 100,
+        qdrant_url: "http://localhost:6333".to_string(),
+        qdrant_collection_name: "default".to_string(),
+    };
+
+    let (chunks_request, pending) = document.prepare_for_ingestion(&cfg);
+    assert_eq!(chunks_request, document.to_chunks_request(100));
+    assert_eq!(chunks_request.id, "file_123");
+    assert_eq!(chunks_request.filename, "llamaedge.md");
+    assert_eq!(chunks_request.chunk_capacity, 100);
+
+    let chunks = ChunksResponse {
+        id: chunks_request.id,
+        filename: chunks_request.filename,
+        chunks: vec![
+            "LlamaEdge runs LLMs locally.".to_string(),
+            "It is fast.".to_string(),
+        ],
+        chunk_token_counts: None,
+    };
+    let request = pending.with_chunks(&chunks).try_build().unwrap();
+    assert_eq!(request.qdrant_collection_name, "default");
+    assert_eq!(
+        request.embedding_request.input,
+        InputText::from(vec!["LlamaEdge runs LLMs locally.", "It is fast."])
+    );
+
+    // the metadata is propagated to the payload of each chunk
+    let payloads = request.payloads.as_ref().unwrap();
+    assert_eq!(payloads.len(), 2);
+    for payload in payloads {
+        assert_eq!(payload["title"], "LlamaEdge");
+        assert_eq!(payload["url"], "https://llamaedge.com");
+        assert_eq!(payload[DOCUMENT_ID_PAYLOAD_KEY], "file_123");
+        assert_eq!(payload["filename"], "llamaedge.md");
+    }
+    let points = request
+        .to_qdrant_points(vec![vec![0.5, 0.25], vec![0.125, 1.0]])
+        .unwrap();
+    assert_eq!(points["points"][1]["payload"]["source"], "It is fast.");
+    assert_eq!(points["points"][1]["payload"]["title"], "LlamaEdge");
+
+    // the metadata is optional
+    let document: Document =
+        serde_json::from_str(r#"{"id":"file_456","filename":"notes.txt"}"#).unwrap();
+    assert!(document.metadata.is_empty());
+    let (_, pending) = document.prepare_for_ingestion(&cfg);
+    let chunks = ChunksResponse {
+        id: "file_456".to_string(),
+        filename: "notes.txt".to_string(),
+        chunks: vec!["Hello, world!".to_string()],
+        chunk_token_counts: None,
+    };
+    let request = pending.with_chunks(&chunks).build();
+    assert_eq!(request.payloads.as_ref().unwrap()[0].len(), 2);
+}
+
+/// Represents the result of a retrieval.
+///
+/// Implements `PartialEq` but not `Eq`, because the scores are floats: an object containing a NaN score is not equal to itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetrieveObject {
+    /// The retrieved sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<Vec<RagScoredPoint>>,
+
+    /// The number of similar points to retrieve
+    pub limit: usize,
+
+    /// The score threshold
+    ///
+    /// Note that the threshold applies to the raw scores returned by Qdrant, whose meaning depends on the distance metric of the collection: for `Cosine` and `Dot`, points with a score below the threshold are excluded, while for `Euclid` and `Manhattan`, points with a distance above the threshold are excluded. Use [RetrieveObject::normalized_points] to compare the scores across metrics.
+    pub score_threshold: f32,
+
+    /// The payload filter applied to the vector search, echoing the `filter` of the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Value>,
+}
+impl RetrieveObject {
+    /// Returns the number of distinct documents the points come from, identified by the [DOCUMENT_ID_PAYLOAD_KEY] payload field. Each point without the field counts as a document of its own.
+    pub fn distinct_documents(&self) -> usize {
+        let points = match &self.points {
+            Some(points) => points,
+            None => return 0,
+        };
+
+        let mut document_ids = HashSet::new();
+        let mut unidentified = 0;
+        for point in points {
+            match point
+                .payload
+                .as_ref()
+                .and_then(|payload| payload.get(DOCUMENT_ID_PAYLOAD_KEY))
+            {
+                Some(id) => {
+                    document_ids.insert(id.to_string());
+                }
+                None => unidentified += 1,
+            }
         }
+
+        document_ids.len() + unidentified
     }
 
-    pub fn from_chat_completions_request(
-        chat_completions_request: ChatCompletionRequest,
-        qdrant_url: impl Into<String>,
-        qdrant_collection_name: impl Into<String>,
-        limit: u64,
-    ) -> Self {
-        RagChatCompletionsRequest {
-            chat_model: chat_completions_request.model,
-            messages: chat_completions_request.messages,
-            embedding_model: "dummy-embedding-model".to_string(),
-            encoding_format: None,
-            qdrant_url: qdrant_url.into(),
-            qdrant_collection_name: qdrant_collection_name.into(),
-            limit,
-            temperature: chat_completions_request.temperature,
-            top_p: chat_completions_request.top_p,
-            n_choice: chat_completions_request.n_choice,
-            stream: chat_completions_request.stream,
-            stream_options: chat_completions_request.stream_options,
-            stop: chat_completions_request.stop,
-            max_tokens: chat_completions_request.max_tokens,
-            presence_penalty: chat_completions_request.presence_penalty,
-            frequency_penalty: chat_completions_request.frequency_penalty,
-            logit_bias: chat_completions_request.logit_bias,
-            user: chat_completions_request.user,
-            response_format: chat_completions_request.response_format,
-            tool_choice: chat_completions_request.tool_choice,
-            tools: chat_completions_request.tools,
-            context_window: chat_completions_request.context_window,
+    /// Removes the points with the same `id` as a point with a higher [ranking score](RagScoredPoint::ranking_score), e.g. after merging the points retrieved from multiple collections. Of points with equal scores, the first one is kept. Unless all of the points are reranked, `score` is compared instead.
+    ///
+    /// The kept points stay in their order, and points without an id are always kept.
+    pub fn dedup_by_id(&mut self) {
+        let points = match &mut self.points {
+            Some(points) => points,
+            None => return,
+        };
+        let all_reranked = all_reranked(points.iter());
+
+        // the index of the best point of each id
+        let mut best: HashMap<&str, usize> = HashMap::new();
+        for (index, point) in points.iter().enumerate() {
+            if let Some(id) = point.id.as_deref() {
+                best.entry(id)
+                    .and_modify(|best_index| {
+                        if point.ranking_score_among(all_reranked)
+                            > points[*best_index].ranking_score_among(all_reranked)
+                        {
+                            *best_index = index;
+                        }
+                    })
+                    .or_insert(index);
+            }
+        }
+        let kept: HashSet<usize> = best.into_values().collect();
+
+        let mut index = 0;
+        points.retain(|point| {
+            let keep = point.id.is_none() || kept.contains(&index);
+            index += 1;
+            keep
+        });
+    }
+
+    /// Returns the retrieved points sorted in descending order by [RagScoredPoint::ranking_score] if all of them are reranked, or else by `score`. Points with equal scores keep their order. Returns an empty vector if there is no point.
+    pub fn sorted_points(&self) -> Vec<&RagScoredPoint> {
+        let mut points: Vec<&RagScoredPoint> = self.points.iter().flatten().collect();
+        let all_reranked = all_reranked(points.iter().copied());
+        points.sort_by(|a, b| {
+            b.ranking_score_among(all_reranked)
+                .total_cmp(&a.ranking_score_among(all_reranked))
+        });
+        points
+    }
+
+    /// Returns the source of the best match, i.e. the first of [RetrieveObject::sorted_points], or `None` if there is no point.
+    pub fn top_source(&self) -> Option<&str> {
+        self.sorted_points()
+            .first()
+            .map(|point| point.source.as_str())
+    }
+
+    /// Renders the retrieved points as an aligned table with the columns rank, score (with 3 decimals) and source, e.g. for CLI tools.
+    ///
+    /// Line breaks in the sources are replaced with spaces, and sources longer than `max_source_width` characters are truncated with an ellipsis. Returns `"No points retrieved."` if there is no point.
+    pub fn to_table(&self, max_source_width: usize) -> String {
+        let points = match &self.points {
+            Some(points) if !points.is_empty() => points,
+            _ => return "No points retrieved.".to_string(),
+        };
+
+        let rows: Vec<[String; 3]> = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let source = point.source.replace(['\r', '\n'], " ");
+                let source = match source.chars().count() > max_source_width {
+                    true if max_source_width > 0 => {
+                        let mut truncated: String =
+                            source.chars().take(max_source_width - 1).collect();
+                        truncated.push('…');
+                        truncated
+                    }
+                    true => String::new(),
+                    false => source,
+                };
+                [(i + 1).to_string(), format!("{:.3}", point.score), source]
+            })
+            .collect();
+
+        let header = [
+            "Rank".to_string(),
+            "Score".to_string(),
+            "Source".to_string(),
+        ];
+        let mut widths = header.clone().map(|cell| cell.chars().count());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        std::iter::once(&header)
+            .chain(rows.iter())
+            .map(|row| {
+                let line = format!(
+                    "{:>rank$}  {:>score$}  {}",
+                    row[0],
+                    row[1],
+                    row[2],
+                    rank = widths[0],
+                    score = widths[1],
+                );
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Renders the retrieved points as CSV with the columns `query`, `rank`, `score` and `source`, e.g. for spreadsheets. The first row is the header, and each line ends with `\n`.
+    ///
+    /// Fields containing commas, quotes or line breaks are quoted, with the quotes doubled, as per RFC 4180.
+    pub fn to_csv(&self, query: &str) -> String {
+        let mut csv = String::from("query,rank,score,source\n");
+        for (i, point) in self.points.iter().flatten().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                escape_csv_field(query),
+                i + 1,
+                point.score,
+                escape_csv_field(&point.source)
+            ));
+        }
+
+        csv
+    }
+
+    /// Returns the [RFC 6902](https://datatracker.ietf.org/doc/html/rfc6902) JSON Patch turning this object into `newer`, e.g. for updating a live preview of the retrieval incrementally.
+    ///
+    /// Points are identified by their `id`, or by their `source` if they have no id. Points missing in `newer` are removed first, then the remaining points are moved into the order of `newer`, new points are added, and changed points are replaced. Changes of the other fields are replaced as well.
+    pub fn json_patch(&self, newer: &RetrieveObject) -> Value {
+        let mut patch = vec![];
+
+        match (&self.points, &newer.points) {
+            (None, None) => {}
+            (None, Some(points)) => patch.push(serde_json::json!({
+                "op": "add",
+                "path": "/points",
+                "value": serde_json::to_value(points).unwrap_or_default(),
+            })),
+            (Some(_), None) => patch.push(serde_json::json!({ "op": "remove", "path": "/points" })),
+            (Some(older), Some(newer)) => patch.extend(points_patch(older, newer)),
+        }
+
+        if self.limit != newer.limit {
+            patch.push(
+                serde_json::json!({ "op": "replace", "path": "/limit", "value": newer.limit }),
+            );
+        }
+        if self.score_threshold != newer.score_threshold {
+            patch.push(serde_json::json!({
+                "op": "replace",
+                "path": "/score_threshold",
+                "value": newer.score_threshold,
+            }));
+        }
+        match (&self.filter, &newer.filter) {
+            (None, Some(filter)) => {
+                patch.push(serde_json::json!({ "op": "add", "path": "/filter", "value": filter }))
+            }
+            (Some(_), None) => patch.push(serde_json::json!({ "op": "remove", "path": "/filter" })),
+            (Some(older), Some(filter)) if older != filter => patch
+                .push(serde_json::json!({ "op": "replace", "path": "/filter", "value": filter })),
+            _ => {}
+        }
+
+        Value::Array(patch)
+    }
+
+    /// Returns the retrieved points with their raw scores rewritten into similarities between 0.0 and 1.0 by [Distance::normalize_score], so that they are comparable across distance metrics.
+    ///
+    /// # Arguments
+    ///
+    /// * `distance` - The distance metric of the collection the points are retrieved from.
+    pub fn normalized_points(&self, distance: Distance) -> Vec<RagScoredPoint> {
+        self.points
+            .iter()
+            .flatten()
+            .map(|point| RagScoredPoint {
+                score: distance.normalize_score(point.score),
+                ..point.clone()
+            })
+            .collect()
+    }
+
+    /// Computes the statistics of the lengths (in characters) of the retrieved sources, which helps to tune the `chunk_capacity` of a collection. Returns `None` if no point is retrieved.
+    pub fn source_length_stats(&self) -> Option<LengthStats> {
+        let mut lengths: Vec<usize> = self
+            .points
+            .as_ref()?
+            .iter()
+            .map(|point| point.source.chars().count())
+            .collect();
+        if lengths.is_empty() {
+            return None;
+        }
+        lengths.sort_unstable();
+
+        let n = lengths.len();
+        let median = match n % 2 {
+            0 => (lengths[n / 2 - 1] + lengths[n / 2]) as f64 / 2.0,
+            _ => lengths[n / 2] as f64,
+        };
+
+        Some(LengthStats {
+            min: lengths[0],
+            max: lengths[n - 1],
+            mean: lengths.iter().sum::<usize>() as f64 / n as f64,
+            median,
+        })
+    }
+}
+
+/// Statistics of the lengths (in characters) of a set of texts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LengthStats {
+    /// The length of the shortest text.
+    pub min: usize,
+    /// The length of the longest text.
+    pub max: usize,
+    /// The mean length of the texts.
+    pub mean: f64,
+    /// The median length of the texts.
+    pub median: f64,
+}
+
+#[test]
+fn test_rag_source_length_stats() {
+    let retrieve_object = |sources: &[&str]| RetrieveObject {
+        points: Some(
+            sources
+                .iter()
+                .map(|source| RagScoredPoint {
+                    source: source.to_string(),
+                    score: 0.5,
+                    ..Default::default()
+                })
+                .collect(),
+        ),
+        limit: 5,
+        score_threshold: 0.0,
+        filter: None,
+    };
+
+    // lengths: 1, 3, 4, 8. Lengths are counted in characters, not bytes.
+    let stats = retrieve_object(&["a", "abc", "ab\u{e9}d", "abcdefgh"])
+        .source_length_stats()
+        .unwrap();
+    assert_eq!(
+        stats,
+        LengthStats {
+            min: 1,
+            max: 8,
+            mean: 4.0,
+            median: 3.5,
+        }
+    );
+
+    // lengths: 2, 5, 11
+    let stats = retrieve_object(&["ab", "hello", "hello world"])
+        .source_length_stats()
+        .unwrap();
+    assert_eq!(stats.min, 2);
+    assert_eq!(stats.max, 11);
+    assert_eq!(stats.mean, 6.0);
+    assert_eq!(stats.median, 5.0);
+
+    assert!(retrieve_object(&[]).source_length_stats().is_none());
+    assert!(RetrieveObject::default().source_length_stats().is_none());
+}
+
+#[test]
+fn test_rag_retrieve_object_to_table() {
+    let retrieve_object = RetrieveObject {
+        points: Some(vec![
+            RagScoredPoint {
+                source: "LlamaEdge is the easiest way to run LLMs".to_string(),
+                score: 0.91234,
+                ..Default::default()
+            },
+            RagScoredPoint {
+                source: "它可以在本地运行大模型".to_string(),
+                score: 0.5,
+                ..Default::default()
+            },
+            RagScoredPoint {
+                source: "short\nsource".to_string(),
+                score: 0.0456,
+                ..Default::default()
+            },
+        ]),
+        limit: 10,
+        score_threshold: 0.0,
+        filter: None,
+    };
+
+    let expected = [
+        "Rank  Score  Source",
+        "   1  0.912  LlamaEdge…",
+        "   2  0.500  它可以在本地运行大…",
+        "   3  0.046  short sou…",
+    ]
+    .join("\n");
+    assert_eq!(retrieve_object.to_table(10), expected);
+
+    let expected = [
+        "Rank  Score  Source",
+        "   1  0.912  LlamaEdge is the easiest way to run LLMs",
+        "   2  0.500  它可以在本地运行大模型",
+        "   3  0.046  short source",
+    ]
+    .join("\n");
+    assert_eq!(retrieve_object.to_table(100), expected);
+
+    assert_eq!(
+        RetrieveObject::default().to_table(10),
+        "No points retrieved."
+    );
+}
+
+/// Returns the JSON Patch operations turning the `older` points into the `newer` ones, see [RetrieveObject::json_patch].
+fn points_patch(older: &[RagScoredPoint], newer: &[RagScoredPoint]) -> Vec<Value> {
+    fn key(point: &RagScoredPoint) -> (bool, &str) {
+        match &point.id {
+            Some(id) => (true, id.as_str()),
+            None => (false, point.source.as_str()),
+        }
+    }
+
+    let mut patch = vec![];
+
+    // remove the points missing in `newer`, counting duplicates
+    let mut remaining: HashMap<(bool, &str), usize> = HashMap::new();
+    for point in newer {
+        *remaining.entry(key(point)).or_default() += 1;
+    }
+    let mut kept = vec![true; older.len()];
+    for (i, point) in older.iter().enumerate() {
+        match remaining.get_mut(&key(point)) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => kept[i] = false,
+        }
+    }
+    for i in (0..older.len()).rev().filter(|&i| !kept[i]) {
+        patch.push(serde_json::json!({ "op": "remove", "path": format!("/points/{}", i) }));
+    }
+    let mut current: Vec<&RagScoredPoint> = older
+        .iter()
+        .zip(kept)
+        .filter_map(|(point, kept)| kept.then_some(point))
+        .collect();
+
+    // move, add and replace the points in the order of `newer`
+    for (i, point) in newer.iter().enumerate() {
+        match (i..current.len()).find(|&j| key(current[j]) == key(point)) {
+            Some(j) => {
+                if j != i {
+                    patch.push(serde_json::json!({
+                        "op": "move",
+                        "from": format!("/points/{}", j),
+                        "path": format!("/points/{}", i),
+                    }));
+                    let moved = current.remove(j);
+                    current.insert(i, moved);
+                }
+                if current[i] != point {
+                    patch.push(serde_json::json!({
+                        "op": "replace",
+                        "path": format!("/points/{}", i),
+                        "value": serde_json::to_value(point).unwrap_or_default(),
+                    }));
+                    current[i] = point;
+                }
+            }
+            None => {
+                patch.push(serde_json::json!({
+                    "op": "add",
+                    "path": format!("/points/{}", i),
+                    "value": serde_json::to_value(point).unwrap_or_default(),
+                }));
+                current.insert(i, point);
+            }
         }
     }
+
+    patch
+}
+
+#[test]
+fn test_rag_retrieve_object_dedup_by_id() {
+    let point = |id: Option<&str>, source: &str, score: f32| RagScoredPoint {
+        source: source.to_string(),
+        score,
+        id: id.map(|id| id.to_string()),
+        ..Default::default()
+    };
+    let mut retrieve_object = RetrieveObject {
+        points: Some(vec![
+            point(Some("1"), "LlamaEdge from docs", 0.5),
+            point(Some("2"), "WasmEdge", 0.625),
+            point(None, "Rust", 0.25),
+            point(Some("1"), "LlamaEdge from faq", 0.75),
+            point(None, "Rust", 0.25),
+            point(Some("2"), "WasmEdge again", 0.625),
+        ]),
+        limit: 6,
+        score_threshold: 0.0,
+        filter: None,
+    };
+
+    retrieve_object.dedup_by_id();
+    let sources: Vec<&str> = retrieve_object
+        .points
+        .iter()
+        .flatten()
+        .map(|point| point.source.as_str())
+        .collect();
+    assert_eq!(
+        sources,
+        vec!["WasmEdge", "Rust", "LlamaEdge from faq", "Rust"]
+    );
+
+    // the rerank scores are only compared if all of the points are reranked
+    let mut retrieve_object = RetrieveObject {
+        points: Some(vec![
+            point(Some("1"), "vector", 0.75),
+            RagScoredPoint {
+                rerank_score: Some(0.875),
+                ..point(Some("1"), "reranked", 0.5)
+            },
+        ]),
+        limit: 2,
+        score_threshold: 0.0,
+        filter: None,
+    };
+    let mut reranked = retrieve_object.clone();
+    retrieve_object.dedup_by_id();
+    assert_eq!(retrieve_object.points.unwrap()[0].source, "vector");
+    reranked.points.as_mut().unwrap()[0].rerank_score = Some(0.25);
+    reranked.dedup_by_id();
+    assert_eq!(reranked.points.unwrap()[0].source, "reranked");
+
+    let mut retrieve_object = RetrieveObject::default();
+    retrieve_object.dedup_by_id();
+    assert!(retrieve_object.points.is_none());
+}
+
+#[test]
+fn test_rag_retrieve_object_sorted_points() {
+    let point = |source: &str, score: f32| RagScoredPoint {
+        source: source.to_string(),
+        score,
+        ..Default::default()
+    };
+    let retrieve_object = RetrieveObject {
+        points: Some(vec![
+            point("Rust", 0.25),
+            point("LlamaEdge", 0.75),
+            point("WasmEdge", 0.5),
+            point("WASI", 0.75),
+        ]),
+        limit: 4,
+        score_threshold: 0.0,
+        filter: None,
+    };
+
+    // ties keep their order
+    let sources: Vec<&str> = retrieve_object
+        .sorted_points()
+        .iter()
+        .map(|point| point.source.as_str())
+        .collect();
+    assert_eq!(sources, vec!["LlamaEdge", "WASI", "WasmEdge", "Rust"]);
+    assert_eq!(retrieve_object.top_source(), Some("LlamaEdge"));
+
+    // the points themselves are not reordered
+    assert_eq!(retrieve_object.points.as_ref().unwrap()[0].source, "Rust");
+
+    // a rerank score is not compared with the vector scores of the other points
+    let mut retrieve_object = retrieve_object;
+    retrieve_object.points.as_mut().unwrap()[0].rerank_score = Some(0.875);
+    assert_eq!(retrieve_object.top_source(), Some("LlamaEdge"));
+
+    // reranked points are ordered by the rerank score
+    for (point, rerank_score) in retrieve_object
+        .points
+        .as_mut()
+        .unwrap()
+        .iter_mut()
+        .zip([0.875, 0.125, 0.5, 0.25])
+    {
+        point.rerank_score = Some(rerank_score);
+    }
+    let sources: Vec<&str> = retrieve_object
+        .sorted_points()
+        .iter()
+        .map(|point| point.source.as_str())
+        .collect();
+    assert_eq!(sources, vec!["Rust", "WasmEdge", "WASI", "LlamaEdge"]);
+
+    // no points
+    let retrieve_object = RetrieveObject::default();
+    assert!(retrieve_object.sorted_points().is_empty());
+    assert!(retrieve_object.top_source().is_none());
+    let retrieve_object = RetrieveObject {
+        points: Some(vec![]),
+        ..Default::default()
+    };
+    assert!(retrieve_object.sorted_points().is_empty());
+    assert!(retrieve_object.top_source().is_none());
+}
+
+#[test]
+fn test_rag_serialize_point_id() {
+    let point = RagScoredPoint {
+        source: "source".to_string(),
+        score: 0.5,
+        id: Some("42".to_string()),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(json, r#"{"source":"source","score":0.5,"id":"42"}"#);
+    assert_eq!(
+        serde_json::from_str::<RagScoredPoint>(&json).unwrap(),
+        point
+    );
+
+    // Qdrant gives numeric ids as integers
+    let point: RagScoredPoint =
+        serde_json::from_str(r#"{"source":"s","score":0.5,"id":42}"#).unwrap();
+    assert_eq!(point.id.as_deref(), Some("42"));
+    assert_eq!(
+        serde_json::to_string(&point).unwrap(),
+        r#"{"source":"s","score":0.5,"id":"42"}"#
+    );
+    let point: RagScoredPoint = serde_json::from_str(
+        r#"{"source":"s","score":0.5,"id":"5c56c793-69f3-4fbf-87e6-c4bf54c28c26"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        point.id.as_deref(),
+        Some("5c56c793-69f3-4fbf-87e6-c4bf54c28c26")
+    );
+    assert!(
+        serde_json::from_str::<RagScoredPoint>(r#"{"source":"s","score":0.5,"id":-1}"#).is_err()
+    );
+
+    // points without an id keep their shape
+    let point: RagScoredPoint = serde_json::from_str(r#"{"source":"source","score":0.5}"#).unwrap();
+    assert!(point.id.is_none());
+    assert_eq!(
+        serde_json::to_string(&point).unwrap(),
+        r#"{"source":"source","score":0.5}"#
+    );
+}
+
+#[test]
+fn test_rag_retrieve_object_json_patch() {
+    let retrieve_object = |points: &[(&str, f32)], limit: usize| RetrieveObject {
+        points: Some(
+            points
+                .iter()
+                .map(|(source, score)| RagScoredPoint {
+                    source: source.to_string(),
+                    score: *score,
+                    ..Default::default()
+                })
+                .collect(),
+        ),
+        limit,
+        score_threshold: 0.0,
+        filter: None,
+    };
+    let older = retrieve_object(
+        &[("LlamaEdge", 0.75), ("WasmEdge", 0.5), ("llama.cpp", 0.25)],
+        3,
+    );
+
+    // `WasmEdge` is removed, and `Rust` is added
+    let newer = retrieve_object(
+        &[("LlamaEdge", 0.75), ("Rust", 0.625), ("llama.cpp", 0.25)],
+        3,
+    );
+    assert_eq!(
+        older.json_patch(&newer),
+        serde_json::json!([
+            { "op": "remove", "path": "/points/1" },
+            { "op": "add", "path": "/points/1", "value": { "source": "Rust", "score": 0.625 } },
+        ])
+    );
+
+    // reordered and rescored points
+    let mut newer = retrieve_object(&[("llama.cpp", 0.875), ("LlamaEdge", 0.75)], 2);
+    newer.filter = Some(serde_json::json!({ "must": [] }));
+    assert_eq!(
+        older.json_patch(&newer),
+        serde_json::json!([
+            { "op": "remove", "path": "/points/1" },
+            { "op": "move", "from": "/points/1", "path": "/points/0" },
+            { "op": "replace", "path": "/points/0", "value": { "source": "llama.cpp", "score": 0.875 } },
+            { "op": "replace", "path": "/limit", "value": 2 },
+            { "op": "add", "path": "/filter", "value": { "must": [] } },
+        ])
+    );
+
+    // points with ids are identified by their ids
+    let mut older = retrieve_object(&[("LlamaEdge", 0.75)], 1);
+    let mut newer = older.clone();
+    older.points.as_mut().unwrap()[0].id = Some("1".to_string());
+    newer.points.as_mut().unwrap()[0].id = Some("2".to_string());
+    assert_eq!(
+        older.json_patch(&newer),
+        serde_json::json!([
+            { "op": "remove", "path": "/points/0" },
+            { "op": "add", "path": "/points/0", "value": { "source": "LlamaEdge", "score": 0.75, "id": "2" } },
+        ])
+    );
+
+    // identical objects yield an empty patch
+    assert_eq!(older.json_patch(&older.clone()), serde_json::json!([]));
+    assert_eq!(
+        RetrieveObject::default().json_patch(&older),
+        serde_json::json!([
+            { "op": "add", "path": "/points", "value": [{ "source": "LlamaEdge", "score": 0.75, "id": "1" }] },
+            { "op": "replace", "path": "/limit", "value": 1 },
+        ])
+    );
+    assert_eq!(
+        older.json_patch(&RetrieveObject::default()),
+        serde_json::json!([
+            { "op": "remove", "path": "/points" },
+            { "op": "replace", "path": "/limit", "value": 0 },
+        ])
+    );
+}
+
+/// Quotes the field if it contains a comma, a quote or a line break, doubling the quotes.
+fn escape_csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+#[test]
+fn test_rag_retrieve_object_to_csv() {
+    let retrieve_object = RetrieveObject {
+        points: Some(vec![
+            RagScoredPoint {
+                source: "LlamaEdge runs LLMs locally".to_string(),
+                score: 0.75,
+                ..Default::default()
+            },
+            RagScoredPoint {
+                source: "fast, portable, and secure".to_string(),
+                score: 0.5,
+                ..Default::default()
+            },
+            RagScoredPoint {
+                source: "it is \"lightweight\"\nand cross-platform".to_string(),
+                score: 0.25,
+                ..Default::default()
+            },
+        ]),
+        limit: 3,
+        score_threshold: 0.0,
+        filter: None,
+    };
+
+    let expected = [
+        "query,rank,score,source",
+        "What is LlamaEdge?,1,0.75,LlamaEdge runs LLMs locally",
+        "What is LlamaEdge?,2,0.5,\"fast, portable, and secure\"",
+        "What is LlamaEdge?,3,0.25,\"it is \"\"lightweight\"\"\nand cross-platform\"",
+        "",
+    ]
+    .join("\n");
+    assert_eq!(retrieve_object.to_csv("What is LlamaEdge?"), expected);
+
+    // the query is escaped as well
+    assert_eq!(
+        RetrieveObject::default().to_csv("a, b"),
+        "query,rank,score,source\n"
+    );
+    assert!(retrieve_object
+        .to_csv("LlamaEdge, \"WasmEdge\"")
+        .contains("\n\"LlamaEdge, \"\"WasmEdge\"\"\",1,0.75,"));
+}
+
+#[test]
+fn test_rag_normalized_points() {
+    let retrieve_object = |scores: &[f32]| RetrieveObject {
+        points: Some(
+            scores
+                .iter()
+                .map(|score| RagScoredPoint {
+                    source: "source".to_string(),
+                    score: *score,
+                    ..Default::default()
+                })
+                .collect(),
+        ),
+        limit: scores.len(),
+        score_threshold: 0.0,
+        filter: None,
+    };
+    let scores = |points: Vec<RagScoredPoint>| -> Vec<f32> {
+        points.iter().map(|point| point.score).collect()
+    };
+
+    let ro = retrieve_object(&[1.0, 0.0, -1.0]);
+    assert_eq!(
+        scores(ro.normalized_points(Distance::Cosine)),
+        vec![1.0, 0.5, 0.0]
+    );
+    assert_eq!(
+        scores(ro.normalized_points(Distance::Dot)),
+        vec![1.0, 0.5, 0.0]
+    );
+
+    // distances: lower is more similar
+    let ro = retrieve_object(&[0.0, 1.0, 3.0]);
+    assert_eq!(
+        scores(ro.normalized_points(Distance::Euclid)),
+        vec![1.0, 0.5, 0.25]
+    );
+    assert_eq!(
+        scores(ro.normalized_points(Distance::Manhattan)),
+        vec![1.0, 0.5, 0.25]
+    );
+
+    // out-of-range scores are clamped, other fields are kept
+    let ro = retrieve_object(&[1.5, -2.0]);
+    let points = ro.normalized_points(Distance::Dot);
+    assert_eq!(scores(points.clone()), vec![1.0, 0.0]);
+    assert_eq!(points[0].source, "source");
+
+    assert!(RetrieveObject::default()
+        .normalized_points(Distance::Cosine)
+        .is_empty());
 }
 
-/// Request builder for creating a new RAG chat completion request.
-pub struct RagChatCompletionRequestBuilder {
-    req: RagChatCompletionsRequest,
+/// Builder for creating a [RetrieveObject], such as in servers or test fixtures.
+#[derive(Debug, Default)]
+pub struct RetrieveObjectBuilder {
+    points: Vec<RagScoredPoint>,
+    limit: Option<usize>,
+    score_threshold: f32,
+    distance: Distance,
+    filter: Option<Value>,
 }
-impl RagChatCompletionRequestBuilder {
-    /// Creates a new builder with the given model.
-    ///
-    /// # Arguments
-    ///
-    /// * `model` - ID of the model to use.
-    ///
-    /// * `messages` - A list of messages comprising the conversation so far.
-    ///
-    /// * `sampling` - The sampling method to use.
-    pub fn new(
-        messages: Vec<ChatCompletionRequestMessage>,
-        qdrant_url: impl Into<String>,
-        qdrant_collection_name: impl Into<String>,
-        limit: u64,
-    ) -> Self {
-        Self {
-            req: RagChatCompletionsRequest {
-                chat_model: Some("dummy-chat-model".to_string()),
-                messages,
-                embedding_model: "dummy-embedding-model".to_string(),
-                encoding_format: Some("float".to_string()),
-                qdrant_url: qdrant_url.into(),
-                qdrant_collection_name: qdrant_collection_name.into(),
-                limit,
-                temperature: Some(1.0),
-                top_p: Some(1.0),
-                n_choice: Some(1),
-                stream: Some(false),
-                stream_options: None,
-                stop: None,
-                max_tokens: Some(1024),
-                presence_penalty: Some(0.0),
-                frequency_penalty: Some(0.0),
-                logit_bias: None,
-                user: None,
-                response_format: None,
-                tool_choice: None,
-                tools: None,
-                context_window: Some(1),
-            },
-        }
+impl RetrieveObjectBuilder {
+    /// Creates a new builder without any points.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn with_sampling(mut self, sampling: ChatCompletionRequestSampling) -> Self {
-        let (temperature, top_p) = match sampling {
-            ChatCompletionRequestSampling::Temperature(t) => (t, 1.0),
-            ChatCompletionRequestSampling::TopP(p) => (1.0, p),
-        };
-        self.req.temperature = Some(temperature);
-        self.req.top_p = Some(top_p);
+    /// Adds a retrieved point. The points can be added in any order.
+    pub fn add_point(mut self, point: RagScoredPoint) -> Self {
+        self.points.push(point);
         self
     }
 
-    /// Sets the number of chat completion choices to generate for each input message.
-    ///
-    /// # Arguments
-    ///
-    /// * `n` - How many chat completion choices to generate for each input message. If `n` is less than 1, then sets to `1`.
-    pub fn with_n_choices(mut self, n: u64) -> Self {
-        let n_choice = if n < 1 { 1 } else { n };
-        self.req.n_choice = Some(n_choice);
+    /// Sets the number of similar points to retrieve. Defaults to the number of added points.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
         self
     }
 
-    pub fn with_stream(mut self, flag: bool) -> Self {
-        self.req.stream = Some(flag);
+    /// Sets the score threshold, which must be in the range of `0.0..=1.0` for `Cosine` and `Dot`, or a finite non-negative distance for `Euclid` and `Manhattan`, see [RetrieveObjectBuilder::with_distance]. Defaults to `0.0`.
+    pub fn with_score_threshold(mut self, score_threshold: f32) -> Self {
+        self.score_threshold = score_threshold;
         self
     }
 
-    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
-        self.req.stop = Some(stop);
+    /// Sets the distance metric of the collection the points are retrieved from, which determines the valid range of the score threshold. Defaults to `Cosine`.
+    pub fn with_distance(mut self, distance: Distance) -> Self {
+        self.distance = distance;
         self
     }
 
-    /// Sets the maximum number of tokens to generate in the chat completion. The total length of input tokens and generated tokens is limited by the model's context length.
-    ///
-    /// # Argument
-    ///
-    /// * `max_tokens` - The maximum number of tokens to generate in the chat completion. If `max_tokens` is less than 1, then sets to `16`.
-    pub fn with_max_tokens(mut self, max_tokens: u64) -> Self {
-        let max_tokens = if max_tokens < 1 { 16 } else { max_tokens };
-        self.req.max_tokens = Some(max_tokens);
+    /// Sets the payload filter applied to the vector search.
+    pub fn with_filter(mut self, filter: Value) -> Self {
+        self.filter = Some(filter);
         self
     }
 
-    /// Sets the presence penalty. Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far, increasing the model's likelihood to talk about new topics.
-    pub fn with_presence_penalty(mut self, penalty: f64) -> Self {
-        self.req.presence_penalty = Some(penalty);
-        self
+    /// Builds the [RetrieveObject] with the points sorted by [RagScoredPoint::ranking_score] in descending order if all of them are reranked, or else by `score`.
+    ///
+    /// Returns an error if the score threshold is not in the range of `0.0..=1.0` for a similarity metric, or not a finite non-negative distance for a distance metric.
+    pub fn build(mut self) -> Result<RetrieveObject, EndpointError> {
+        match self.distance.is_similarity() {
+            true if !(0.0..=1.0).contains(&self.score_threshold) => {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The score threshold must be in the range of 0.0..=1.0, but got {}.",
+                    self.score_threshold
+                )));
+            }
+            false if !(self.score_threshold.is_finite() && self.score_threshold >= 0.0) => {
+                return Err(EndpointError::InvalidArgument(format!(
+                    "The score threshold must be a finite non-negative distance, but got {}.",
+                    self.score_threshold
+                )));
+            }
+            _ => {}
+        }
+
+        let all_reranked = all_reranked(&self.points);
+        self.points.sort_by(|a, b| {
+            b.ranking_score_among(all_reranked)
+                .total_cmp(&a.ranking_score_among(all_reranked))
+        });
+
+        Ok(RetrieveObject {
+            limit: self.limit.unwrap_or(self.points.len()),
+            points: match self.points.is_empty() {
+                true => None,
+                false => Some(self.points),
+            },
+            score_threshold: self.score_threshold,
+            filter: self.filter,
+        })
     }
+}
 
-    /// Sets the frequency penalty. Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far, decreasing the model's likelihood to repeat the same line verbatim.
-    pub fn with_frequency_penalty(mut self, penalty: f64) -> Self {
-        self.req.frequency_penalty = Some(penalty);
-        self
+#[test]
+fn test_rag_build_retrieve_object() {
+    let point = |source: &str, score: f32| RagScoredPoint {
+        source: source.to_string(),
+        score,
+        ..Default::default()
+    };
+
+    let ro = RetrieveObjectBuilder::new()
+        .add_point(point("b", 0.5))
+        .add_point(point("a", 0.9))
+        .add_point(point("c", 0.1))
+        .with_score_threshold(0.1)
+        .build()
+        .unwrap();
+    let sources: Vec<&str> = ro
+        .points
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(|p| p.source.as_str())
+        .collect();
+    assert_eq!(sources, vec!["a", "b", "c"]);
+    assert_eq!(ro.limit, 3);
+    assert_eq!(ro.score_threshold, 0.1);
+
+    let ro = RetrieveObjectBuilder::new().with_limit(5).build().unwrap();
+    assert!(ro.points.is_none());
+    assert_eq!(ro.limit, 5);
+    assert_eq!(ro.score_threshold, 0.0);
+
+    assert!(RetrieveObjectBuilder::new()
+        .with_score_threshold(1.0)
+        .build()
+        .is_ok());
+    assert!(RetrieveObjectBuilder::new()
+        .with_score_threshold(1.5)
+        .build()
+        .is_err());
+    assert!(RetrieveObjectBuilder::new()
+        .with_score_threshold(-0.1)
+        .build()
+        .is_err());
+    assert!(RetrieveObjectBuilder::new()
+        .with_score_threshold(f32::NAN)
+        .build()
+        .is_err());
+
+    // the threshold of a distance metric is a raw distance
+    for distance in [Distance::Euclid, Distance::Manhattan] {
+        let ro = RetrieveObjectBuilder::new()
+            .with_distance(distance)
+            .with_score_threshold(2.5)
+            .build()
+            .unwrap();
+        assert_eq!(ro.score_threshold, 2.5);
+        for score_threshold in [-0.1, f32::NAN, f32::INFINITY] {
+            assert!(RetrieveObjectBuilder::new()
+                .with_distance(distance)
+                .with_score_threshold(score_threshold)
+                .build()
+                .is_err());
+        }
     }
+    assert!(RetrieveObjectBuilder::new()
+        .with_distance(Distance::Dot)
+        .with_score_threshold(2.5)
+        .build()
+        .is_err());
+}
 
-    pub fn with_logits_bias(mut self, map: HashMap<String, f64>) -> Self {
-        self.req.logit_bias = Some(map);
-        self
+/// Represents a retrieved source with its score.
+///
+/// Implements `PartialEq` but not `Eq`, because the score is a float: a point with a NaN score is not equal to itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RagScoredPoint {
+    /// Source of the context
+    pub source: String,
+
+    /// Points vector distance to the query vector
+    pub score: f32,
+
+    /// Id of the point. Numeric ids are converted to strings.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_point_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<String>,
+
+    /// Payload of the point, such as the metadata of the source document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<HashMap<String, Value>>,
+
+    /// Breakdown of the score by scoring component, such as `dense`, `sparse`, `recency` or `rerank`. Only present if `explain` is enabled in the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_breakdown: Option<HashMap<String, f32>>,
+
+    /// The encoding of `source`. `None` means `text`, which is omitted for backward compatibility.
+    #[serde(skip_serializing_if = "SourceEncoding::is_text")]
+    pub source_encoding: Option<SourceEncoding>,
+
+    /// The 0-based index of the query message the point was retrieved for, counted from the oldest of the user messages within `context_window`. Not filled in by the retrieval: callers that query each of the messages separately set it on the points they retrieve, and leave it unset otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_query_index: Option<usize>,
+
+    /// The score of the point given by the cross-encoder model if the points are reranked, while `score` remains the vector similarity. If every point has one, the points are ordered by this score instead of `score`, see [RagScoredPoint::ranking_score].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f32>,
+}
+impl RagScoredPoint {
+    /// Returns the score the point is ordered by, i.e. `rerank_score` if the point is reranked, or else `score`.
+    ///
+    /// A rerank score and a vector score are on different scales, so points are only ordered by their ranking scores if all of them are reranked, and by `score` otherwise.
+    pub fn ranking_score(&self) -> f32 {
+        self.rerank_score.unwrap_or(self.score)
     }
 
-    pub fn with_user(mut self, user: impl Into<String>) -> Self {
-        self.req.user = Some(user.into());
-        self
+    /// Returns the score the point is ordered by among points that are `all_reranked` or not, see [all_reranked].
+    fn ranking_score_among(&self, all_reranked: bool) -> f32 {
+        match all_reranked {
+            true => self.ranking_score(),
+            false => self.score,
+        }
     }
 
-    pub fn with_context_window(mut self, context_window: u64) -> Self {
-        self.req.context_window = Some(context_window);
-        self
+    /// Returns the label of the highest threshold the score meets, e.g. for coloring the points in a UI, or [UNCLASSIFIED_CONFIDENCE_LABEL] if the score is below all thresholds or NaN.
+    ///
+    /// * `thresholds` - The minimum scores with their labels, in any order, e.g. `[(0.8, "high"), (0.5, "medium"), (0.0, "low")]`.
+    pub fn confidence_bucket<'a>(&self, thresholds: &[(f32, &'a str)]) -> &'a str {
+        thresholds
+            .iter()
+            .filter(|(threshold, _)| self.score >= *threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map_or(UNCLASSIFIED_CONFIDENCE_LABEL, |(_, label)| *label)
     }
 
-    pub fn build(self) -> RagChatCompletionsRequest {
-        self.req
+    /// Returns the bytes of the source, decoding it if it is base64-encoded.
+    pub fn decoded_bytes(&self) -> Result<Vec<u8>, EndpointError> {
+        match self.source_encoding.unwrap_or_default() {
+            SourceEncoding::Text => Ok(self.source.as_bytes().to_vec()),
+            SourceEncoding::Base64 => general_purpose::STANDARD.decode(&self.source).map_err(|e| {
+                EndpointError::InvalidArgument(format!(
+                    "Failed to decode the base64-encoded source. Reason: {}",
+                    e
+                ))
+            }),
+        }
+    }
+}
+impl From<SearchResult> for RagScoredPoint {
+    fn from(result: SearchResult) -> Self {
+        RagScoredPoint {
+            source: result.text,
+            score: result.score,
+            id: match result.id.is_empty() {
+                true => None,
+                false => Some(result.id),
+            },
+            payload: match result.metadata.is_empty() {
+                true => None,
+                false => Some(result.metadata),
+            },
+            score_breakdown: None,
+            source_encoding: None,
+            matched_query_index: None,
+            rerank_score: None,
+        }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChunksRequest {
-    pub id: String,
-    pub filename: String,
-    pub chunk_capacity: usize,
+/// Whether every point has a `rerank_score`, in which case the points are ordered by it. Otherwise, the points are ordered by `score`, since the two scores are on different scales.
+fn all_reranked<'a>(points: impl IntoIterator<Item = &'a RagScoredPoint>) -> bool {
+    points.into_iter().all(|point| point.rerank_score.is_some())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChunksResponse {
-    pub id: String,
-    pub filename: String,
-    pub chunks: Vec<String>,
+/// Deserializes the id of a point, which Qdrant gives as either a UUID string or an unsigned integer.
+fn deserialize_point_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PointId {
+        Uuid(String),
+        Num(u64),
+    }
+
+    Ok(
+        Option::<PointId>::deserialize(deserializer)?.map(|id| match id {
+            PointId::Uuid(id) => id,
+            PointId::Num(id) => id.to_string(),
+        }),
+    )
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct RetrieveObject {
-    /// The retrieved sources.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub points: Option<Vec<RagScoredPoint>>,
+/// The label returned by [RagScoredPoint::confidence_bucket] if the score is below all thresholds.
+pub const UNCLASSIFIED_CONFIDENCE_LABEL: &str = "none";
 
-    /// The number of similar points to retrieve
-    pub limit: usize,
+#[test]
+fn test_rag_confidence_bucket() {
+    let thresholds = [(0.5, "medium"), (0.8, "high"), (0.2, "low")];
+    let point = |score: f32| RagScoredPoint {
+        source: "source".to_string(),
+        score,
+        ..Default::default()
+    };
 
-    /// The score threshold
-    pub score_threshold: f32,
+    assert_eq!(point(0.95).confidence_bucket(&thresholds), "high");
+    // the thresholds are inclusive
+    assert_eq!(point(0.8).confidence_bucket(&thresholds), "high");
+    assert_eq!(point(0.79).confidence_bucket(&thresholds), "medium");
+    assert_eq!(point(0.5).confidence_bucket(&thresholds), "medium");
+    assert_eq!(point(0.2).confidence_bucket(&thresholds), "low");
+    assert_eq!(
+        point(0.19).confidence_bucket(&thresholds),
+        UNCLASSIFIED_CONFIDENCE_LABEL
+    );
+    assert_eq!(
+        point(f32::NAN).confidence_bucket(&thresholds),
+        UNCLASSIFIED_CONFIDENCE_LABEL
+    );
+    assert_eq!(
+        point(0.95).confidence_bucket(&[]),
+        UNCLASSIFIED_CONFIDENCE_LABEL
+    );
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RagScoredPoint {
-    /// Source of the context
-    pub source: String,
+/// The encoding of the source of a [RagScoredPoint].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceEncoding {
+    /// Plain text.
+    #[default]
+    Text,
+    /// Base64-encoded binary data, such as an image or a PDF page.
+    Base64,
+}
+impl SourceEncoding {
+    fn is_text(encoding: &Option<SourceEncoding>) -> bool {
+        matches!(encoding, None | Some(SourceEncoding::Text))
+    }
+}
 
-    /// Points vector distance to the query vector
+#[test]
+fn test_rag_source_encoding() {
+    let point = RagScoredPoint {
+        source: "Hello".to_string(),
+        score: 0.5,
+        source_encoding: Some(SourceEncoding::Text),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(json, r#"{"source":"Hello","score":0.5}"#);
+    assert_eq!(point.decoded_bytes().unwrap(), b"Hello");
+
+    let point = RagScoredPoint {
+        source: "AAEC/w==".to_string(),
+        score: 0.5,
+        source_encoding: Some(SourceEncoding::Base64),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(
+        json,
+        r#"{"source":"AAEC/w==","score":0.5,"source_encoding":"base64"}"#
+    );
+    let point: RagScoredPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(point.source_encoding, Some(SourceEncoding::Base64));
+    assert_eq!(point.decoded_bytes().unwrap(), vec![0, 1, 2, 255]);
+
+    let point: RagScoredPoint = serde_json::from_str(r#"{"source":"Hello","score":0.5}"#).unwrap();
+    assert_eq!(point.source_encoding, None);
+    assert_eq!(point.decoded_bytes().unwrap(), b"Hello");
+
+    let point = RagScoredPoint {
+        source: "not base64!".to_string(),
+        source_encoding: Some(SourceEncoding::Base64),
+        ..Default::default()
+    };
+    assert!(point.decoded_bytes().is_err());
+}
+
+/// A vector-database-agnostic search result, used to interoperate with other tooling.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    /// Id of the result. Empty if unknown.
+    pub id: String,
+    /// Text of the result.
+    pub text: String,
+    /// Similarity score of the result.
     pub score: f32,
+    /// Metadata of the result.
+    pub metadata: HashMap<String, Value>,
+}
+impl From<RagScoredPoint> for SearchResult {
+    fn from(point: RagScoredPoint) -> Self {
+        SearchResult {
+            id: point.id.unwrap_or_default(),
+            text: point.source,
+            score: point.score,
+            metadata: point.payload.unwrap_or_default(),
+        }
+    }
+}
+
+#[test]
+fn test_rag_convert_search_result() {
+    let mut metadata = HashMap::new();
+    metadata.insert("title".to_string(), Value::from("LlamaEdge"));
+    metadata.insert("page".to_string(), Value::from(3));
+    let result = SearchResult {
+        id: "42".to_string(),
+        text: "source".to_string(),
+        score: 0.75,
+        metadata,
+    };
+
+    let point = RagScoredPoint::from(result.clone());
+    assert_eq!(point.source, "source");
+    assert_eq!(point.score, 0.75);
+    assert_eq!(point.id.as_deref(), Some("42"));
+    assert_eq!(point.payload.as_ref().unwrap()["page"], Value::from(3));
+    assert_eq!(SearchResult::from(point), result);
+
+    // empty id and metadata are treated as absent
+    let result = SearchResult {
+        text: "source".to_string(),
+        score: 0.5,
+        ..Default::default()
+    };
+    let point = RagScoredPoint::from(result.clone());
+    assert!(point.id.is_none());
+    assert!(point.payload.is_none());
+    assert_eq!(SearchResult::from(point), result);
 }
 
 #[test]
@@ -394,9 +7497,11 @@ fn test_rag_serialize_retrieve_object() {
             points: Some(vec![RagScoredPoint {
                 source: "source".to_string(),
                 score: 0.5,
+                ..Default::default()
             }]),
             limit: 1,
             score_threshold: 0.5,
+            filter: None,
         };
         let json = serde_json::to_string(&ro).unwrap();
         assert_eq!(
@@ -410,12 +7515,225 @@ fn test_rag_serialize_retrieve_object() {
             points: None,
             limit: 1,
             score_threshold: 0.5,
+            filter: None,
         };
         let json = serde_json::to_string(&ro).unwrap();
         assert_eq!(json, r#"{"limit":1,"score_threshold":0.5}"#);
     }
 }
 
+#[test]
+fn test_rag_serialize_filter() {
+    let filter = serde_json::json!({
+        "must": [
+            {"key": "doc_type", "match": {"value": "policy"}},
+            {"key": "year", "range": {"gte": 2020, "lt": 2025}}
+        ],
+        "should": [
+            {"must_not": [{"key": "draft", "match": {"value": true}}]}
+        ]
+    });
+
+    let mut request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 3)
+            .with_filter(filter.clone())
+            .build();
+    request.embedding_model = "model".to_string();
+    let json = serde_json::to_string(&request).unwrap();
+    let round_trip: RagChatCompletionsRequest = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip.filter.as_ref(), Some(&filter));
+
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 3);
+    assert!(!serde_json::to_string(&request).unwrap().contains("filter"));
+
+    // echoed on the retrieval result
+    let ro = RetrieveObjectBuilder::new()
+        .add_point(RagScoredPoint {
+            source: "source".to_string(),
+            score: 0.5,
+            ..Default::default()
+        })
+        .with_filter(filter.clone())
+        .build()
+        .unwrap();
+    let json = serde_json::to_string(&ro).unwrap();
+    let round_trip: RetrieveObject = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, ro);
+    assert_eq!(round_trip.filter.as_ref(), Some(&filter));
+}
+
+#[test]
+fn test_rag_serialize_payload() {
+    let mut payload = HashMap::new();
+    payload.insert(
+        "document".to_string(),
+        serde_json::json!({ "title": "LlamaEdge", "url": "https://llamaedge.com", "pages": [3, 4] }),
+    );
+    let point = RagScoredPoint {
+        source: "source".to_string(),
+        score: 0.5,
+        payload: Some(payload),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(
+        json,
+        r#"{"source":"source","score":0.5,"payload":{"document":{"pages":[3,4],"title":"LlamaEdge","url":"https://llamaedge.com"}}}"#
+    );
+    let round_trip: RagScoredPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, point);
+    assert_eq!(round_trip.payload.unwrap()["document"]["pages"][1], 4);
+
+    // points without payload keep their shape
+    let point = RagScoredPoint {
+        payload: None,
+        ..point
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(json, r#"{"source":"source","score":0.5}"#);
+    let point: RagScoredPoint = serde_json::from_str(&json).unwrap();
+    assert!(point.payload.is_none());
+}
+
+#[test]
+fn test_rag_serialize_matched_query_index() {
+    let point = RagScoredPoint {
+        source: "source".to_string(),
+        score: 0.5,
+        matched_query_index: Some(1),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(
+        json,
+        r#"{"source":"source","score":0.5,"matched_query_index":1}"#
+    );
+    let round_trip: RagScoredPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, point);
+
+    // omitted for single-message retrieval
+    let point = RagScoredPoint {
+        matched_query_index: None,
+        ..point
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(json, r#"{"source":"source","score":0.5}"#);
+    let point: RagScoredPoint = serde_json::from_str(&json).unwrap();
+    assert!(point.matched_query_index.is_none());
+}
+
+#[test]
+fn test_rag_serialize_rerank_score() {
+    let point = RagScoredPoint {
+        source: "source".to_string(),
+        score: 0.5,
+        rerank_score: Some(0.875),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(
+        json,
+        r#"{"source":"source","score":0.5,"rerank_score":0.875}"#
+    );
+    let round_trip: RagScoredPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, point);
+    assert_eq!(point.ranking_score(), 0.875);
+
+    // omitted without reranking
+    let point: RagScoredPoint = serde_json::from_str(r#"{"source":"source","score":0.5}"#).unwrap();
+    assert!(point.rerank_score.is_none());
+    assert_eq!(point.ranking_score(), 0.5);
+    assert_eq!(
+        serde_json::to_string(&point).unwrap(),
+        r#"{"source":"source","score":0.5}"#
+    );
+
+    // the reranked points are ordered by the rerank score
+    let retrieve_object = RetrieveObjectBuilder::new()
+        .add_point(RagScoredPoint {
+            source: "a".to_string(),
+            score: 0.75,
+            rerank_score: Some(0.25),
+            ..Default::default()
+        })
+        .add_point(RagScoredPoint {
+            source: "b".to_string(),
+            score: 0.5,
+            rerank_score: Some(0.5),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let sources: Vec<&str> = retrieve_object
+        .points
+        .iter()
+        .flatten()
+        .map(|point| point.source.as_str())
+        .collect();
+    assert_eq!(sources, vec!["b", "a"]);
+
+    // unless some of the points are not reranked, since the scores are on different scales
+    let retrieve_object = RetrieveObjectBuilder::new()
+        .add_point(RagScoredPoint {
+            source: "a".to_string(),
+            score: 0.75,
+            ..Default::default()
+        })
+        .add_point(RagScoredPoint {
+            source: "b".to_string(),
+            score: 0.5,
+            rerank_score: Some(0.875),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let sources: Vec<&str> = retrieve_object
+        .points
+        .iter()
+        .flatten()
+        .map(|point| point.source.as_str())
+        .collect();
+    assert_eq!(sources, vec!["a", "b"]);
+}
+
+#[test]
+fn test_rag_serialize_score_breakdown() {
+    let mut score_breakdown = HashMap::new();
+    score_breakdown.insert("dense".to_string(), 0.75);
+    let point = RagScoredPoint {
+        source: "source".to_string(),
+        score: 0.75,
+        score_breakdown: Some(score_breakdown),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(
+        json,
+        r#"{"source":"source","score":0.75,"score_breakdown":{"dense":0.75}}"#
+    );
+
+    let json = r#"{"source":"source","score":0.6,"score_breakdown":{"dense":0.5,"sparse":0.25,"recency":0.125,"rerank":0.6}}"#;
+    let point: RagScoredPoint = serde_json::from_str(json).unwrap();
+    let score_breakdown = point.score_breakdown.as_ref().unwrap();
+    assert_eq!(score_breakdown.len(), 4);
+    assert_eq!(score_breakdown["sparse"], 0.25);
+    assert_eq!(score_breakdown["rerank"], 0.6);
+    let round_trip: RagScoredPoint =
+        serde_json::from_str(&serde_json::to_string(&point).unwrap()).unwrap();
+    assert_eq!(round_trip, point);
+
+    // the explain mode of the request
+    let request =
+        RagChatCompletionRequestBuilder::new(vec![], "http://localhost:6333", "default", 1)
+            .with_explain(true)
+            .build();
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(json.contains(r#""explain":true"#));
+    let request = RagChatCompletionsRequest::new(vec![], "http://localhost:6333", "default", 1);
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("explain"));
+}
+
 #[test]
 fn test_rag_deserialize_retrieve_object() {
     {
@@ -439,3 +7757,68 @@ fn test_rag_deserialize_retrieve_object() {
         assert!(ro.points.is_none());
     }
 }
+
+#[cfg(feature = "strict-deserialize")]
+#[test]
+fn test_rag_strict_deserialize_rejects_dummy_models() {
+    let json = r#"{"messages":[],"chat_model":"dummy-chat-model","embedding_model":"model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let err = serde_json::from_str::<RagChatCompletionsRequest>(json).unwrap_err();
+    assert!(err.to_string().contains("`chat_model`"));
+
+    let json = r#"{"messages":[],"embedding_model":"dummy-embedding-model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let err = serde_json::from_str::<RagChatCompletionsRequest>(json).unwrap_err();
+    assert!(err.to_string().contains("`embedding_model`"));
+
+    // real model names, and an absent chat model, are accepted
+    let json = r#"{"messages":[],"chat_model":"llama","embedding_model":"model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.chat_model.as_deref(), Some("llama"));
+    let json = r#"{"messages":[],"embedding_model":"model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert!(request.chat_model.is_none());
+}
+
+#[cfg(not(feature = "strict-deserialize"))]
+#[test]
+fn test_rag_deserialize_accepts_dummy_models() {
+    let json = r#"{"messages":[],"chat_model":"dummy-chat-model","embedding_model":"dummy-embedding-model","qdrant_url":"http://localhost:6333","qdrant_collection_name":"default","limit":3}"#;
+    let request: RagChatCompletionsRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.chat_model.as_deref(), Some(DUMMY_CHAT_MODEL));
+    assert_eq!(request.embedding_model, DUMMY_EMBEDDING_MODEL);
+}
+
+#[test]
+fn test_rag_into_chat_completions_request() {
+    let messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Text("What is the capital of France?".to_string()),
+        None,
+    )];
+    let mut request =
+        RagChatCompletionRequestBuilder::new(messages, "http://localhost:6333", "default", 3)
+            .with_stream_options(StreamOptions {
+                include_usage: Some(true),
+                usage_interval: None,
+            })
+            .with_force_non_stream_with_tools(true)
+            .with_logits_bias(HashMap::from([("50256".to_string(), -100.0)]))
+            .build();
+    request.chat_model = Some("llama".to_string());
+    request.tools = Some(vec![Tool {
+        ty: "function".to_string(),
+        function: crate::chat::ToolFunction {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters: None,
+        },
+    }]);
+
+    // moving the fields gives the same request as cloning them
+    let borrowed = serde_json::to_value(request.as_chat_completions_request()).unwrap();
+    let owned = serde_json::to_value(ChatCompletionRequest::from(request)).unwrap();
+    assert_eq!(owned, borrowed);
+    assert_eq!(owned["model"], "llama");
+    assert_eq!(owned["stream"], false);
+    assert!(owned.get("stream_options").is_none());
+    assert_eq!(owned["tools"][0]["function"]["name"], "get_weather");
+    assert_eq!(owned["logit_bias"]["50256"], -100.0);
+}
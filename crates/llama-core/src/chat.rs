@@ -27,8 +27,9 @@ use endpoints::{
 };
 use error::{BackendError, LlamaCoreError};
 use futures::StreamExt;
+use once_cell::sync::OnceCell;
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     pin::Pin,
     sync::Mutex,
     task::{Context, Poll},
@@ -1794,6 +1795,49 @@ async fn check_model_metadata(
         }
     }
 
+    // check if necessary to update repeat_penalty
+    if let Some(repeat_penalty) = chat_request.repeat_penalty {
+        if metadata.repeat_penalty != repeat_penalty {
+            // update repeat_penalty
+            metadata.repeat_penalty = repeat_penalty;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update grammar. The grammar of a request only applies to that request, so a
+    // request without `grammar` restores the grammar the model was served with.
+    {
+        let applied_grammars =
+            APPLIED_GRAMMARS.get_or_init(|| Mutex::new(AppliedGrammars::default()));
+        let mut applied_grammars = applied_grammars.lock().map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of `APPLIED_GRAMMARS`. {}", e);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+        let served_grammar = metadata.grammar.clone();
+        if let Some(grammar) = applied_grammars.resolve(
+            &metadata.model_name,
+            &served_grammar,
+            chat_request.grammar.as_ref(),
+        ) {
+            // update grammar
+            metadata.grammar = grammar;
+
+            if !should_update {
+                should_update = true;
+            }
+        } else if let Some(grammar) = &chat_request.grammar {
+            metadata.grammar = grammar.clone();
+        }
+    }
+
     // check if the `embedding` option is disabled
     if metadata.embeddings {
         metadata.embeddings = false;
@@ -1811,6 +1855,39 @@ async fn check_model_metadata(
     Ok(metadata)
 }
 
+/// The grammar currently applied to each chat graph.
+static APPLIED_GRAMMARS: OnceCell<Mutex<AppliedGrammars>> = OnceCell::new();
+
+/// Tracks the grammar applied to each chat graph, keyed by model name, so that the grammar of one request does not
+/// leak into the next.
+#[derive(Debug, Default)]
+struct AppliedGrammars(HashMap<String, String>);
+
+impl AppliedGrammars {
+    /// Returns the grammar to apply for a request, or `None` if the graph already runs with it.
+    ///
+    /// A request without `grammar` runs with `served_grammar`, the grammar the model was served with.
+    fn resolve(
+        &mut self,
+        model_name: &str,
+        served_grammar: &str,
+        requested: Option<&String>,
+    ) -> Option<String> {
+        let grammar = requested.map(String::as_str).unwrap_or(served_grammar);
+        let applied = self
+            .0
+            .get(model_name)
+            .map(String::as_str)
+            .unwrap_or(served_grammar);
+        if grammar == applied {
+            return None;
+        }
+
+        self.0.insert(model_name.to_string(), grammar.to_string());
+        Some(grammar.to_string())
+    }
+}
+
 async fn update_n_predict(
     chat_request: &ChatCompletionRequest,
     metadata: &mut GgmlMetadata,
@@ -4211,3 +4288,23 @@ struct ParseResult {
     content: Option<String>,
     tool_calls: Vec<ToolCall>,
 }
+
+#[test]
+fn test_chat_applied_grammars_restore_served_grammar() {
+    let mut applied = AppliedGrammars::default();
+    let grammar = String::from("root ::= \"yes\" | \"no\"");
+
+    // the first request sets its own grammar
+    assert_eq!(
+        applied.resolve("model", "", Some(&grammar)),
+        Some(grammar.clone())
+    );
+    // the same grammar is already applied
+    assert_eq!(applied.resolve("model", "", Some(&grammar)), None);
+    // a later request without grammar restores the served one
+    assert_eq!(applied.resolve("model", "", None), Some(String::new()));
+    assert_eq!(applied.resolve("model", "", None), None);
+
+    // other models are tracked separately
+    assert_eq!(applied.resolve("other", "", None), None);
+}
@@ -3,9 +3,13 @@
 use crate::{embeddings::embeddings, error::LlamaCoreError, running_mode, RunningMode};
 use endpoints::{
     embeddings::{EmbeddingObject, EmbeddingsResponse, InputText},
-    rag::{RagEmbeddingRequest, RagScoredPoint, RetrieveObject},
+    rag::{
+        CreateCollectionConfig, RagEmbeddingRequest, RagScoredPoint, RetrieveObject, VectorDbConfig,
+    },
 };
 use qdrant::*;
+use serde_json::Value;
+use std::collections::HashMap;
 use text_splitter::{MarkdownSplitter, TextSplitter};
 use tiktoken_rs::cl100k_base;
 
@@ -13,15 +17,11 @@ use tiktoken_rs::cl100k_base;
 ///
 /// # Arguments
 ///
-/// * `embedding_request` - A reference to an `EmbeddingRequest` object.
-///
-/// * `qdrant_url` - URL of the Qdrant server.
-///
-/// * `qdrant_collection_name` - Name of the Qdrant collection to be created.
+/// * `embedding_request` - A reference to an `EmbeddingRequest` object. The embeddings are stored in the vector database given by [RagEmbeddingRequest::vector_db_config], which must be a Qdrant collection. The request must pass [RagEmbeddingRequest::validate], and pooling and late chunking are not supported yet.
 ///
 /// # Returns
 ///
-/// Name of the Qdrant collection if successful.
+/// The embeddings of the document chunks if successful.
 pub async fn rag_doc_chunks_to_embeddings(
     rag_embedding_request: &RagEmbeddingRequest,
 ) -> Result<EmbeddingsResponse, LlamaCoreError> {
@@ -41,9 +41,47 @@ pub async fn rag_doc_chunks_to_embeddings(
         return Err(LlamaCoreError::Operation(err_msg));
     }
 
+    if let Err(e) = rag_embedding_request.validate() {
+        let err_msg = format!("Invalid RAG embedding request: {}", e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg));
+    }
+
+    // each input is stored as a separate point
+    if let Some(pooling) = rag_embedding_request
+        .pooling
+        .filter(|_| rag_embedding_request.is_pooled())
+    {
+        let err_msg = format!(
+            "The pooling of the embeddings is not supported yet: {:?}",
+            pooling
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg));
+    }
+
+    // each input is embedded separately
+    if let Some(late_chunking) = &rag_embedding_request.late_chunking {
+        let err_msg = format!(
+            "The late chunking is not supported yet: {:?}",
+            late_chunking
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg));
+    }
+
     let embedding_request = &rag_embedding_request.embedding_request;
-    let qdrant_url = rag_embedding_request.qdrant_url.as_str();
-    let qdrant_collection_name = rag_embedding_request.qdrant_collection_name.as_str();
+    let vector_db = rag_embedding_request.vector_db_config();
+    let (qdrant_url, qdrant_collection_name) = qdrant_collection(&vector_db)?;
 
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Compute embeddings for document chunks.");
@@ -58,11 +96,32 @@ pub async fn rag_doc_chunks_to_embeddings(
     let embeddings = response.data.as_slice();
     let dim = embeddings[0].embedding.len();
 
+    // check the dimension of the collection to create against the computed embeddings
+    if let Some(config) = &rag_embedding_request.create_collection {
+        if config.dimension != dim {
+            let err_msg = format!(
+                "The dimension of the collection ({}) does not match the dimension of the embeddings ({}).",
+                config.dimension, dim
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg));
+        }
+    }
+
     // create a Qdrant client
     let qdrant_client = qdrant::Qdrant::new_with_url(qdrant_url.to_string());
 
     // create a collection
-    qdrant_create_collection(&qdrant_client, qdrant_collection_name, dim).await?;
+    qdrant_create_collection(
+        &qdrant_client,
+        qdrant_collection_name,
+        dim,
+        rag_embedding_request.create_collection.as_ref(),
+    )
+    .await?;
 
     let chunks = match &embedding_request.input {
         InputText::String(text) => vec![text.clone()],
@@ -80,6 +139,7 @@ pub async fn rag_doc_chunks_to_embeddings(
         qdrant_collection_name,
         embeddings,
         chunks.as_slice(),
+        rag_embedding_request.payloads.as_deref(),
     )
     .await?;
 
@@ -116,25 +176,27 @@ pub async fn rag_query_to_embeddings(
 ///
 /// * `query_embedding` - A reference to a query embedding.
 ///
-/// * `qdrant_url` - URL of the Qdrant server.
-///
-/// * `qdrant_collection_name` - Name of the Qdrant collection to be created.
+/// * `vector_db` - The vector database to retrieve from, e.g. [RagChatCompletionsRequest::vector_db_config](endpoints::rag::RagChatCompletionsRequest::vector_db_config). Only Qdrant collections are supported.
 ///
 /// * `limit` - Number of retrieved results.
 ///
 /// * `score_threshold` - The minimum score of the retrieved results.
+///
+/// * `filter` - The Qdrant payload filter the retrieved results must match, e.g. `filter` of a `RagChatCompletionsRequest`. The filter is echoed in the returned `RetrieveObject`.
 pub async fn rag_retrieve_context(
     query_embedding: &[f32],
-    qdrant_url: impl AsRef<str>,
-    qdrant_collection_name: impl AsRef<str>,
+    vector_db: &VectorDbConfig,
     limit: usize,
     score_threshold: Option<f32>,
+    filter: Option<&Value>,
 ) -> Result<RetrieveObject, LlamaCoreError> {
+    let (qdrant_url, qdrant_collection_name) = qdrant_collection(vector_db)?;
+
     #[cfg(feature = "logging")]
     {
         info!(target: "stdout", "Retrieve context.");
 
-        info!(target: "stdout", "qdrant_url: {}, qdrant_collection_name: {}, limit: {}, score_threshold: {}", qdrant_url.as_ref(), qdrant_collection_name.as_ref(), limit, score_threshold.unwrap_or_default());
+        info!(target: "stdout", "qdrant_url: {}, qdrant_collection_name: {}, limit: {}, score_threshold: {}", qdrant_url, qdrant_collection_name, limit, score_threshold.unwrap_or_default());
     }
 
     let running_mode = running_mode()?;
@@ -151,15 +213,16 @@ pub async fn rag_retrieve_context(
     }
 
     // create a Qdrant client
-    let qdrant_client = qdrant::Qdrant::new_with_url(qdrant_url.as_ref().to_string());
+    let qdrant_client = qdrant::Qdrant::new_with_url(qdrant_url.to_string());
 
     // search for similar points
     let scored_points = match qdrant_search_similar_points(
         &qdrant_client,
-        qdrant_collection_name.as_ref(),
+        qdrant_collection_name,
         query_embedding,
         limit,
         score_threshold,
+        filter,
     )
     .await
     {
@@ -177,15 +240,32 @@ pub async fn rag_retrieve_context(
             points: None,
             limit,
             score_threshold: score_threshold.unwrap_or(0.0),
+            filter: filter.cloned(),
         },
         false => {
             let mut points: Vec<RagScoredPoint> = vec![];
             for point in scored_points.iter() {
                 if let Some(payload) = &point.payload {
                     if let Some(source) = payload.get("source") {
+                        // keep the rest of the payload, such as the metadata of the source document
+                        let metadata: HashMap<String, Value> = payload
+                            .iter()
+                            .filter(|(key, _)| key.as_str() != "source")
+                            .map(|(key, value)| (key.clone(), value.clone()))
+                            .collect();
+
                         points.push(RagScoredPoint {
                             source: source.to_string(),
                             score: point.score,
+                            id: Some(match &point.id {
+                                PointId::Uuid(id) => id.clone(),
+                                PointId::Num(id) => id.to_string(),
+                            }),
+                            payload: match metadata.is_empty() {
+                                true => None,
+                                false => Some(metadata),
+                            },
+                            ..Default::default()
                         })
                     }
                 }
@@ -195,6 +275,7 @@ pub async fn rag_retrieve_context(
                 points: Some(points),
                 limit,
                 score_threshold: score_threshold.unwrap_or(0.0),
+                filter: filter.cloned(),
             }
         }
     };
@@ -202,18 +283,57 @@ pub async fn rag_retrieve_context(
     Ok(ro)
 }
 
+/// Returns the URL of the Qdrant server and the name of the collection, or an error if the vector database is not Qdrant, which is the only backend supported yet.
+fn qdrant_collection(vector_db: &VectorDbConfig) -> Result<(&str, &str), LlamaCoreError> {
+    let backend = match vector_db {
+        VectorDbConfig::Qdrant {
+            url,
+            collection_name,
+        } => return Ok((url.as_str(), collection_name.as_str())),
+        VectorDbConfig::Milvus { .. } => "milvus",
+        VectorDbConfig::Pinecone { .. } => "pinecone",
+        VectorDbConfig::LocalFile { .. } => "local_file",
+    };
+
+    let err_msg = format!(
+        "The `{}` vector database is not supported yet. Only `qdrant` is supported.",
+        backend
+    );
+
+    #[cfg(feature = "logging")]
+    error!(target: "stdout", "{}", &err_msg);
+
+    Err(LlamaCoreError::Operation(err_msg))
+}
+
+/// Creates the collection with `config` if given, or else with vectors of `dim` dimensions compared with the cosine distance.
 async fn qdrant_create_collection(
     qdrant_client: &qdrant::Qdrant,
     collection_name: impl AsRef<str>,
     dim: usize,
+    config: Option<&CreateCollectionConfig>,
 ) -> Result<(), LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Create a Qdrant collection named {} of {} dimensions.", collection_name.as_ref(), dim);
 
-    if let Err(e) = qdrant_client
-        .create_collection(collection_name.as_ref(), dim as u32)
-        .await
-    {
+    let result = match config {
+        // the config is serialized in the shape of the vector parameters of the create-collection body
+        Some(config) => {
+            qdrant_client
+                .create_collection_api(
+                    collection_name.as_ref(),
+                    &serde_json::json!({ "vectors": config }),
+                )
+                .await
+        }
+        None => {
+            qdrant_client
+                .create_collection(collection_name.as_ref(), dim as u32)
+                .await
+        }
+    };
+
+    if let Err(e) = result {
         let err_msg = e.to_string();
 
         #[cfg(feature = "logging")]
@@ -230,6 +350,7 @@ async fn qdrant_persist_embeddings(
     collection_name: impl AsRef<str>,
     embeddings: &[EmbeddingObject],
     chunks: &[String],
+    payloads: Option<&[HashMap<String, Value>]>,
 ) -> Result<(), LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Persist embeddings to the Qdrant instance.");
@@ -239,10 +360,16 @@ async fn qdrant_persist_embeddings(
         // convert the embedding to a vector
         let vector: Vec<_> = embedding.embedding.iter().map(|x| *x as f32).collect();
 
-        // create a payload
-        let payload = serde_json::json!({"source": chunks[embedding.index as usize]})
-            .as_object()
-            .map(|m| m.to_owned());
+        // create a payload, merging the source into the payload of the chunk if any
+        let mut payload: serde_json::Map<String, Value> = payloads
+            .and_then(|payloads| payloads.get(embedding.index as usize))
+            .map(|payload| payload.clone().into_iter().collect())
+            .unwrap_or_default();
+        payload.insert(
+            "source".to_string(),
+            Value::from(chunks[embedding.index as usize].as_str()),
+        );
+        let payload = Some(payload);
 
         // create a point
         let p = Point {
@@ -278,28 +405,43 @@ async fn qdrant_search_similar_points(
     query_vector: &[f32],
     limit: usize,
     score_threshold: Option<f32>,
+    filter: Option<&Value>,
 ) -> Result<Vec<ScoredPoint>, LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Search similar points from the qdrant instance.");
 
-    match qdrant_client
-        .search_points(
-            collection_name.as_ref(),
-            query_vector.to_vec(),
-            limit as u64,
-            score_threshold,
-        )
+    // `Qdrant::search_points` has no filter, so the search body is built here
+    let mut params = serde_json::json!({
+        "vector": query_vector,
+        "limit": limit,
+        "with_payload": true,
+        "with_vector": true,
+        "score_threshold": score_threshold.unwrap_or(0.0),
+    });
+    if let Some(filter) = filter {
+        params["filter"] = filter.clone();
+    }
+
+    let search_result = match qdrant_client
+        .search_points_api(collection_name.as_ref(), &params)
         .await
     {
+        Ok(response) => match response.get("result") {
+            Some(result) => serde_json::from_value::<Vec<ScoredPoint>>(result.clone())
+                .map_err(|e| format!("[qdrant] Failed to parse the scored points. {}", e)),
+            None => Err("[qdrant] The given key 'result' does not exist.".to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+
+    match search_result {
         Ok(search_result) => {
             #[cfg(feature = "logging")]
             info!(target: "stdout", "Number of similar points found: {}", search_result.len());
 
             Ok(search_result)
         }
-        Err(e) => {
-            let err_msg = e.to_string();
-
+        Err(err_msg) => {
             #[cfg(feature = "logging")]
             error!(target: "stdout", "{}", &err_msg);
 
@@ -5,7 +5,7 @@ use endpoints::{
     embeddings::EmbeddingRequest,
     reranker::RerankerRequest,
     files::{DeleteFileStatus, FileObject, ListFilesResponse},
-    rag::{ChunksRequest, ChunksResponse},
+    rag::{ChunkingStrategy, ChunksRequest, ChunksResponse},
 };
 use futures_util::TryStreamExt;
 use hyper::{body::to_bytes, Body, Method, Request, Response};
@@ -456,6 +456,18 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
         }
     };
 
+    // the backend does not constrain the output to a JSON schema yet
+    if let Some(response_format) = &chat_request.response_format {
+        if response_format.ty == "json_schema" {
+            let err_msg = "The `json_schema` response format is not supported yet. Use the `json_object` response format or a `grammar` instead.";
+
+            // log
+            error!(target: "stdout", "{}", err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    }
+
     // check if the user id is provided
     if chat_request.user.is_none() {
         chat_request.user = Some(gen_chat_id())
@@ -1055,6 +1067,16 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
         }
     };
 
+    // check if the charset is recognized
+    if let Err(e) = chunks_request.validate() {
+        let err_msg = e.to_string();
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return error::bad_request(err_msg);
+    }
+
     // check if the archives directory exists
     let path = Path::new("archives");
     if !path.exists() {
@@ -1124,8 +1146,8 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
     };
 
     // read the file
-    let mut contents = String::new();
-    if let Err(e) = file.read_to_string(&mut contents) {
+    let mut bytes = Vec::new();
+    if let Err(e) = file.read_to_end(&mut bytes) {
         let err_msg = format!("Failed to read `{}`. {}", &chunks_request.filename, e);
 
         // log
@@ -1134,13 +1156,49 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
         return error::internal_server_error(err_msg);
     }
 
-    let res = match llama_core::rag::chunk_text(&contents, extension, chunks_request.chunk_capacity)
-    {
+    // transcode the contents to UTF-8
+    let contents = match chunks_request.decode(&bytes) {
+        Ok(contents) => contents,
+        Err(e) => {
+            let err_msg = format!("Failed to decode `{}`. {}", &chunks_request.filename, e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    // only fixed-size chunking is supported by the chunker
+    let chunk_capacity = match chunks_request.chunking_strategy() {
+        ChunkingStrategy::FixedCapacity { capacity } => capacity,
+        strategy => {
+            let err_msg = format!("The chunking strategy is not supported yet: {:?}", strategy);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    // the chunker does not overlap the chunks
+    if let Some(overlap) = chunks_request.chunk_overlap.filter(|overlap| *overlap > 0) {
+        let err_msg = format!("The chunk overlap is not supported yet: {}", overlap);
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return error::bad_request(err_msg);
+    }
+
+    let res = match llama_core::rag::chunk_text(&contents, extension, chunk_capacity) {
         Ok(chunks) => {
             let chunks_response = ChunksResponse {
                 id: chunks_request.id,
                 filename: chunks_request.filename,
                 chunks,
+                chunk_token_counts: None,
             };
 
             // serialize embedding object